@@ -0,0 +1,249 @@
+use glob::{MatchOptions, Pattern};
+use std::fmt;
+
+// same as git wildcard pathspecs: `*` matches `/` too, so `*.png` excludes
+// png files on any level
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// Subset of a commit tree to archive
+///
+/// Empty filter means the whole tree
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveFilter {
+    /// path prefixes (directories or files) relative to the repo root
+    paths: Vec<String>,
+    /// glob patterns, matched against the whole path
+    excludes: Vec<Pattern>,
+}
+
+impl ArchiveFilter {
+    pub fn new(
+        paths: impl IntoIterator<Item = impl AsRef<str>>,
+        excludes: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> anyhow::Result<Self> {
+        let mut clean_paths = Vec::new();
+        for path in paths {
+            let path = path.as_ref();
+            let clean = path.trim_start_matches("./").trim_end_matches('/');
+            if clean.is_empty() || clean == "." {
+                // the whole tree
+                continue;
+            }
+            if clean.starts_with('/') {
+                anyhow::bail!("path `{}` must be relative to the git root", path);
+            }
+            if clean
+                .split('/')
+                .any(|part| part == ".." || part == "." || part.is_empty())
+            {
+                anyhow::bail!("path `{}` must be normalized", path);
+            }
+            clean_paths.push(clean.to_owned());
+        }
+        clean_paths.sort();
+        clean_paths.dedup();
+
+        let mut clean_excludes = Vec::new();
+        for exclude in excludes {
+            let exclude = exclude.as_ref();
+            let pattern = Pattern::new(exclude.trim_start_matches("./").trim_end_matches('/'))
+                .map_err(|err| anyhow::anyhow!("wrong exclude pattern `{}`: {}", exclude, err))?;
+            clean_excludes.push(pattern);
+        }
+        clean_excludes.sort();
+        clean_excludes.dedup();
+
+        Ok(Self {
+            paths: clean_paths,
+            excludes: clean_excludes,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.excludes.is_empty()
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    pub fn excludes(&self) -> impl Iterator<Item = &str> {
+        self.excludes.iter().map(Pattern::as_str)
+    }
+
+    /// `path` (file or directory) should be in the archive
+    pub fn includes(&self, path: &str) -> bool {
+        !self.is_excluded(path)
+            && (self.paths.is_empty() || self.paths.iter().any(|prefix| is_under(path, prefix)))
+    }
+
+    /// directory `dir` has to be walked to find included paths
+    pub fn may_contain(&self, dir: &str) -> bool {
+        !self.is_excluded(dir)
+            && (self.paths.is_empty()
+                || self
+                    .paths
+                    .iter()
+                    .any(|prefix| is_under(dir, prefix) || is_under(prefix, dir)))
+    }
+
+    pub fn is_excluded(&self, path: &str) -> bool {
+        self.excludes
+            .iter()
+            .any(|pattern| pattern.matches_with(path, MATCH_OPTIONS))
+    }
+
+    /// `path` or one of its parent directories is excluded
+    pub fn is_pruned(&self, path: &str) -> bool {
+        self.is_excluded(path)
+            || path
+                .match_indices('/')
+                .any(|(index, _)| self.is_excluded(&path[..index]))
+    }
+
+    /// filter for the tree mounted at `root` (e.g. a submodule), paths become
+    /// relative to `root`, `None` if nothing under `root` is included
+    ///
+    /// excludes are not carried over: they match full paths, so the outer filter
+    /// has to be applied to the mounted entries
+    pub fn under(&self, root: &str) -> Option<ArchiveFilter> {
+        if self.is_pruned(root) {
+            return None;
+        }
+        if self.paths.is_empty() || self.paths.iter().any(|path| is_under(root, path)) {
+            return Some(Self::default());
+        }
+        let paths = self
+            .paths
+            .iter()
+            .filter_map(|path| path.strip_prefix(root)?.strip_prefix('/'))
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        if paths.is_empty() {
+            None
+        } else {
+            Some(Self {
+                paths,
+                excludes: Vec::new(),
+            })
+        }
+    }
+
+    /// same filter where paths inside of `roots` are replaced by the root itself,
+    /// so the outer tree keeps the mount points
+    pub fn outside(&self, roots: &[&str]) -> ArchiveFilter {
+        let mut paths = self
+            .paths
+            .iter()
+            .map(|path| {
+                roots
+                    .iter()
+                    .find(|root| is_under(path, root))
+                    .map_or_else(|| path.clone(), |root| root.to_string())
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+        Self {
+            paths,
+            excludes: self.excludes.clone(),
+        }
+    }
+
+    /// git pathspecs with the same meaning, e.g. for `git archive`
+    pub fn pathspecs(&self) -> Vec<String> {
+        self.paths
+            .iter()
+            .map(|path| format!(":(literal){}", path))
+            .chain(
+                self.excludes()
+                    .map(|exclude| format!(":(exclude){}", exclude)),
+            )
+            .collect()
+    }
+}
+
+/// canonical form (sorted, `!` for excludes) which is used in SBOM
+impl fmt::Display for ArchiveFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = self
+            .paths
+            .iter()
+            .cloned()
+            .chain(self.excludes().map(|exclude| format!("!{}", exclude)))
+            .collect::<Vec<_>>();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+fn is_under(path: &str, prefix: &str) -> bool {
+    path == prefix || (path.starts_with(prefix) && path.as_bytes().get(prefix.len()) == Some(&b'/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_matching_test() {
+        let filter = ArchiveFilter::new(["./src/tool/"], ["*.png"]).unwrap();
+
+        assert!(filter.includes("src/tool"));
+        assert!(filter.includes("src/tool/a.rs"));
+        assert!(filter.includes("src/tool/img"));
+        assert!(!filter.includes("src/tool/img/x.png"));
+        assert!(!filter.includes("src/toolbox/a.rs"));
+        assert!(!filter.includes("src"));
+
+        assert!(filter.may_contain("src"));
+        assert!(filter.may_contain("src/tool/img"));
+        assert!(!filter.may_contain("docs"));
+
+        assert_eq!(filter.to_string(), "src/tool,!*.png");
+        assert_eq!(
+            filter.pathspecs(),
+            vec![":(literal)src/tool", ":(exclude)*.png"]
+        );
+    }
+
+    #[test]
+    fn filter_mount_test() {
+        let filter = ArchiveFilter::new(["vendor/lib/src", "README.md"], ["docs"]).unwrap();
+
+        assert_eq!(
+            filter.under("vendor/lib").unwrap(),
+            ArchiveFilter::new(["src"], [""; 0]).unwrap()
+        );
+        assert_eq!(filter.under("vendor/other"), None);
+        assert_eq!(filter.under("docs/theme"), None);
+        assert!(filter.is_pruned("docs/theme/x.css"));
+        assert!(ArchiveFilter::new(["vendor"], [""; 0])
+            .unwrap()
+            .under("vendor/lib")
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            filter.outside(&["vendor/lib"]),
+            ArchiveFilter::new(["vendor/lib", "README.md"], ["docs"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn filter_validation_test() {
+        assert!(ArchiveFilter::new(["/etc"], [""; 0]).is_err());
+        assert!(ArchiveFilter::new(["src/../.."], [""; 0]).is_err());
+        assert!(ArchiveFilter::new([""; 0], ["[*"]).is_err());
+        assert!(ArchiveFilter::new(["."], [""; 0]).unwrap().is_empty());
+        assert_eq!(
+            ArchiveFilter::new(["b", "a", "b/"], ["*.md", "*.md"])
+                .unwrap()
+                .to_string(),
+            "a,b,!*.md"
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use super::GitBackend;
+use crate::{
+    archive_filter::ArchiveFilter,
+    error::GitRegistryError,
+    refs::{self, GitRef},
+    sandbox,
+    submodule::Gitlink,
+};
+use bytes::Bytes;
+use gosh_utils::{
+    stream::{ByteStream, CHUNK_SIZE},
+    tracing_pipe::MapPerLine,
+};
+use std::{path::Path, process::Stdio};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::Command,
+    sync::mpsc,
+};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tokio_util::io::ReaderStream;
+
+/// Spawns `git` for every operation
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CliGitBackend;
+
+impl CliGitBackend {
+    async fn stdout(mut command: Command) -> anyhow::Result<(bool, Vec<u8>)> {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        tracing::trace!("{:?}", command);
+        let mut process = command.spawn()?;
+
+        if let Some(io) = process.stderr.take() {
+            io.map_per_line(|line| tracing::debug!("{}", line))
+        }
+
+        let Some(mut stdout) = process.stdout.take() else {
+            anyhow::bail!("unable to take STDOUT: {:?}", command);
+        };
+
+        let mut body = Vec::new();
+        stdout.read_to_end(&mut body).await?;
+        tracing::trace!("body: {}", body.len());
+
+        Ok((process.wait().await?.success(), body))
+    }
+
+    async fn object_exists(git_dir: &Path, object: String) -> anyhow::Result<bool> {
+        let status = sandbox::git()
+            .arg("cat-file")
+            .arg("-e")
+            .arg(object)
+            .current_dir(git_dir)
+            .stderr(Stdio::null())
+            .status()
+            .await?;
+        Ok(status.success())
+    }
+
+    /// `path` is in the tree at `commit`, gitlinks too: their commits aren't
+    /// in this repo, so the entry is checked and not the object
+    async fn entry_exists(git_dir: &Path, commit: &str, path: &str) -> anyhow::Result<bool> {
+        let status = sandbox::git()
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("--quiet")
+            .arg(format!("{}:{}", commit, path))
+            .current_dir(git_dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await?;
+        Ok(status.success())
+    }
+
+    /// typed errors for wrong `commit` or `paths`, because streaming commands
+    /// only fail at the end of the stream
+    async fn check_exists(
+        git_dir: &Path,
+        commit: &str,
+        paths: impl IntoIterator<Item = &str>,
+    ) -> anyhow::Result<()> {
+        if !Self::object_exists(git_dir, format!("{}^{{commit}}", commit)).await? {
+            return Err(GitRegistryError::RefNotFound {
+                rev: commit.to_owned(),
+            }
+            .into());
+        }
+        for path in paths {
+            if !Self::entry_exists(git_dir, commit, path).await? {
+                return Err(GitRegistryError::PathNotFound {
+                    commit: commit.to_owned(),
+                    path: path.to_owned(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// stream STDOUT, non zero exit code turns into `error` at the end of the stream
+    ///
+    /// `input` is written to STDIN
+    pub(crate) fn stream_stdout(
+        mut command: Command,
+        input: Option<Bytes>,
+        error: String,
+    ) -> anyhow::Result<ByteStream> {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        if input.is_some() {
+            command.stdin(Stdio::piped());
+        }
+
+        tracing::trace!("{:?}", command);
+        let mut process = command.spawn()?;
+
+        if let (Some(mut stdin), Some(input)) = (process.stdin.take(), input) {
+            tokio::spawn(async move {
+                // the process may not read everything, e.g. if it fails early
+                if let Err(error) = stdin.write_all(&input).await {
+                    tracing::debug!("unable to write STDIN: {}", error);
+                }
+            });
+        }
+
+        if let Some(io) = process.stderr.take() {
+            io.map_per_line(|line| tracing::debug!("{}", line))
+        }
+
+        let Some(stdout) = process.stdout.take() else {
+            anyhow::bail!("unable to take STDOUT: {:?}", command);
+        };
+
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut chunks = ReaderStream::with_capacity(stdout, CHUNK_SIZE);
+            while let Some(chunk) = chunks.next().await {
+                if tx.send(chunk).await.is_err() {
+                    tracing::debug!("stream consumer is gone, kill {:?}", command);
+                    process.kill().await.ok();
+                    return;
+                }
+            }
+            match process.wait().await {
+                Ok(status) if status.success() => {}
+                Ok(_) => {
+                    tracing::error!("{}", error);
+                    tx.send(Err(std::io::Error::new(std::io::ErrorKind::Other, error)))
+                        .await
+                        .ok();
+                }
+                Err(err) => {
+                    tx.send(Err(err)).await.ok();
+                }
+            }
+        });
+        Ok(Box::pin(ReceiverStream::new(rx)))
+    }
+}
+
+#[async_trait::async_trait]
+impl GitBackend for CliGitBackend {
+    async fn show(
+        &self,
+        git_dir: &Path,
+        commit: &str,
+        file_path: &str,
+    ) -> anyhow::Result<ByteStream> {
+        Self::check_exists(git_dir, commit, [file_path]).await?;
+
+        let mut command = sandbox::git();
+        command
+            .arg("show")
+            .arg(format!("{}:{}", commit, file_path))
+            .current_dir(git_dir);
+
+        Self::stream_stdout(command, None, "git-show process failed".to_owned())
+    }
+
+    async fn archive(
+        &self,
+        git_dir: &Path,
+        commit: &str,
+        filter: &ArchiveFilter,
+    ) -> anyhow::Result<ByteStream> {
+        Self::check_exists(git_dir, commit, filter.paths().iter().map(String::as_str)).await?;
+
+        let mut command = sandbox::git();
+        command
+            .arg("archive")
+            .arg("--format=tar")
+            .arg(commit)
+            .current_dir(git_dir);
+        if !filter.is_empty() {
+            command.arg("--").args(filter.pathspecs());
+        }
+
+        Self::stream_stdout(command, None, "git-archive process failed".to_owned())
+    }
+
+    async fn gitlinks(&self, git_dir: &Path, commit: &str) -> anyhow::Result<Vec<Gitlink>> {
+        let mut command = sandbox::git();
+        command
+            .arg("ls-tree")
+            .arg("-r")
+            .arg("-z")
+            .arg(commit)
+            .current_dir(git_dir);
+
+        let (true, body) = Self::stdout(command).await? else {
+            return Err(GitRegistryError::RefNotFound {
+                rev: commit.to_owned(),
+            }
+            .into());
+        };
+        let mut gitlinks = Vec::new();
+        // `<mode> <type> <object>\t<path>\0`
+        for record in String::from_utf8(body)?.split_terminator('\0') {
+            let Some((info, path)) = record.split_once('\t') else {
+                anyhow::bail!("unexpected ls-tree output: {:?}", record);
+            };
+            if let ["160000", "commit", object] = info.split(' ').collect::<Vec<_>>()[..] {
+                gitlinks.push(Gitlink {
+                    path: path.to_owned(),
+                    commit: object.to_owned(),
+                });
+            }
+        }
+        Ok(gitlinks)
+    }
+
+    async fn list_refs(&self, git_dir: &Path) -> anyhow::Result<Vec<GitRef>> {
+        let mut command = sandbox::git();
+        command
+            .arg("for-each-ref")
+            .arg("--format=%(objecttype) %(objectname) %(*objecttype) %(*objectname) %(refname)")
+            .current_dir(git_dir);
+
+        let (true, body) = Self::stdout(command).await? else {
+            anyhow::bail!("git-for-each-ref process failed: {:?}", git_dir);
+        };
+        let mut raw = Vec::new();
+        // `<type> <object> <peeled type> <peeled object> <ref>`, peeled ones are
+        // empty unless the ref is an annotated tag
+        for line in String::from_utf8(body)?.lines() {
+            let (commit, name) = match line.splitn(5, ' ').collect::<Vec<_>>()[..] {
+                ["commit", object, "", "", name] => (object, name),
+                [_, _, "commit", object, name] => (object, name),
+                [_, _, _, _, _] => continue,
+                _ => anyhow::bail!("unexpected for-each-ref output: {:?}", line),
+            };
+            raw.push((name.to_owned(), commit.to_owned()));
+        }
+        Ok(refs::collect_refs(raw))
+    }
+
+    async fn rev_parse_commit(&self, git_dir: &Path, rev: &str) -> anyhow::Result<String> {
+        let mut command = sandbox::git();
+        command
+            .arg("rev-list")
+            .arg("--no-walk")
+            .arg(rev)
+            .current_dir(git_dir);
+
+        match Self::stdout(command).await? {
+            (true, body) => Ok(String::from_utf8(body)?.trim().to_string()),
+            (false, _) => Err(GitRegistryError::RefNotFound {
+                rev: rev.to_owned(),
+            }
+            .into()),
+        }
+    }
+
+    async fn update_server_info(&self, git_dir: &Path) -> anyhow::Result<()> {
+        sandbox::git()
+            .arg("update-server-info")
+            .current_dir(git_dir)
+            .output()
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,361 @@
+use super::GitBackend;
+use crate::{
+    archive_filter::ArchiveFilter,
+    error::GitRegistryError,
+    refs::{self, GitRef},
+    sandbox,
+    submodule::Gitlink,
+};
+use bytes::Bytes;
+use git2::{
+    Object, ObjectType, Oid, ReferenceType, Repository, Tree, TreeWalkMode, TreeWalkResult,
+};
+use gosh_utils::stream::{from_blocking_writer, ByteStream, CHUNK_SIZE};
+use std::{
+    fmt::Write as _,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+// same modes `git archive` produces with the default umask (002)
+const DIR_MODE: u32 = 0o775;
+const FILE_MODE: u32 = 0o664;
+const EXECUTABLE_MODE: u32 = 0o775;
+const SYMLINK_MODE: u32 = 0o777;
+
+const GIT_FILEMODE_EXECUTABLE: i32 = 0o100755;
+const GIT_FILEMODE_LINK: i32 = 0o120000;
+
+/// Reads objects and refs in-process with libgit2
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Libgit2Backend;
+
+/// git2 is blocking, so every call is moved to the blocking thread pool
+async fn blocking<T, F>(git_dir: &Path, f: F) -> anyhow::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(Repository) -> anyhow::Result<T> + Send + 'static,
+{
+    let git_dir = git_dir.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let Ok(repo) = sandbox::open_repository(&git_dir) else {
+            return Err(GitRegistryError::CacheCorrupt { git_dir }.into());
+        };
+        f(repo)
+    })
+    .await?
+}
+
+fn revparse<'r>(repo: &'r Repository, rev: &str) -> anyhow::Result<Object<'r>> {
+    repo.revparse_single(rev).map_err(|_| {
+        GitRegistryError::RefNotFound {
+            rev: rev.to_owned(),
+        }
+        .into()
+    })
+}
+
+#[async_trait::async_trait]
+impl GitBackend for Libgit2Backend {
+    async fn show(
+        &self,
+        git_dir: &Path,
+        commit: &str,
+        file_path: &str,
+    ) -> anyhow::Result<ByteStream> {
+        let commit = commit.to_owned();
+        let file_path = file_path.to_owned();
+        // libgit2 can't stream packed objects, so the blob itself is loaded into
+        // memory, but it's the only copy of it
+        let body = blocking(git_dir, move |repo| {
+            let tree = revparse(&repo, &commit)?.peel_to_tree()?;
+            let Ok(entry) = tree.get_path(Path::new(&file_path)) else {
+                return Err(GitRegistryError::PathNotFound {
+                    commit,
+                    path: file_path,
+                }
+                .into());
+            };
+            let blob = entry.to_object(&repo)?.peel_to_blob()?;
+            Ok(Bytes::copy_from_slice(blob.content()))
+        })
+        .await?;
+
+        let chunks = (0..body.len())
+            .step_by(CHUNK_SIZE)
+            .map(move |start| Ok(body.slice(start..body.len().min(start + CHUNK_SIZE))))
+            .collect::<Vec<_>>();
+        Ok(Box::pin(tokio_stream::iter(chunks)))
+    }
+
+    async fn archive(
+        &self,
+        git_dir: &Path,
+        commit: &str,
+        filter: &ArchiveFilter,
+    ) -> anyhow::Result<ByteStream> {
+        // collect entries before streaming to fail early on wrong commits and paths
+        let commit = commit.to_owned();
+        let filter = filter.clone();
+        let (entries, mtime) = blocking(git_dir, move |repo| {
+            let commit = revparse(&repo, &commit)?.peel_to_commit()?;
+            let mtime = u64::try_from(commit.time().seconds()).unwrap_or_default();
+
+            let mut entries = Vec::new();
+            collect_tree(&repo, &commit.tree()?, "", &filter, &mut entries)?;
+            for path in filter.paths() {
+                if !entries.iter().any(|entry| entry.path == *path) {
+                    return Err(GitRegistryError::PathNotFound {
+                        commit: commit.id().to_string(),
+                        path: path.to_owned(),
+                    }
+                    .into());
+                }
+            }
+            Ok((entries, mtime))
+        })
+        .await?;
+
+        let git_dir = git_dir.to_owned();
+        Ok(from_blocking_writer(move |writer| {
+            let repo = sandbox::open_repository(&git_dir)?;
+            let mut builder = tar::Builder::new(writer);
+            for entry in entries {
+                append_entry(&repo, &mut builder, entry, mtime)?;
+            }
+            builder.finish()?;
+            Ok(())
+        }))
+    }
+
+    async fn gitlinks(&self, git_dir: &Path, commit: &str) -> anyhow::Result<Vec<Gitlink>> {
+        let commit = commit.to_owned();
+        blocking(git_dir, move |repo| {
+            let tree = revparse(&repo, &commit)?.peel_to_tree()?;
+            let mut gitlinks = Vec::new();
+            tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+                if entry.kind() == Some(ObjectType::Commit) {
+                    gitlinks.push(Gitlink {
+                        path: format!("{}{}", root, String::from_utf8_lossy(entry.name_bytes())),
+                        commit: entry.id().to_string(),
+                    });
+                }
+                TreeWalkResult::Ok
+            })?;
+            Ok(gitlinks)
+        })
+        .await
+    }
+
+    async fn list_refs(&self, git_dir: &Path) -> anyhow::Result<Vec<GitRef>> {
+        blocking(git_dir, |repo| {
+            let mut raw = Vec::new();
+            for reference in repo.references()? {
+                let reference = reference?;
+                if reference.kind() != Some(ReferenceType::Direct) {
+                    continue;
+                }
+                let (Some(name), Ok(commit)) = (reference.name(), reference.peel_to_commit())
+                else {
+                    // e.g. a tag of a tree
+                    continue;
+                };
+                raw.push((name.to_owned(), commit.id().to_string()));
+            }
+            Ok(refs::collect_refs(raw))
+        })
+        .await
+    }
+
+    async fn rev_parse_commit(&self, git_dir: &Path, rev: &str) -> anyhow::Result<String> {
+        let rev = rev.to_owned();
+        blocking(git_dir, move |repo| {
+            Ok(revparse(&repo, &rev)?.peel_to_commit()?.id().to_string())
+        })
+        .await
+    }
+
+    async fn update_server_info(&self, git_dir: &Path) -> anyhow::Result<()> {
+        blocking(git_dir, |repo| {
+            write_atomic(&repo.path().join("info").join("refs"), &info_refs(&repo)?)?;
+            write_atomic(
+                &repo.path().join("objects").join("info").join("packs"),
+                &info_packs(&repo)?,
+            )
+        })
+        .await
+    }
+}
+
+#[derive(Debug)]
+struct ArchiveEntry {
+    path: String,
+    kind: ArchiveEntryKind,
+}
+
+#[derive(Debug)]
+enum ArchiveEntryKind {
+    Directory,
+    Blob { oid: Oid, filemode: i32 },
+    // `git archive` leaves an empty directory in place of a submodule
+    Submodule,
+}
+
+fn collect_tree(
+    repo: &Repository,
+    tree: &Tree,
+    prefix: &str,
+    filter: &ArchiveFilter,
+    entries: &mut Vec<ArchiveEntry>,
+) -> anyhow::Result<()> {
+    for entry in tree.iter() {
+        let Some(name) = entry.name() else {
+            anyhow::bail!("non utf-8 path in {:?}: {:?}", prefix, entry.name_bytes());
+        };
+        let path = if prefix.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+
+        let kind = match entry.kind() {
+            Some(ObjectType::Tree) => {
+                if !filter.may_contain(&path) {
+                    continue;
+                }
+                let dir_index = entries.len();
+                entries.push(ArchiveEntry {
+                    path: path.clone(),
+                    kind: ArchiveEntryKind::Directory,
+                });
+                collect_tree(repo, &repo.find_tree(entry.id())?, &path, filter, entries)?;
+                if entries.len() == dir_index + 1 && !filter.is_empty() {
+                    // nothing matched inside
+                    entries.pop();
+                }
+                continue;
+            }
+            Some(ObjectType::Blob) => ArchiveEntryKind::Blob {
+                oid: entry.id(),
+                filemode: entry.filemode(),
+            },
+            Some(ObjectType::Commit) => ArchiveEntryKind::Submodule,
+            _ => {
+                tracing::warn!("unexpected tree entry: {:?}", path);
+                continue;
+            }
+        };
+
+        if filter.includes(&path) {
+            entries.push(ArchiveEntry { path, kind });
+        }
+    }
+    Ok(())
+}
+
+fn append_entry(
+    repo: &Repository,
+    builder: &mut tar::Builder<impl Write>,
+    entry: ArchiveEntry,
+    mtime: u64,
+) -> anyhow::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_mtime(mtime);
+    header.set_size(0);
+
+    match entry.kind {
+        ArchiveEntryKind::Directory | ArchiveEntryKind::Submodule => {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(DIR_MODE);
+            builder.append_data(&mut header, &entry.path, std::io::empty())?;
+        }
+        ArchiveEntryKind::Blob {
+            oid,
+            filemode: GIT_FILEMODE_LINK,
+        } => {
+            let blob = repo.find_blob(oid)?;
+            let target = PathBuf::from(std::str::from_utf8(blob.content())?);
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_mode(SYMLINK_MODE);
+            builder.append_link(&mut header, &entry.path, target)?;
+        }
+        ArchiveEntryKind::Blob { oid, filemode } => {
+            let blob = repo.find_blob(oid)?;
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(if filemode == GIT_FILEMODE_EXECUTABLE {
+                EXECUTABLE_MODE
+            } else {
+                FILE_MODE
+            });
+            header.set_size(blob.content().len() as u64);
+            builder.append_data(&mut header, &entry.path, blob.content())?;
+        }
+    }
+    Ok(())
+}
+
+/// `info/refs` in the format of `git update-server-info`
+fn info_refs(repo: &Repository) -> anyhow::Result<String> {
+    let mut refs = Vec::new();
+    for reference in repo.references()? {
+        let reference = reference?;
+        let Some(name) = reference.name().map(str::to_owned) else {
+            continue;
+        };
+        // symbolic refs (e.g. `refs/remotes/origin/HEAD`) are advertised with their target
+        let Some(oid) = reference.resolve()?.target() else {
+            continue;
+        };
+        refs.push((name, oid));
+    }
+    refs.sort();
+
+    let mut out = String::new();
+    for (name, oid) in refs {
+        writeln!(out, "{}\t{}", oid, name)?;
+        let peeled = peel_tags(repo, oid)?;
+        if peeled != oid {
+            writeln!(out, "{}\t{}^{{}}", peeled, name)?;
+        }
+    }
+    Ok(out)
+}
+
+fn peel_tags(repo: &Repository, mut oid: Oid) -> anyhow::Result<Oid> {
+    while let Ok(tag) = repo.find_tag(oid) {
+        oid = tag.target_id();
+    }
+    Ok(oid)
+}
+
+/// `objects/info/packs` in the format of `git update-server-info`
+fn info_packs(repo: &Repository) -> anyhow::Result<String> {
+    let pack_dir = repo.path().join("objects").join("pack");
+    let mut packs = Vec::new();
+    if pack_dir.exists() {
+        for entry in std::fs::read_dir(pack_dir)? {
+            let file_name = entry?.file_name().to_string_lossy().into_owned();
+            if file_name.ends_with(".pack") {
+                packs.push(file_name);
+            }
+        }
+    }
+    packs.sort();
+
+    let mut out = String::new();
+    for pack in packs {
+        writeln!(out, "P {}", pack)?;
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+fn write_atomic(path: &Path, content: &str) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, content)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+mod cli;
+#[cfg(feature = "libgit2")]
+mod libgit2;
+
+#[cfg(test)]
+mod tests;
+
+pub use cli::CliGitBackend;
+#[cfg(feature = "libgit2")]
+pub use libgit2::Libgit2Backend;
+
+use crate::{archive_filter::ArchiveFilter, refs::GitRef, submodule::Gitlink};
+use gosh_utils::stream::ByteStream;
+use std::{fmt::Debug, path::Path, sync::Arc};
+
+/// env var to force a specific backend: `cli` or `libgit2`
+pub const GOSH_GIT_BACKEND: &str = "GOSH_GIT_BACKEND";
+
+/// Read access to an already cloned repository
+///
+/// `git_dir` is the working tree of the cached clone (the one with `.git` inside)
+///
+/// Bodies are streamed, so failures which happen in the middle (e.g. `git` exited
+/// with an error) arrive as the last item of the stream
+#[async_trait::async_trait]
+pub trait GitBackend: Debug + Send + Sync {
+    /// raw content of `file_path` at `commit`
+    async fn show(
+        &self,
+        git_dir: &Path,
+        commit: &str,
+        file_path: &str,
+    ) -> anyhow::Result<ByteStream>;
+
+    /// uncompressed tar of the tree at `commit`, only paths matching `filter`
+    ///
+    /// same layout as `git archive <commit> -- <pathspecs>`: paths stay relative to
+    /// the repo root and directories without matching files are omitted
+    async fn archive(
+        &self,
+        git_dir: &Path,
+        commit: &str,
+        filter: &ArchiveFilter,
+    ) -> anyhow::Result<ByteStream>;
+
+    /// every submodule entry of the tree at `commit`, nested trees included
+    async fn gitlinks(&self, git_dir: &Path, commit: &str) -> anyhow::Result<Vec<Gitlink>>;
+
+    /// branches (upstream ones included) and tags, see [`crate::refs::collect_refs`]
+    async fn list_refs(&self, git_dir: &Path) -> anyhow::Result<Vec<GitRef>>;
+
+    /// resolve any revision (branch, tag, short hash) to the full commit hash
+    async fn rev_parse_commit(&self, git_dir: &Path, rev: &str) -> anyhow::Result<String>;
+
+    /// regenerate `info/refs` and `objects/info/packs` for the dumb http protocol
+    async fn update_server_info(&self, git_dir: &Path) -> anyhow::Result<()>;
+}
+
+/// In-process backend if it's compiled in, `git` subprocesses otherwise
+///
+/// `GOSH_GIT_BACKEND=cli` falls back to `git` subprocesses explicitly
+pub fn default_backend() -> Arc<dyn GitBackend> {
+    match std::env::var(GOSH_GIT_BACKEND).as_deref() {
+        Ok("cli") => Arc::new(CliGitBackend),
+        #[cfg(feature = "libgit2")]
+        Ok("libgit2") | Err(_) => Arc::new(Libgit2Backend),
+        Ok(other) => {
+            tracing::warn!("unknown {}={}, fallback to cli", GOSH_GIT_BACKEND, other);
+            Arc::new(CliGitBackend)
+        }
+        #[cfg(not(feature = "libgit2"))]
+        Err(_) => Arc::new(CliGitBackend),
+    }
+}
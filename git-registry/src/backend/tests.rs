@@ -0,0 +1,337 @@
+use super::*;
+use crate::{
+    archive_filter::ArchiveFilter,
+    error::GitRegistryError,
+    refs::{GitRef, RefKind},
+    submodule::Gitlink,
+    test_util::{git, init},
+};
+use gosh_utils::stream::read_to_end;
+
+/// pinned commit of the fixture submodule, the object itself doesn't exist
+const GITLINK: &str = "1111111111111111111111111111111111111111";
+
+fn fixture_repo() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path();
+    init(path);
+
+    std::fs::create_dir_all(path.join("src/bin")).unwrap();
+    std::fs::write(path.join("README.md"), "# fixture\n").unwrap();
+    std::fs::write(path.join("src/lib.rs"), "pub fn f() {}\n").unwrap();
+    std::fs::write(path.join("src/bin/run.sh"), "#!/bin/sh\n").unwrap();
+    std::fs::set_permissions(
+        path.join("src/bin/run.sh"),
+        std::os::unix::fs::PermissionsExt::from_mode(0o755),
+    )
+    .unwrap();
+    git(path, &["add", "."]);
+    git(path, &["commit", "--quiet", "-m", "first"]);
+    git(path, &["tag", "-a", "v1", "-m", "v1"]);
+
+    std::os::unix::fs::symlink("src/lib.rs", path.join("lib.rs")).unwrap();
+    std::fs::write(
+        path.join(".gitmodules"),
+        "[submodule \"lib\"]\n\tpath = vendor/lib\n\turl = ../lib\n",
+    )
+    .unwrap();
+    git(path, &["add", "."]);
+    git(
+        path,
+        &[
+            "update-index",
+            "--add",
+            "--cacheinfo",
+            &format!("160000,{},vendor/lib", GITLINK),
+        ],
+    );
+    git(path, &["commit", "--quiet", "-m", "second"]);
+    git(path, &["gc", "--quiet"]);
+    dir
+}
+
+fn backends() -> Vec<Arc<dyn GitBackend>> {
+    vec![
+        Arc::new(CliGitBackend),
+        #[cfg(feature = "libgit2")]
+        Arc::new(Libgit2Backend),
+    ]
+}
+
+/// (path, mode, content) of every entry except the pax header `git archive` adds
+fn tar_entries(body: &[u8]) -> Vec<(String, u32, Vec<u8>)> {
+    use std::io::Read;
+
+    let mut archive = tar::Archive::new(body);
+    let mut entries = Vec::new();
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        if entry.header().entry_type() == tar::EntryType::XGlobalHeader {
+            continue;
+        }
+        let path = entry
+            .path()
+            .unwrap()
+            .to_string_lossy()
+            .trim_end_matches('/')
+            .to_owned();
+        let mode = entry.header().mode().unwrap();
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content).unwrap();
+        entries.push((path, mode, content));
+    }
+    entries.sort();
+    entries
+}
+
+#[tokio::test]
+async fn backends_resolve_the_same_commits() {
+    let repo = fixture_repo();
+    let mut results = Vec::new();
+    for backend in backends() {
+        let head = backend.rev_parse_commit(repo.path(), "main").await.unwrap();
+        let tag = backend.rev_parse_commit(repo.path(), "v1").await.unwrap();
+        let short = backend
+            .rev_parse_commit(repo.path(), &head[..8])
+            .await
+            .unwrap();
+        assert_eq!(head.len(), 40);
+        assert_ne!(head, tag);
+        assert_eq!(head, short);
+        let error = backend
+            .rev_parse_commit(repo.path(), "nope")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            GitRegistryError::of(&error),
+            Some(GitRegistryError::RefNotFound { .. })
+        ));
+        results.push((head, tag));
+    }
+    results.dedup();
+    assert_eq!(results.len(), 1, "{:?}", results);
+}
+
+#[tokio::test]
+async fn backends_show_the_same_files() {
+    let repo = fixture_repo();
+    for backend in backends() {
+        let body = backend.show(repo.path(), "v1", "src/lib.rs").await.unwrap();
+        assert_eq!(read_to_end(body).await.unwrap(), b"pub fn f() {}\n");
+        for (commit, path) in [("v1", "lib.rs"), ("main", "missing")] {
+            let Err(error) = backend.show(repo.path(), commit, path).await else {
+                panic!("{:?} {}:{}", backend, commit, path);
+            };
+            assert!(
+                matches!(
+                    GitRegistryError::of(&error),
+                    Some(GitRegistryError::PathNotFound { .. })
+                ),
+                "{:?} {:?}",
+                backend,
+                error
+            );
+        }
+        let Err(error) = backend.show(repo.path(), "nope", "README.md").await else {
+            panic!("{:?} nope:README.md", backend);
+        };
+        assert!(GitRegistryError::of(&error).unwrap().is_not_found());
+    }
+}
+
+#[tokio::test]
+async fn backends_list_the_same_gitlinks() {
+    let repo = fixture_repo();
+    for backend in backends() {
+        let gitlinks = backend.gitlinks(repo.path(), "main").await.unwrap();
+        assert_eq!(
+            gitlinks,
+            vec![Gitlink {
+                path: "vendor/lib".to_owned(),
+                commit: GITLINK.to_owned(),
+            }],
+            "{:?}",
+            backend
+        );
+        assert!(backend
+            .gitlinks(repo.path(), "v1")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
+
+#[tokio::test]
+async fn backends_list_the_same_refs() {
+    let repo = fixture_repo();
+    // upstream branches of a cached clone
+    git(
+        repo.path(),
+        &["update-ref", "refs/remotes/origin/main", "v1"],
+    );
+    git(
+        repo.path(),
+        &["update-ref", "refs/remotes/origin/dev", "v1"],
+    );
+    git(
+        repo.path(),
+        &[
+            "symbolic-ref",
+            "refs/remotes/origin/HEAD",
+            "refs/remotes/origin/main",
+        ],
+    );
+    git(repo.path(), &["tag", "light", "main"]);
+
+    let mut results = Vec::new();
+    for backend in backends() {
+        let head = backend.rev_parse_commit(repo.path(), "main").await.unwrap();
+        let v1 = backend.rev_parse_commit(repo.path(), "v1").await.unwrap();
+        let git_ref = |kind, name: &str, commit: &str| GitRef {
+            kind,
+            name: name.to_owned(),
+            commit: commit.to_owned(),
+        };
+        let refs = backend.list_refs(repo.path()).await.unwrap();
+        assert_eq!(
+            refs,
+            vec![
+                git_ref(RefKind::Branch, "dev", &v1),
+                git_ref(RefKind::Branch, "main", &head),
+                git_ref(RefKind::Tag, "light", &head),
+                git_ref(RefKind::Tag, "v1", &v1),
+            ],
+            "{:?}",
+            backend
+        );
+        results.push(refs);
+    }
+    results.dedup();
+    assert_eq!(results.len(), 1);
+}
+
+#[cfg(feature = "libgit2")]
+#[tokio::test]
+async fn libgit2_archive_matches_git_archive() {
+    let repo = fixture_repo();
+    let filter = ArchiveFilter::default();
+    let expected = CliGitBackend
+        .archive(repo.path(), "main", &filter)
+        .await
+        .unwrap();
+    let expected = tar_entries(&read_to_end(expected).await.unwrap());
+    let actual = Libgit2Backend
+        .archive(repo.path(), "main", &filter)
+        .await
+        .unwrap();
+    let actual = tar_entries(&read_to_end(actual).await.unwrap());
+    assert_eq!(actual, expected);
+    assert!(Libgit2Backend
+        .archive(repo.path(), "nope", &filter)
+        .await
+        .is_err());
+    assert!(actual
+        .iter()
+        .any(|(path, mode, _)| path == "src/bin/run.sh" && *mode == 0o775));
+}
+
+#[cfg(feature = "libgit2")]
+#[tokio::test]
+async fn libgit2_filtered_archive_matches_git_archive() {
+    let repo = fixture_repo();
+    let filters = [
+        (vec!["src"], vec![]),
+        (vec!["src/bin/run.sh", "README.md"], vec![]),
+        (vec![], vec!["*.rs"]),
+        (vec!["src"], vec!["*.rs"]),
+        (vec![], vec!["src/bin"]),
+    ];
+    for (paths, excludes) in filters {
+        let filter = ArchiveFilter::new(paths, excludes).unwrap();
+        let expected = CliGitBackend
+            .archive(repo.path(), "main", &filter)
+            .await
+            .unwrap();
+        let expected = tar_entries(&read_to_end(expected).await.unwrap());
+        let actual = Libgit2Backend
+            .archive(repo.path(), "main", &filter)
+            .await
+            .unwrap();
+        let actual = tar_entries(&read_to_end(actual).await.unwrap());
+        assert_eq!(actual, expected, "{}", filter);
+    }
+
+    let filter = ArchiveFilter::new(["nope"], [""; 0]).unwrap();
+    assert!(Libgit2Backend
+        .archive(repo.path(), "main", &filter)
+        .await
+        .is_err());
+}
+
+#[cfg(feature = "libgit2")]
+#[tokio::test]
+async fn libgit2_server_info_matches_git_update_server_info() {
+    let repo = fixture_repo();
+    let info_refs = repo.path().join(".git/info/refs");
+    let info_packs = repo.path().join(".git/objects/info/packs");
+
+    CliGitBackend.update_server_info(repo.path()).await.unwrap();
+    let expected = (
+        std::fs::read_to_string(&info_refs).unwrap(),
+        std::fs::read_to_string(&info_packs).unwrap(),
+    );
+    std::fs::remove_file(&info_refs).unwrap();
+    std::fs::remove_file(&info_packs).unwrap();
+
+    Libgit2Backend
+        .update_server_info(repo.path())
+        .await
+        .unwrap();
+    let actual = (
+        std::fs::read_to_string(&info_refs).unwrap(),
+        std::fs::read_to_string(&info_packs).unwrap(),
+    );
+    assert_eq!(actual, expected);
+    assert!(actual.0.contains("refs/tags/v1^{}"));
+}
+
+#[tokio::test]
+async fn backends_report_missing_archive_paths() {
+    let repo = fixture_repo();
+    let filter = ArchiveFilter::new(["src", "nope"], [""; 0]).unwrap();
+    for backend in backends() {
+        let Err(error) = backend.archive(repo.path(), "main", &filter).await else {
+            panic!("{:?}", backend);
+        };
+        assert!(
+            matches!(
+                GitRegistryError::of(&error),
+                Some(GitRegistryError::PathNotFound { path, .. }) if path == "nope"
+            ),
+            "{:?} {:?}",
+            backend,
+            error
+        );
+    }
+}
+
+#[tokio::test]
+async fn backends_archive_gitlink_paths() {
+    // `ArchiveFilter::outside` maps paths in submodules to their gitlinks
+    let repo = fixture_repo();
+    let filter = ArchiveFilter::new(["vendor/lib/src"], [""; 0])
+        .unwrap()
+        .outside(&["vendor/lib"]);
+    for backend in backends() {
+        let body = backend
+            .archive(repo.path(), "main", &filter)
+            .await
+            .unwrap_or_else(|error| panic!("{:?} {:?}", backend, error));
+        let entries = tar_entries(&read_to_end(body).await.unwrap());
+        let paths = entries
+            .iter()
+            .map(|(path, _, _)| path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["vendor", "vendor/lib"], "{:?}", backend);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{git_url::GitUrl, registry::GitCacheRegistry};
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+/// first entry of the archive
+pub const MANIFEST: &str = "manifest.json";
+const MANIFEST_VERSION: u32 = 1;
+const COMMIT_HEX_LEN: usize = 40;
+
+/// Content of a cache archive: `manifest.json` followed by a git bundle per repo
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub repos: Vec<BundledRepo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BundledRepo {
+    pub url: String,
+    /// empty if the whole repo is bundled
+    pub commits: Vec<String>,
+    /// file name of the bundle in the archive
+    pub bundle: String,
+    /// sha256 of the bundle, lowercase hex
+    pub sha256: String,
+}
+
+/// Repos and commits to export, `None` means every ref of the repo
+pub type Sources = BTreeMap<GitUrl, Option<BTreeSet<String>>>;
+
+/// Git sources referenced by raw SBOM components: `<url>:<commit>[:<suffix>]`
+/// and `<url>` (the whole repo was served), others are skipped
+pub fn sources<'a>(components: impl IntoIterator<Item = &'a str>) -> Sources {
+    let mut sources = Sources::new();
+    for component in components {
+        let Some((url, commit)) = parse_component(component) else {
+            tracing::debug!("not a git component: {}", component);
+            continue;
+        };
+        let commits = sources.entry(url).or_insert_with(|| Some(BTreeSet::new()));
+        match (commits, commit) {
+            (Some(commits), Some(commit)) => {
+                commits.insert(commit);
+            }
+            (commits, None) => *commits = None,
+            (None, Some(_)) => {}
+        }
+    }
+    sources
+}
+
+fn parse_component(component: &str) -> Option<(GitUrl, Option<String>)> {
+    let is_commit = |hex: &str| hex.chars().all(|c| c.is_ascii_hexdigit());
+    for (index, _) in component.match_indices(':') {
+        let rest = &component[index + 1..];
+        let Some(commit) = rest.get(..COMMIT_HEX_LEN) else {
+            break;
+        };
+        let ends = rest.len() == COMMIT_HEX_LEN || rest[COMMIT_HEX_LEN..].starts_with(':');
+        if ends && is_commit(commit) {
+            let url = component[..index].parse().ok()?;
+            return Some((url, Some(commit.to_ascii_lowercase())));
+        }
+    }
+    Some((component.parse().ok()?, None))
+}
+
+/// Write `sources` from `registry` as a cache archive to `out`
+pub async fn export(
+    registry: &GitCacheRegistry,
+    sources: &Sources,
+    out: &Path,
+) -> anyhow::Result<Manifest> {
+    let dir = tempfile::tempdir()?;
+    let mut manifest = Manifest {
+        version: MANIFEST_VERSION,
+        repos: Vec::new(),
+    };
+    for (index, (url, commits)) in sources.iter().enumerate() {
+        let commits = commits
+            .as_ref()
+            .map(|commits| commits.iter().cloned().collect::<Vec<_>>());
+        let bundle = format!("{}.bundle", index);
+        let path = dir.path().join(&bundle);
+        registry
+            .export_bundle(url, commits.as_deref(), &path)
+            .await
+            .with_context(|| format!("export {}", url))?;
+        manifest.repos.push(BundledRepo {
+            url: url.to_string(),
+            commits: commits.unwrap_or_default(),
+            sha256: sha256_of(&path)?,
+            bundle,
+        });
+    }
+
+    let mut builder = tar::Builder::new(File::create(out)?);
+    let content = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(content.len() as u64);
+    builder.append_data(&mut header, MANIFEST, content.as_slice())?;
+    for repo in &manifest.repos {
+        builder.append_path_with_name(dir.path().join(&repo.bundle), &repo.bundle)?;
+    }
+    builder.into_inner()?.sync_all()?;
+    Ok(manifest)
+}
+
+/// Populate `registry` storage from a cache archive made by [`export`]
+pub async fn import(registry: &GitCacheRegistry, archive: &Path) -> anyhow::Result<Manifest> {
+    let dir = tempfile::tempdir()?;
+    let mut manifest = None;
+    let mut entries = tar::Archive::new(BufReader::new(File::open(archive)?));
+    for entry in entries.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if name == MANIFEST {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            manifest = Some(serde_json::from_slice::<Manifest>(&content)?);
+        } else if !entry.unpack_in(dir.path())? {
+            anyhow::bail!("unexpected path in {:?}: {}", archive, name);
+        }
+    }
+    let Some(manifest) = manifest else {
+        anyhow::bail!("{:?} has no {}", archive, MANIFEST);
+    };
+    if manifest.version != MANIFEST_VERSION {
+        anyhow::bail!("unsupported manifest version {}", manifest.version);
+    }
+
+    for repo in &manifest.repos {
+        let url = repo.url.parse::<GitUrl>()?;
+        let path = dir.path().join(&repo.bundle);
+        if path.parent() != Some(dir.path()) || !path.is_file() {
+            anyhow::bail!("bundle of {} is missing: {}", url, repo.bundle);
+        }
+        let sha256 = sha256_of(&path)?;
+        if sha256 != repo.sha256 {
+            anyhow::bail!(
+                "bundle of {} is damaged: sha256 {} instead of {}",
+                url,
+                sha256,
+                repo.sha256
+            );
+        }
+        registry
+            .import_bundle(&url, &path, &repo.commits)
+            .await
+            .with_context(|| format!("import {}", url))?;
+    }
+    Ok(manifest)
+}
+
+fn sha256_of(path: &Path) -> anyhow::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        git_url::{AllowedHosts, FILE_HOST},
+        test_util::{commit_file, init},
+    };
+
+    const ADDRESS: &str = "0:0d5c05d7a63f438b57ede179b7110d3e903f5be3b5f543d3d6743d774698e92c";
+
+    #[test]
+    fn sources_test() {
+        let commit = "a".repeat(40);
+        let other = "b".repeat(40);
+        let gosh = format!("gosh://{}/dao/repo", ADDRESS);
+        let components = [
+            format!("{}:{}", gosh, commit),
+            format!("{}:{}:src/*.rs", gosh, other),
+            format!("https://github.com:443/a/b:{}:README.md", commit),
+            "file:///srv/repo".to_owned(),
+            format!("file:///srv/repo:{}", commit),
+            "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e".to_owned(),
+        ];
+        let sources = sources(components.iter().map(String::as_str));
+
+        let commits = |list: &[&String]| Some(list.iter().map(|c| c.to_string()).collect());
+        let expected = Sources::from([
+            (gosh.parse().unwrap(), commits(&[&commit, &other])),
+            (
+                "https://github.com:443/a/b".parse().unwrap(),
+                commits(&[&commit]),
+            ),
+            ("file:///srv/repo".parse().unwrap(), None),
+        ]);
+        assert_eq!(sources, expected);
+    }
+
+    #[tokio::test]
+    async fn export_import_test() {
+        let upstream = tempfile::tempdir().unwrap();
+        init(upstream.path());
+        let first = commit_file(upstream.path(), "README.md", "first\n", "first");
+        let second = commit_file(upstream.path(), "README.md", "second\n", "second");
+
+        let url: GitUrl = format!("file://{}", upstream.path().display())
+            .parse()
+            .unwrap();
+        let registry = |cache: &tempfile::TempDir| {
+            GitCacheRegistry::default()
+                .with_cache_dir(cache.path())
+                .with_allowed_hosts(AllowedHosts::new([FILE_HOST]))
+        };
+
+        let online_cache = tempfile::tempdir().unwrap();
+        let archive = online_cache.path().join("cache.tar");
+        let sources = Sources::from([(url.clone(), Some(BTreeSet::from([first.clone()])))]);
+        let manifest = export(&registry(&online_cache), &sources, &archive)
+            .await
+            .unwrap();
+        assert_eq!(manifest.repos[0].commits, vec![first.clone()]);
+
+        let offline_cache = tempfile::tempdir().unwrap();
+        let offline = registry(&offline_cache).with_offline(true);
+        assert!(offline.normalized_commit(&url, &first).await.is_err());
+
+        let offline = registry(&offline_cache).with_offline(true);
+        import(&offline, &archive).await.unwrap();
+        assert_eq!(
+            offline.normalized_commit(&url, &first).await.unwrap(),
+            first
+        );
+        let readme = offline
+            .git_show_uncompressed(&url, &first, "README.md")
+            .await
+            .unwrap();
+        assert_eq!(readme, b"first\n");
+        // only history of the exported commits
+        assert!(offline.normalized_commit(&url, &second).await.is_err());
+
+        // damaged bundle: the bundle is there, its sha256 in the manifest is wrong
+        let manifest = serde_json::to_vec(&Manifest {
+            repos: vec![BundledRepo {
+                sha256: "0".repeat(64),
+                ..manifest.repos[0].clone()
+            }],
+            ..manifest
+        })
+        .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(manifest.len() as u64);
+        let damaged = online_cache.path().join("damaged.tar");
+        let mut builder = tar::Builder::new(File::create(&damaged).unwrap());
+        builder
+            .append_data(&mut header, MANIFEST, manifest.as_slice())
+            .unwrap();
+        let mut exported = tar::Archive::new(File::open(&archive).unwrap());
+        for entry in exported.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            if entry.path().unwrap().as_os_str() != MANIFEST {
+                let header = entry.header().clone();
+                builder.append(&header, &mut entry).unwrap();
+            }
+        }
+        builder.finish().unwrap();
+        let error = import(&registry(&offline_cache), &damaged)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("damaged"), "{:?}", error);
+    }
+}
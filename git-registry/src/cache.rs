@@ -1,32 +1,278 @@
-use gosh_utils::{tracing_pipe::MapPerLine, zstd::ZstdReadToEnd};
-use std::{collections::hash_map::DefaultHasher, hash::Hasher, path::PathBuf, process::Stdio};
-use tokio::{io::AsyncReadExt, process::Command};
+use crate::{
+    archive_filter::ArchiveFilter,
+    backend::{CliGitBackend, GitBackend},
+    clone_policy::ClonePolicy,
+    dumb_path,
+    error::GitRegistryError,
+    git_url::GitUrl,
+    refs::GitRef,
+    sandbox,
+    submodule::{self, Submodule},
+    upstream::UpstreamCache,
+    url_rewrite::UrlRewrites,
+};
+use bytes::Bytes;
+use gosh_utils::{
+    stream::{self, ByteStream},
+    tracing_pipe::MapPerLine,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    ffi::OsStr,
+    hash::Hasher,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+};
+use tokio::io::AsyncWriteExt;
+
+/// refs which keep exported commits reachable, `<prefix><commit>`
+const PINNED_REFS: &str = "refs/gosh/pinned/";
+/// pinned commits of upstream caches are fetched along with branches
+const PINNED_REFSPEC: &str = "+refs/gosh/pinned/*:refs/gosh/pinned/*";
+
+/// Refs [`GitCacheRepo::unbundle`] takes from a bundle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BundleRefs {
+    /// branches and tags too if the repo isn't cached yet, for bundles the
+    /// user exported
+    All,
+    /// only `refs/gosh/pinned/*`, for bundles anyone could have made:
+    /// branches and tags come from fetches of the remote
+    Pinned,
+}
+/// refs which keep commits fetched into shallow clones by hash, `<prefix><commit>`
+const FETCHED_REFS: &str = "refs/gosh/fetched/";
+
+/// `~/.cache/gosh`, every repo is cloned into a subdir named by the hash of its url
+pub fn default_cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or(PathBuf::from(".cache"))
+        .join("gosh")
+}
 
 #[derive(Debug)]
 pub(crate) struct GitCacheRepo {
     pub git_dir: PathBuf,
-    pub url: String,
+    pub url: GitUrl,
+    backend: Arc<dyn GitBackend>,
+    rewrites: Arc<UrlRewrites>,
+    upstream: Option<Arc<UpstreamCache>>,
+    clone_policy: ClonePolicy,
 }
 
 impl GitCacheRepo {
-    pub fn from(url: String) -> Self {
-        let repo_url_hash = hex_hash(&url);
-        let git_dir = dirs::cache_dir()
-            .unwrap_or(PathBuf::from(".cache"))
-            .join("gosh")
-            .join(repo_url_hash);
-        Self { git_dir, url }
+    pub fn from(
+        url: GitUrl,
+        cache_dir: &Path,
+        backend: Arc<dyn GitBackend>,
+        rewrites: Arc<UrlRewrites>,
+        upstream: Option<Arc<UpstreamCache>>,
+        clone_policy: ClonePolicy,
+    ) -> Self {
+        Self {
+            git_dir: Self::dir_of(cache_dir, &url),
+            url,
+            backend,
+            rewrites,
+            upstream,
+            clone_policy,
+        }
+    }
+
+    /// where `url` is cloned in `cache_dir`
+    pub fn dir_of(cache_dir: &Path, url: &GitUrl) -> PathBuf {
+        cache_dir.join(hex_hash(&url.to_string()))
     }
 
+    /// clone or pull the repo, the upstream cache and mirrors are tried in order
+    /// before the canonical url
+    ///
+    /// the upstream cache speaks the dumb protocol, which can't make shallow or
+    /// partial clones, so it's only used for full ones
     pub async fn update(&self) -> anyhow::Result<()> {
-        if self.git_dir.exists() {
-            // TODO: test that repo is not hijaked
-            // try git pull
-            tracing::info!("git-cache: repo dir exists, try to pull {}", self.url);
+        if self.is_cloned() {
+            if let Err(error) = self.verify_origin().await {
+                tracing::warn!("git-cache: {:?}, clone {} again", error, self.url);
+                std::fs::remove_dir_all(&self.git_dir)?;
+            }
+        }
+
+        let upstream = self
+            .upstream
+            .as_ref()
+            .filter(|_| self.clone_policy == ClonePolicy::Full)
+            .and_then(|upstream| upstream.url_of(&self.url));
+        let mut last_error = None;
+        for remote in upstream
+            .iter()
+            .cloned()
+            .chain(self.rewrites.candidates(&self.url))
+        {
+            match self.update_from(&remote).await {
+                Ok(()) => {
+                    self.update_server_info().await?;
+                    if upstream.is_some() && Some(&remote) != upstream.as_ref() {
+                        self.share().await;
+                    }
+                    return Ok(());
+                }
+                Err(error) => {
+                    tracing::warn!("git-cache: {} failed: {:?}", remote, error);
+                    last_error = Some(error);
+                }
+            }
+        }
+        let error = last_error.unwrap_or_else(|| anyhow::anyhow!("no remote for {}", self.url));
+        Err(error.context(GitRegistryError::Unreachable {
+            url: self.url.clone(),
+        }))
+    }
+
+    /// check that the cache dir holds a clone of this repo and not of another one
+    /// which got the same dir or was planted there: `origin` has to be one of
+    /// the urls the repo is fetched from
+    pub async fn verify_origin(&self) -> anyhow::Result<()> {
+        let origin = self
+            .git(["config", "--get", "remote.origin.url"])
+            .await
+            .unwrap_or_default();
+        let origin = origin.trim();
+        let is_known = self
+            .upstream
+            .as_ref()
+            .and_then(|upstream| upstream.url_of(&self.url))
+            .into_iter()
+            .chain(self.rewrites.candidates(&self.url))
+            .any(|remote| remote == origin);
+        if !is_known {
+            return Err(anyhow::anyhow!(
+                "cached clone of {} has unexpected origin `{}`",
+                self.url,
+                origin
+            )
+            .context(GitRegistryError::CacheCorrupt {
+                git_dir: self.git_dir.clone(),
+            }));
+        }
+        Ok(())
+    }
+
+    /// upload the repo to the upstream cache if it accepts uploads, failures only
+    /// cost the next team member a fetch from the remote
+    async fn share(&self) {
+        let Some(upstream) = self.upstream.as_ref().filter(|upstream| upstream.uploads()) else {
+            return;
+        };
+        let shared = async {
+            let mut commits = self
+                .list_refs()
+                .await?
+                .into_iter()
+                .map(|git_ref| git_ref.commit)
+                .collect::<Vec<_>>();
+            commits.sort();
+            commits.dedup();
+            if commits.is_empty() {
+                return Ok(());
+            }
+            let dir = tempfile::tempdir()?;
+            let bundle = dir.path().join("upload.bundle");
+            self.bundle(Some(&commits), &bundle).await?;
+            upstream.upload(&self.url, &bundle).await
+        }
+        .await;
+        match shared {
+            Ok(()) => tracing::info!("git-cache: {} is shared with the upstream cache", self.url),
+            Err(error) => tracing::warn!("git-cache: can't share {}: {:?}", self.url, error),
+        }
+    }
+
+    /// there is a clone in the cache dir, maybe outdated
+    pub fn is_cloned(&self) -> bool {
+        self.git_dir.join(".git").exists()
+    }
+
+    /// history is cut, commits behind the cut have to be fetched
+    pub fn is_shallow(&self) -> bool {
+        self.git_dir.join(".git").join("shallow").exists()
+    }
+
+    /// blobs are fetched on demand, `git clone --filter` marks its packs with
+    /// `.promisor` files
+    pub fn is_partial(&self) -> bool {
+        std::fs::read_dir(self.git_dir.join(".git").join("objects").join("pack"))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .any(|entry| entry.path().extension() == Some(OsStr::new("promisor")))
+            })
+            .unwrap_or(false)
+    }
+
+    /// libgit2 can't fetch missing blobs of partial clones, `git` does it on demand
+    fn backend(&self) -> &dyn GitBackend {
+        if self.is_partial() {
+            &CliGitBackend
+        } else {
+            self.backend.as_ref()
+        }
+    }
+
+    async fn update_from(&self, remote: &str) -> anyhow::Result<()> {
+        if self.is_cloned() {
+            // try git pull, `update` has checked the origin
+            tracing::info!("git-cache: repo dir exists, try to pull {}", remote);
             tracing::debug!("{:?}", &self.git_dir);
-            let mut git_pull_process = Command::new("git")
-                .arg("pull")
-                .arg("--all")
+            let status = sandbox::git()
+                .arg("remote")
+                .arg("set-url")
+                .arg("origin")
+                .arg(remote)
+                .current_dir(&self.git_dir)
+                .status()
+                .await?;
+
+            if !status.success() {
+                return Err(GitRegistryError::CacheCorrupt {
+                    git_dir: self.git_dir.clone(),
+                }
+                .into());
+            }
+            // clones made before upstream caches existed don't have it
+            self.git([
+                "config",
+                "--replace-all",
+                "remote.origin.fetch",
+                PINNED_REFSPEC,
+                "^\\+refs/gosh/pinned/",
+            ])
+            .await?;
+
+            // imported caches may have no branch checked out, there's nothing to pull into
+            let has_head = self
+                .git(["rev-parse", "--verify", "--quiet", "HEAD"])
+                .await
+                .is_ok();
+            // shallow tips have no common history with the old ones, so they
+            // can't be merged by `git pull`
+            let is_shallow_policy = self.clone_policy == ClonePolicy::Shallow;
+            let mut args = vec![
+                if has_head && !is_shallow_policy {
+                    "pull"
+                } else {
+                    "fetch"
+                },
+                "--all",
+            ];
+            if is_shallow_policy {
+                args.extend(["--depth", "1"]);
+            } else if self.is_shallow() {
+                // the policy changed since the clone
+                args.push("--unshallow");
+            }
+            let mut git_pull_process = sandbox::git()
+                .args(args)
                 .stdout(Stdio::piped())
                 .stderr(Stdio::piped())
                 .current_dir(&self.git_dir)
@@ -45,18 +291,36 @@ impl GitCacheRepo {
             if !status.success() {
                 anyhow::bail!(
                     "git pull process failed: url={} dir={:?}",
-                    &self.url,
+                    remote,
                     &self.git_dir
                 );
             }
+            if has_head
+                && is_shallow_policy
+                && self
+                    .git(["rev-parse", "--verify", "--quiet", "@{upstream}"])
+                    .await
+                    .is_ok()
+            {
+                // the cache has no local changes, same as a fast-forward
+                self.git(["reset", "--quiet", "--hard", "@{upstream}"])
+                    .await?;
+            }
         } else {
             // git clone
+            if self.git_dir.exists() {
+                // leftovers of the failed clone
+                std::fs::remove_dir_all(&self.git_dir)?;
+            }
             std::fs::create_dir_all(&self.git_dir)?;
 
             tracing::debug!("{:?}", &self.git_dir);
-            let mut git_clone_process = Command::new("git")
+            let mut git_clone_process = sandbox::git()
                 .arg("clone")
-                .arg(&self.url)
+                .arg("--config")
+                .arg(format!("remote.origin.fetch={}", PINNED_REFSPEC))
+                .args(self.clone_policy.clone_args())
+                .arg(remote)
                 .arg(".") // clone into current dir
                 .current_dir(&self.git_dir)
                 .stdout(Stdio::piped())
@@ -76,7 +340,7 @@ impl GitCacheRepo {
             if !status.success() {
                 anyhow::bail!(
                     "git clone process failed: url={} dir={:?}",
-                    &self.url,
+                    remote,
                     &self.git_dir
                 );
             }
@@ -84,149 +348,521 @@ impl GitCacheRepo {
         Ok(())
     }
 
-    pub async fn update_server_info(&self) -> anyhow::Result<()> {
-        Command::new("git")
-            .arg("update-server-info")
-            .current_dir(&self.git_dir)
-            .output()
-            .await?;
-        Ok(())
-    }
-
-    pub async fn dumb(&self, src: impl AsRef<str>) -> anyhow::Result<PathBuf> {
-        Ok(self.git_dir.join(".git").join(src.as_ref()))
+    /// run `git` in the repo dir, STDOUT if it succeeded
+    async fn git<I, S>(&self, args: I) -> anyhow::Result<String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Self::git_in(&self.git_dir, args).await
     }
 
-    pub async fn git_archive(&self, commit: impl AsRef<str>) -> anyhow::Result<Vec<u8>> {
-        let mut git_archive_process = Command::new("git")
-            .arg("archive")
-            .arg("--format=tar")
-            .arg(commit.as_ref())
-            .current_dir(&self.git_dir)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
-
-        if let Some(io) = git_archive_process.stderr.take() {
-            io.map_per_line(|line| tracing::debug!("{}", line))
+    /// run `git` in `dir`, STDOUT if it succeeded
+    async fn git_in<I, S>(dir: &Path, args: I) -> anyhow::Result<String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut command = sandbox::git();
+        command.args(args).current_dir(dir).stdin(Stdio::null());
+        tracing::trace!("{:?}", command);
+        let output = command.output().await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{:?} failed: {}",
+                command,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
         }
+        Ok(String::from_utf8(output.stdout)?)
+    }
 
-        let Some(stdout) = git_archive_process.stdout.take() else {
-            anyhow::bail!("unable to take STDOUT: git_dir={:?}", &self.git_dir);
+    /// write `commits` (every ref if `None`) with their history to the git bundle at `path`
+    ///
+    /// branches and tags pointing exactly at `commits` go along
+    pub async fn bundle(&self, commits: Option<&[String]>, path: &Path) -> anyhow::Result<()> {
+        let Some(commits) = commits else {
+            self.git([
+                OsStr::new("bundle"),
+                OsStr::new("create"),
+                path.as_os_str(),
+                OsStr::new("--all"),
+            ])
+            .await?;
+            return Ok(());
         };
 
-        let zstd_body = stdout.zstd_read_to_end().await?;
+        let mut pinned = Vec::new();
+        let mut refs = Vec::new();
+        let result = async {
+            for commit in commits {
+                let pin = format!("{}{}", PINNED_REFS, commit);
+                if self
+                    .git(["update-ref", &pin, &format!("{}^{{commit}}", commit)])
+                    .await
+                    .is_err()
+                {
+                    return Err(GitRegistryError::RefNotFound {
+                        rev: commit.to_owned(),
+                    }
+                    .into());
+                }
+                pinned.push(pin.clone());
+                refs.push(pin);
+                let names = self
+                    .git([
+                        "for-each-ref",
+                        "--format=%(refname)",
+                        "--points-at",
+                        commit,
+                        "refs/heads",
+                        "refs/remotes/origin",
+                        "refs/tags",
+                    ])
+                    .await?;
+                refs.extend(names.lines().map(str::to_owned));
+            }
+            refs.sort();
+            refs.dedup();
+            let mut args = vec![OsStr::new("bundle"), OsStr::new("create"), path.as_os_str()];
+            args.extend(refs.iter().map(OsStr::new));
+            self.git(args).await
+        }
+        .await;
 
-        if git_archive_process.wait().await?.success() {
-            Ok(zstd_body)
-        } else {
-            tracing::error!("git-archive process failed: zstd_body={}", zstd_body.len());
-            anyhow::bail!("git-archive process failed")
+        // exported commits don't have to stay pinned here
+        for pin in pinned {
+            self.git(["update-ref", "-d", &pin]).await?;
         }
+        result.map(|_| ())
     }
 
-    pub async fn git_show(
+    /// fetch every object of the bundle at `path` made by [`GitCacheRepo::bundle`]
+    /// and check that `commits` are there
+    ///
+    /// objects are checked with `fsck` while they're fetched, branches and tags
+    /// are only taken into a fresh cache and only with [`BundleRefs::All`],
+    /// with [`BundleRefs::Pinned`] see [`GitCacheRepo::import_pins`]
+    pub async fn unbundle(
         &self,
-        commit: impl AsRef<str>,
-        file_path: impl AsRef<str>,
-    ) -> anyhow::Result<Vec<u8>> {
-        let mut command = Command::new("git");
-        command
-            .arg("show")
-            .arg(format!("{}:{}", commit.as_ref(), file_path.as_ref()))
-            .current_dir(&self.git_dir)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+        path: &Path,
+        commits: &[String],
+        refs: BundleRefs,
+    ) -> anyhow::Result<()> {
+        let is_fresh = !self.is_cloned();
+        if is_fresh && refs == BundleRefs::Pinned {
+            anyhow::bail!("{} isn't cached, pinned commits need a clone", self.url);
+        }
+        if !is_fresh {
+            self.verify_origin().await?;
+        } else {
+            if self.git_dir.exists() {
+                // leftovers of the failed clone
+                std::fs::remove_dir_all(&self.git_dir)?;
+            }
+            std::fs::create_dir_all(&self.git_dir)?;
+            self.git(["init", "--quiet"]).await?;
+            self.git(["remote", "add", "origin", &self.url.to_string()])
+                .await?;
+        }
 
-        tracing::trace!("{:?}", command);
-        let mut git_show_process = command.spawn()?;
+        if refs == BundleRefs::Pinned {
+            self.import_pins(path).await?;
+        } else {
+            let mut args = vec![
+                OsStr::new("-c"),
+                OsStr::new("fetch.fsckObjects=true"),
+                OsStr::new("fetch"),
+                OsStr::new("--quiet"),
+                OsStr::new("--update-head-ok"),
+                path.as_os_str(),
+            ];
+            let pinned = format!("+{0}*:{0}*", PINNED_REFS);
+            args.push(OsStr::new(&pinned));
+            if is_fresh {
+                args.push(OsStr::new("+refs/heads/*:refs/heads/*"));
+                args.push(OsStr::new("+refs/tags/*:refs/tags/*"));
+                args.push(OsStr::new("+refs/remotes/origin/*:refs/remotes/origin/*"));
+            }
+            self.git(args).await?;
+        }
 
-        if let Some(io) = git_show_process.stderr.take() {
-            io.map_per_line(|line| tracing::debug!("{}", line))
+        if is_fresh
+            && self
+                .git(["rev-parse", "--verify", "--quiet", "HEAD"])
+                .await
+                .is_ok()
+        {
+            // same state as after `git clone`, so `git pull` works later
+            self.git(["reset", "--quiet", "--hard"]).await?;
         }
 
-        let Some(stdout) = git_show_process.stdout.take() else {
-            tracing::error!("unable to take STDOUT: url={}", &self.url);
-            anyhow::bail!("internal error");
-        };
+        for commit in commits {
+            if self
+                .git(["cat-file", "-e", &format!("{}^{{commit}}", commit)])
+                .await
+                .is_err()
+            {
+                anyhow::bail!("bundle {:?} doesn't contain commit {}", path, commit);
+            }
+        }
+        self.update_server_info().await
+    }
 
-        let zstd_body = stdout.zstd_read_to_end().await?;
-        tracing::trace!("zstd_body: {:?}", &zstd_body);
+    /// take pins of the bundle at `path` which are in the history of what came
+    /// from the origin: its branches, tags and pins
+    ///
+    /// anyone with the upload token could have made the bundle, so it's
+    /// fetched into a scratch repo which borrows the objects of this one and
+    /// only the history of verified pins is fetched from there. Pins are named
+    /// after the commits they keep, others could point anywhere
+    async fn import_pins(&self, path: &Path) -> anyhow::Result<()> {
+        let objects = std::fs::canonicalize(self.git_dir.join(".git").join("objects"))?;
+        let scratch = tempfile::tempdir_in(self.git_dir.join(".git"))?;
+        Self::git_in(scratch.path(), ["init", "--quiet", "--bare"]).await?;
+        std::fs::write(
+            scratch
+                .path()
+                .join("objects")
+                .join("info")
+                .join("alternates"),
+            format!("{}\n", objects.display()),
+        )?;
+        let pinned = format!("+{0}*:{0}*", PINNED_REFS);
+        Self::git_in(
+            scratch.path(),
+            [
+                OsStr::new("-c"),
+                OsStr::new("fetch.fsckObjects=true"),
+                OsStr::new("fetch"),
+                OsStr::new("--quiet"),
+                path.as_os_str(),
+                OsStr::new(&pinned),
+            ],
+        )
+        .await?;
+
+        let origin = self
+            .git([
+                "for-each-ref",
+                "--format=%(objectname)",
+                "refs/heads",
+                "refs/tags",
+                "refs/remotes/origin",
+                PINNED_REFS,
+                FETCHED_REFS,
+            ])
+            .await?;
+        let pins = Self::git_in(
+            scratch.path(),
+            [
+                "for-each-ref",
+                "--format=%(refname) %(objectname)",
+                PINNED_REFS,
+            ],
+        )
+        .await?;
+        let mut verified = Vec::new();
+        for pin in pins.lines() {
+            let Some((name, commit)) = pin.split_once(' ') else {
+                continue;
+            };
+            if name.strip_prefix(PINNED_REFS) != Some(commit) {
+                tracing::warn!("git-cache: {} of {} points at {}", name, self.url, commit);
+                continue;
+            }
+            // nothing is left of the history of `commit` without the one of
+            // the origin, the history of shallow clones goes on in the bundle,
+            // if it doesn't the walk fails
+            let mut args = vec!["rev-list", "--max-count=1", commit, "--not"];
+            args.extend(origin.lines());
+            let is_verified = Self::git_in(scratch.path(), args)
+                .await
+                .map(|unknown| unknown.is_empty())
+                .unwrap_or(false);
+            if is_verified {
+                verified.push(format!("+{0}:{0}", name));
+            } else {
+                tracing::warn!(
+                    "git-cache: {} of {} isn't in the history of its origin",
+                    name,
+                    self.url
+                );
+            }
+        }
+        if verified.is_empty() {
+            return Ok(());
+        }
+        let mut args = vec![
+            OsStr::new("fetch"),
+            OsStr::new("--quiet"),
+            scratch.path().as_os_str(),
+        ];
+        args.extend(verified.iter().map(OsStr::new));
+        self.git(args).await?;
+        Ok(())
+    }
 
-        if git_show_process.wait().await?.success() {
-            Ok(zstd_body)
+    /// fetch `rev` which is cut off from a shallow clone: exact commits and tags
+    /// are fetched alone, anything else needs the whole history
+    pub async fn fetch_missing(&self, rev: &str) -> anyhow::Result<()> {
+        if !self.is_shallow() || self.has_commit(rev).await {
+            return Ok(());
+        }
+        let refspec = if is_full_hash(rev) {
+            // remotes have to allow fetching commits by hash
+            format!("+{0}:{1}{0}", rev, FETCHED_REFS)
         } else {
-            anyhow::bail!("git-show process failed (usually it's because file doesn't exist)")
+            format!("+refs/tags/{0}:refs/tags/{0}", rev)
+        };
+        if let Err(error) = self
+            .git(["fetch", "--quiet", "--depth", "1", "origin", &refspec])
+            .await
+        {
+            tracing::debug!("git-cache: can't fetch {} alone: {:?}", rev, error);
+        }
+        if !self.has_commit(rev).await {
+            tracing::info!(
+                "git-cache: {} isn't in the shallow clone of {}, fetch the whole history",
+                rev,
+                self.url
+            );
+            self.git(["fetch", "--quiet", "--unshallow", "--tags", "origin"])
+                .await?;
         }
+        self.update_server_info().await
     }
 
-    pub async fn git_show_uncompressed(
-        &self,
-        commit: impl AsRef<str>,
-        file_path: impl AsRef<str>,
-    ) -> anyhow::Result<Vec<u8>> {
-        let mut command = Command::new("git");
+    /// fetch blobs of the tree at `commit` (only paths matching `filter`) which
+    /// a partial clone doesn't have in one go, `git` would fetch them one by one
+    pub async fn fetch_blobs(&self, commit: &str, filter: &ArchiveFilter) -> anyhow::Result<()> {
+        if !self.is_partial() {
+            return Ok(());
+        }
+        // trees are there, so missing objects are listed without fetching them
+        let missing = self
+            .git([
+                "rev-list",
+                "--objects",
+                "--missing=print",
+                "--no-walk",
+                commit,
+            ])
+            .await?
+            .lines()
+            .filter_map(|line| line.strip_prefix('?').map(str::to_owned))
+            .collect::<HashSet<_>>();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let tree = self.git(["ls-tree", "-r", "-z", commit]).await?;
+        // `<mode> blob <object>\t<path>\0`
+        let blobs = tree
+            .split_terminator('\0')
+            .filter_map(|record| {
+                let (info, path) = record.split_once('\t')?;
+                let ["blob", object] = info.split(' ').skip(1).collect::<Vec<_>>()[..] else {
+                    return None;
+                };
+                (missing.contains(object) && filter.includes(path)).then(|| object.to_owned())
+            })
+            .collect::<Vec<_>>();
+        if blobs.is_empty() {
+            return Ok(());
+        }
+
+        tracing::debug!("git-cache: fetch {} blobs of {}", blobs.len(), commit);
+        // same fetch `git` runs for a single missing object
+        let mut command = sandbox::git();
         command
-            .arg("show")
-            .arg(format!("{}:{}", commit.as_ref(), file_path.as_ref()))
+            .args([
+                "-c",
+                "fetch.negotiationAlgorithm=noop",
+                "fetch",
+                "--quiet",
+                "--no-tags",
+                "--no-write-fetch-head",
+                "--recurse-submodules=no",
+                "--filter=blob:none",
+                "--stdin",
+                "origin",
+            ])
             .current_dir(&self.git_dir)
-            .stdout(Stdio::piped())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
             .stderr(Stdio::piped());
-
         tracing::trace!("{:?}", command);
-        let mut git_show_process = command.spawn()?;
+        let mut process = command.spawn()?;
+        if let Some(mut stdin) = process.stdin.take() {
+            stdin.write_all(blobs.join("\n").as_bytes()).await?;
+        }
+        let output = process.wait_with_output().await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{:?} failed: {}",
+                command,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        // a new promisor pack for `objects/info/packs`
+        self.update_server_info().await
+    }
+
+    async fn has_commit(&self, rev: &str) -> bool {
+        self.git(["cat-file", "-e", &format!("{}^{{commit}}", rev)])
+            .await
+            .is_ok()
+    }
 
-        if let Some(io) = git_show_process.stderr.take() {
-            io.map_per_line(|line| tracing::debug!("{}", line))
+    /// `git upload-pack` for smart http clients: the ref (v0) or capability (v2)
+    /// advertisement if `advertise`, otherwise the response to `request`
+    ///
+    /// shallow and filtered fetches are served, commits are fetched by hash as
+    /// long as they're reachable
+    pub fn upload_pack(
+        &self,
+        advertise: bool,
+        v2: bool,
+        request: Bytes,
+    ) -> anyhow::Result<ByteStream> {
+        let mut command = sandbox::git();
+        command
+            .args([
+                "-c",
+                "uploadpack.allowFilter=true",
+                "-c",
+                "uploadpack.allowReachableSHA1InWant=true",
+                "upload-pack",
+                "--stateless-rpc",
+            ])
+            .current_dir(&self.git_dir);
+        if advertise {
+            command.arg("--advertise-refs");
         }
+        command.arg(".");
+        if v2 {
+            command.env("GIT_PROTOCOL", "version=2");
+        }
+        CliGitBackend::stream_stdout(
+            command,
+            (!advertise).then_some(request),
+            "git-upload-pack process failed".to_owned(),
+        )
+    }
 
-        let Some(ref mut stdout) = git_show_process.stdout.take() else {
-            tracing::error!("unable to take STDOUT: url={}", &self.url);
-            anyhow::bail!("internal error");
-        };
+    /// commit `object` points to (itself or a tag of it), `None` for other objects
+    pub async fn commit_of(&self, object: &str) -> Option<String> {
+        self.git([
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{}^{{commit}}", object),
+        ])
+        .await
+        .ok()
+        .map(|commit| commit.trim().to_owned())
+    }
 
-        let mut body = Vec::new();
-        stdout.read_to_end(&mut body).await?;
-        tracing::trace!("body: {:?}", &body);
+    /// `info/refs` and `objects/info/packs` of the dumb protocol, regenerated
+    /// whenever refs or packs change: after updates, fetches and imports
+    pub async fn update_server_info(&self) -> anyhow::Result<()> {
+        self.backend().update_server_info(&self.git_dir).await
+    }
 
-        if git_show_process.wait().await?.success() {
-            Ok(body)
-        } else {
-            anyhow::bail!("git-show process failed (usually it's because file doesn't exist)")
-        }
+    /// the dumb protocol files have been generated, so they can be read without
+    /// the repo lock
+    pub fn has_server_info(git_dir: &Path) -> bool {
+        git_dir.join(".git").join("info").join("refs").exists()
     }
 
-    pub async fn normalized_commit(&self, commit: impl AsRef<str>) -> anyhow::Result<String> {
-        let mut git_process = tokio::process::Command::new("git")
-            .arg("rev-list")
-            .arg("--no-walk")
-            .arg(commit.as_ref())
-            .current_dir(&self.git_dir)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
+    /// file of the dumb protocol, see [`dumb_path::resolve`]
+    pub async fn dumb(&self, src: impl AsRef<str>) -> anyhow::Result<PathBuf> {
+        dumb_path::resolve(&self.git_dir.join(".git"), src.as_ref()).await
+    }
 
-        if let Some(io) = git_process.stderr.take() {
-            io.map_per_line(|line| tracing::debug!("{}", line))
-        }
+    /// uncompressed tar of the tree at `commit` (only paths matching `filter`),
+    /// submodules are left as empty directories
+    pub async fn git_archive(
+        &self,
+        commit: impl AsRef<str>,
+        filter: &ArchiveFilter,
+    ) -> anyhow::Result<ByteStream> {
+        self.backend()
+            .archive(&self.git_dir, commit.as_ref(), filter)
+            .await
+    }
 
-        let Some(mut stdout) = git_process.stdout.take() else {
-            tracing::error!("unable to take STDOUT: url={}", &self.url);
-            anyhow::bail!("internal error");
+    /// submodules pinned in the tree at `commit` under paths matching `filter`
+    pub async fn submodules(
+        &self,
+        commit: impl AsRef<str>,
+        filter: &ArchiveFilter,
+    ) -> anyhow::Result<Vec<Submodule>> {
+        let gitlinks = self
+            .backend()
+            .gitlinks(&self.git_dir, commit.as_ref())
+            .await?
+            .into_iter()
+            .filter(|gitlink| filter.under(&gitlink.path).is_some())
+            .collect::<Vec<_>>();
+        if gitlinks.is_empty() {
+            return Ok(Vec::new());
+        }
+        let gitmodules = match self
+            .git_show_uncompressed(commit.as_ref(), submodule::GITMODULES)
+            .await
+        {
+            Ok(gitmodules) => String::from_utf8_lossy(&gitmodules).into_owned(),
+            Err(error)
+                if matches!(
+                    GitRegistryError::of(&error),
+                    Some(GitRegistryError::PathNotFound { .. })
+                ) =>
+            {
+                String::new()
+            }
+            Err(error) => return Err(error),
         };
+        Ok(submodule::resolve(&self.url, &gitmodules, gitlinks))
+    }
+
+    /// raw content of `file_path` at `commit`
+    pub async fn git_show(
+        &self,
+        commit: impl AsRef<str>,
+        file_path: impl AsRef<str>,
+    ) -> anyhow::Result<ByteStream> {
+        self.backend()
+            .show(&self.git_dir, commit.as_ref(), file_path.as_ref())
+            .await
+    }
+
+    pub async fn git_show_uncompressed(
+        &self,
+        commit: impl AsRef<str>,
+        file_path: impl AsRef<str>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let body = self.git_show(commit, file_path).await?;
+        Ok(stream::read_to_end(body).await?)
+    }
 
-        let mut body = Vec::new();
-        stdout.read_to_end(&mut body).await?;
-        tracing::trace!("body: {:?}", &body);
+    pub async fn list_refs(&self) -> anyhow::Result<Vec<GitRef>> {
+        self.backend().list_refs(&self.git_dir).await
+    }
 
-        if git_process.wait().await?.success() {
-            Ok(String::from_utf8(body)?.trim().to_string())
-        } else {
-            anyhow::bail!("can't normalize `{}` to commit hash", commit.as_ref())
-        }
+    pub async fn normalized_commit(&self, commit: impl AsRef<str>) -> anyhow::Result<String> {
+        self.backend()
+            .rev_parse_commit(&self.git_dir, commit.as_ref())
+            .await
     }
 }
 
+/// sha1 or sha256 commit hash, not an abbreviated one
+fn is_full_hash(rev: &str) -> bool {
+    matches!(rev.len(), 40 | 64) && rev.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
 fn hex_hash<H>(hashable: &H) -> String
 where
     H: std::hash::Hash + ?Sized,
@@ -0,0 +1,230 @@
+use anyhow::Context;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// `.cache` files of cargo index caches: the version byte, index format (u32
+/// le) and index version, then `<version>\0<index line>\0` pairs
+const INDEX_CACHE_VERSION: u8 = 3;
+const INDEX_FORMAT_VERSION: u32 = 2;
+
+/// Line of a cargo registry index, only what's needed to serve it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub version: String,
+    /// sha256 of the `.crate`, lowercase hex
+    pub cksum: String,
+    /// the line as it is in the index
+    pub line: String,
+}
+
+#[derive(serde::Deserialize)]
+struct IndexLine {
+    name: String,
+    vers: String,
+    cksum: String,
+}
+
+/// Crates cargo already downloaded: `<cargo home>/registry/index/*/.cache`
+/// and `<cargo home>/registry/cache/*/<name>-<version>.crate`
+#[derive(Debug, Clone)]
+pub struct CargoCache {
+    registry_dir: PathBuf,
+}
+
+impl CargoCache {
+    pub fn new(cargo_home: impl AsRef<Path>) -> Self {
+        Self {
+            registry_dir: cargo_home.as_ref().join("registry"),
+        }
+    }
+
+    /// `$CARGO_HOME` or `~/.cargo`
+    pub fn default_cargo_home() -> PathBuf {
+        std::env::var_os("CARGO_HOME")
+            .map(PathBuf::from)
+            .or_else(|| dirs::home_dir().map(|home| home.join(".cargo")))
+            .unwrap_or(PathBuf::from(".cargo"))
+    }
+
+    /// index entries of crate `name` in every local registry, by version
+    pub async fn index(&self, name: &str) -> anyhow::Result<BTreeMap<String, IndexEntry>> {
+        let path = index_path(name)?;
+        let mut entries = BTreeMap::new();
+        for registry in self.registries("index").await? {
+            let cache_file = registry.join(".cache").join(&path);
+            let content = match tokio::fs::read(&cache_file).await {
+                Ok(content) => content,
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error).with_context(|| format!("{:?}", cache_file)),
+            };
+            for entry in parse_index_cache(&content)
+                .with_context(|| format!("index cache {:?}", cache_file))?
+            {
+                entries.entry(entry.version.clone()).or_insert(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// `.crate` of `name` `version` if cargo downloaded it
+    pub async fn crate_path(&self, name: &str, version: &str) -> anyhow::Result<Option<PathBuf>> {
+        index_path(name)?;
+        if !is_version(version) {
+            anyhow::bail!("invalid crate version `{}`", version);
+        }
+        let file_name = format!("{}-{}.crate", name, version);
+        for registry in self.registries("cache").await? {
+            let path = registry.join(&file_name);
+            if tokio::fs::metadata(&path).await.is_ok() {
+                return Ok(Some(path));
+            }
+        }
+        Ok(None)
+    }
+
+    /// `registry/<kind>/*`, e.g. `index.crates.io-6f17d22bba15001f`
+    async fn registries(&self, kind: &str) -> anyhow::Result<Vec<PathBuf>> {
+        let mut registries = Vec::new();
+        let mut dir = match tokio::fs::read_dir(self.registry_dir.join(kind)).await {
+            Ok(dir) => dir,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(registries),
+            Err(error) => return Err(error.into()),
+        };
+        while let Some(entry) = dir.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                registries.push(entry.path());
+            }
+        }
+        registries.sort();
+        Ok(registries)
+    }
+}
+
+/// path of crate `name` in a sparse index: `1/a`, `2/ab`, `3/a/abc` or
+/// `ab/cd/abcd`, lowercase
+pub fn index_path(name: &str) -> anyhow::Result<String> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        anyhow::bail!("invalid crate name `{}`", name);
+    }
+    let name = name.to_ascii_lowercase();
+    Ok(match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[0..2], &name[2..4], name),
+    })
+}
+
+fn is_version(version: &str) -> bool {
+    !version.is_empty()
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'))
+}
+
+pub fn parse_index_cache(content: &[u8]) -> anyhow::Result<Vec<IndexEntry>> {
+    let Some((&cache_version, rest)) = content.split_first() else {
+        anyhow::bail!("empty");
+    };
+    if cache_version != INDEX_CACHE_VERSION || rest.len() < 4 {
+        anyhow::bail!("unsupported cache version {}", cache_version);
+    }
+    let (format_version, rest) = rest.split_at(4);
+    let format_version = u32::from_le_bytes(format_version.try_into()?);
+    if format_version > INDEX_FORMAT_VERSION {
+        anyhow::bail!("unsupported index format {}", format_version);
+    }
+    let mut fields = rest.split(|byte| *byte == 0);
+    // index version, e.g. the etag of the file
+    fields.next();
+    let mut entries = Vec::new();
+    while let (Some(version), Some(line)) = (fields.next(), fields.next()) {
+        if version.is_empty() {
+            break;
+        }
+        let line = std::str::from_utf8(line)?;
+        let parsed: IndexLine = serde_json::from_str(line)?;
+        if parsed.vers.as_bytes() != version {
+            anyhow::bail!("{} {} is under another version", parsed.name, parsed.vers);
+        }
+        entries.push(IndexEntry {
+            version: parsed.vers,
+            cksum: parsed.cksum,
+            line: line.to_owned(),
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index_cache(lines: &[(&str, &str)]) -> Vec<u8> {
+        let mut content = vec![INDEX_CACHE_VERSION];
+        content.extend_from_slice(&INDEX_FORMAT_VERSION.to_le_bytes());
+        content.extend_from_slice(b"etag: \"abc\"\0");
+        for (version, line) in lines {
+            content.extend_from_slice(version.as_bytes());
+            content.push(0);
+            content.extend_from_slice(line.as_bytes());
+            content.push(0);
+        }
+        content
+    }
+
+    #[test]
+    fn index_path_test() {
+        assert_eq!(index_path("a").unwrap(), "1/a");
+        assert_eq!(index_path("cc").unwrap(), "2/cc");
+        assert_eq!(index_path("Syn").unwrap(), "3/s/syn");
+        assert_eq!(index_path("serde_json").unwrap(), "se/rd/serde_json");
+        assert!(index_path("").is_err());
+        assert!(index_path("../etc").is_err());
+    }
+
+    #[tokio::test]
+    async fn cargo_cache_test() {
+        let line = r#"{"name":"glob","vers":"0.3.1","deps":[],"cksum":"aa","features":{}}"#;
+        let other = r#"{"name":"glob","vers":"0.3.0","deps":[],"cksum":"bb","features":{}}"#;
+        let content = index_cache(&[("0.3.0", other), ("0.3.1", line)]);
+        let entries = parse_index_cache(&content).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[1],
+            IndexEntry {
+                version: "0.3.1".to_owned(),
+                cksum: "aa".to_owned(),
+                line: line.to_owned(),
+            }
+        );
+        assert!(parse_index_cache(&content[..3]).is_err());
+        assert!(parse_index_cache(&index_cache(&[("0.3.2", line)])).is_err());
+
+        let home = tempfile::tempdir().unwrap();
+        let registry = home.path().join("registry");
+        let index = registry.join("index/index.crates.io-0/.cache/gl/ob");
+        std::fs::create_dir_all(&index).unwrap();
+        std::fs::write(index.join("glob"), &content).unwrap();
+        let cache = registry.join("cache/index.crates.io-0");
+        std::fs::create_dir_all(&cache).unwrap();
+        std::fs::write(cache.join("glob-0.3.1.crate"), b"crate").unwrap();
+
+        let cargo = CargoCache::new(home.path());
+        let index = cargo.index("glob").await.unwrap();
+        assert_eq!(index.keys().collect::<Vec<_>>(), ["0.3.0", "0.3.1"]);
+        assert!(cargo.index("serde").await.unwrap().is_empty());
+        assert_eq!(
+            cargo.crate_path("glob", "0.3.1").await.unwrap(),
+            Some(cache.join("glob-0.3.1.crate"))
+        );
+        assert_eq!(cargo.crate_path("glob", "0.3.0").await.unwrap(), None);
+        assert!(cargo.crate_path("glob", "../0.3.1").await.is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::git_url::GitUrl;
+use glob::{MatchOptions, Pattern};
+use std::{fmt, str::FromStr};
+
+// `*` matches `/` too, so `https://github.com/org/*` covers every repo of the org
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// How much of a repo is fetched into the cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClonePolicy {
+    /// every commit with every blob
+    #[default]
+    Full,
+    /// branch tips only (`--depth 1`), other commits are fetched when they're asked for
+    Shallow,
+    /// every commit without blobs (`--filter=blob:none`), blobs are fetched when
+    /// they're read
+    Partial,
+}
+
+impl ClonePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClonePolicy::Full => "full",
+            ClonePolicy::Shallow => "shallow",
+            ClonePolicy::Partial => "partial",
+        }
+    }
+
+    /// extra `git clone` args
+    pub(crate) fn clone_args(&self) -> &'static [&'static str] {
+        match self {
+            ClonePolicy::Full => &[],
+            ClonePolicy::Shallow => &["--depth", "1", "--no-single-branch"],
+            ClonePolicy::Partial => &["--filter=blob:none"],
+        }
+    }
+}
+
+impl FromStr for ClonePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(ClonePolicy::Full),
+            "shallow" => Ok(ClonePolicy::Shallow),
+            "partial" => Ok(ClonePolicy::Partial),
+            _ => anyhow::bail!(
+                "unknown clone policy `{}`: expected one of `full`, `shallow`, `partial`",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ClonePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// [`ClonePolicy`] for repos matching a glob pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClonePolicyRule {
+    /// matched against the canonical url, e.g. `gosh://0:<system contract>/dao/*`
+    pub repos: Pattern,
+    pub policy: ClonePolicy,
+}
+
+impl ClonePolicyRule {
+    pub fn new(repos: impl AsRef<str>, policy: ClonePolicy) -> anyhow::Result<Self> {
+        let repos = Pattern::new(repos.as_ref())
+            .map_err(|err| anyhow::anyhow!("wrong repos pattern `{}`: {}", repos.as_ref(), err))?;
+        Ok(Self { repos, policy })
+    }
+}
+
+/// Clone policies of repos, the first matching rule wins and the rest are
+/// cloned in full
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClonePolicies(Vec<ClonePolicyRule>);
+
+impl ClonePolicies {
+    pub fn new(rules: impl IntoIterator<Item = ClonePolicyRule>) -> Self {
+        Self(rules.into_iter().collect())
+    }
+
+    pub fn policy(&self, url: &GitUrl) -> ClonePolicy {
+        let canonical = url.to_string();
+        self.0
+            .iter()
+            .find(|rule| rule.repos.matches_with(&canonical, MATCH_OPTIONS))
+            .map_or(ClonePolicy::Full, |rule| rule.policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_test() {
+        let policies = ClonePolicies::new([
+            ClonePolicyRule::new("https://github.com/big/monorepo", ClonePolicy::Partial).unwrap(),
+            ClonePolicyRule::new("https://github.com/big/*", ClonePolicy::Shallow).unwrap(),
+            ClonePolicyRule::new("gosh://*/dao/*", ClonePolicy::Shallow).unwrap(),
+        ]);
+        let cases = [
+            ("https://github.com/big/monorepo", ClonePolicy::Partial),
+            ("https://github.com/big/other.git", ClonePolicy::Shallow),
+            ("https://github.com/small/repo", ClonePolicy::Full),
+            (
+                "gosh://0:0d5c05d7a63f438b57ede179b7110d3e903f5be3b5f543d3d6743d774698e92c/dao/repo",
+                ClonePolicy::Shallow,
+            ),
+            ("file:///srv/git/repo", ClonePolicy::Full),
+        ];
+        for (url, expected) in cases {
+            let url: GitUrl = url.parse().unwrap();
+            assert_eq!(policies.policy(&url), expected, "{}", url);
+        }
+        assert_eq!(
+            ClonePolicies::default().policy(&"https://github.com/big/repo".parse().unwrap()),
+            ClonePolicy::Full
+        );
+    }
+
+    #[test]
+    fn from_str_test() {
+        for policy in [
+            ClonePolicy::Full,
+            ClonePolicy::Shallow,
+            ClonePolicy::Partial,
+        ] {
+            assert_eq!(policy.as_str().parse::<ClonePolicy>().unwrap(), policy);
+        }
+        assert!("blobless".parse::<ClonePolicy>().is_err());
+        assert!(ClonePolicyRule::new("https://github.com/[", ClonePolicy::Full).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use crate::cache;
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::{path::PathBuf, time::Duration};
+use tokio::io::AsyncWriteExt;
+use tokio_stream::StreamExt;
+
+const SHA256_HEX_LEN: usize = 64;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// the whole download, release tarballs of toolchains are large
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+pub const MAX_DOWNLOAD_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// File a build downloaded, kept in a [`DownloadStore`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Download {
+    pub url: String,
+    /// sha256 of the content, lowercase hex
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// Downloads by their sha256 in `<dir>/<sha[0..2]>/<sha[2..4]>/<sha>`,
+/// the same layout as `DirLfsStore`
+#[derive(Debug)]
+pub struct DownloadStore {
+    dir: PathBuf,
+    client: reqwest::Client,
+    max_size: u64,
+}
+
+impl DownloadStore {
+    pub fn new(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        Ok(Self {
+            dir: dir.into(),
+            client: reqwest::Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .timeout(DOWNLOAD_TIMEOUT)
+                .build()?,
+            max_size: MAX_DOWNLOAD_SIZE,
+        })
+    }
+
+    /// downloads larger than `max_size` bytes fail, [`MAX_DOWNLOAD_SIZE`] by default
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// `downloads` in [`cache::default_cache_dir`]
+    pub fn default_dir() -> PathBuf {
+        cache::default_cache_dir().join("downloads")
+    }
+
+    pub fn object_path(&self, sha256: &str) -> anyhow::Result<PathBuf> {
+        if !is_sha256(sha256) {
+            anyhow::bail!("invalid sha256 `{}`", sha256);
+        }
+        Ok(self
+            .dir
+            .join(&sha256[0..2])
+            .join(&sha256[2..4])
+            .join(sha256))
+    }
+
+    pub fn contains(&self, sha256: &str) -> bool {
+        self.object_path(sha256)
+            .map(|path| path.is_file())
+            .unwrap_or(false)
+    }
+
+    /// `url` which is already in the store as `sha256`
+    pub async fn stored(&self, url: &str, sha256: &str) -> Option<Download> {
+        let metadata = tokio::fs::metadata(self.object_path(sha256).ok()?)
+            .await
+            .ok()?;
+        Some(Download {
+            url: url.to_owned(),
+            sha256: sha256.to_owned(),
+            size: metadata.len(),
+        })
+    }
+
+    /// GET `url` into the store, it isn't stored unless its sha256 is
+    /// `expected` (if set)
+    pub async fn fetch(&self, url: &str, expected: Option<&str>) -> anyhow::Result<Download> {
+        tracing::debug!("download: GET {}", url);
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("download {}", url))?;
+        if let Some(length) = response.content_length() {
+            self.check_size(url, length)?;
+        }
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("download store {:?}", self.dir))?;
+        let temp = tempfile::NamedTempFile::new_in(&self.dir)?;
+        let mut file = tokio::fs::File::from_std(temp.reopen()?);
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        let mut body = response.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.with_context(|| format!("download {}", url))?;
+            hasher.update(&chunk);
+            size += chunk.len() as u64;
+            // the length may be missing or wrong, the stream is what counts
+            self.check_size(url, size)?;
+            file.write_all(&chunk).await?;
+        }
+        file.sync_all().await?;
+
+        let sha256 = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        if let Some(expected) = expected {
+            if sha256 != expected {
+                anyhow::bail!(
+                    "content of {} changed: sha256 {} instead of pinned {}",
+                    url,
+                    sha256,
+                    expected
+                );
+            }
+        }
+        let path = self.object_path(&sha256)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        temp.persist(&path)?;
+        Ok(Download {
+            url: url.to_owned(),
+            sha256,
+            size,
+        })
+    }
+
+    fn check_size(&self, url: &str, size: u64) -> anyhow::Result<()> {
+        if size > self.max_size {
+            anyhow::bail!("download {} is larger than {} bytes", url, self.max_size);
+        }
+        Ok(())
+    }
+}
+
+fn is_sha256(hex: &str) -> bool {
+    hex.len() == SHA256_HEX_LEN
+        && hex
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    const CONTENT: &[u8] = b"release tarball";
+
+    /// answers every connection with `body`
+    async fn serve(body: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/release.tar.gz", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                // the request head, the body is empty
+                let (mut head, mut buf) = (Vec::new(), [0; 1024]);
+                while !head.ends_with(b"\r\n\r\n") {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(len) => head.extend_from_slice(&buf[..len]),
+                    }
+                }
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                    body.len()
+                );
+                socket.write_all(head.as_bytes()).await.ok();
+                socket.write_all(body).await.ok();
+            }
+        });
+        url
+    }
+
+    fn sha256_of(content: &[u8]) -> String {
+        Sha256::digest(content)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fetch_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DownloadStore::new(dir.path()).unwrap();
+        let url = serve(CONTENT).await;
+        let sha256 = sha256_of(CONTENT);
+        assert!(!store.contains(&sha256));
+
+        let download = store.fetch(&url, None).await.unwrap();
+        assert_eq!(
+            download,
+            Download {
+                url: url.clone(),
+                sha256: sha256.clone(),
+                size: CONTENT.len() as u64,
+            }
+        );
+        assert!(store.contains(&sha256));
+        let path = store.object_path(&sha256).unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), CONTENT);
+
+        let pinned = store.fetch(&url, Some(&sha256)).await.unwrap();
+        assert_eq!(pinned, download);
+        assert_eq!(store.stored(&url, &sha256).await, Some(download));
+    }
+
+    #[tokio::test]
+    async fn fetch_changed_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DownloadStore::new(dir.path()).unwrap();
+        let url = serve(b"new release").await;
+        let pinned = sha256_of(CONTENT);
+
+        let error = store.fetch(&url, Some(&pinned)).await.unwrap_err();
+        assert!(error.to_string().contains("changed"), "{}", error);
+        assert!(!store.contains(&pinned));
+        assert!(!store.contains(&sha256_of(b"new release")));
+
+        assert!(store.object_path("../../etc/passwd").is_err());
+    }
+
+    #[tokio::test]
+    async fn fetch_too_large_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DownloadStore::new(dir.path())
+            .unwrap()
+            .with_max_size(CONTENT.len() as u64 - 1);
+        let url = serve(CONTENT).await;
+
+        let error = store.fetch(&url, None).await.unwrap_err();
+        assert!(error.to_string().contains("larger"), "{}", error);
+        assert!(!store.contains(&sha256_of(CONTENT)));
+
+        let store = store.with_max_size(CONTENT.len() as u64);
+        assert!(store.fetch(&url, None).await.is_ok());
+    }
+}
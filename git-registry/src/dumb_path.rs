@@ -0,0 +1,123 @@
+use crate::error::GitRegistryError;
+use std::path::{Path, PathBuf};
+
+/// files the dumb protocol reads, everything under `objects/` and `refs/`
+const DUMB_FILES: &[&str] = &["HEAD", "info/refs", "packed-refs"];
+const DUMB_DIRS: &[&str] = &["objects", "refs"];
+
+/// `src` is a file dumb clients may ask for: no `config`, `hooks/` or escapes
+fn is_dumb_path(src: &str) -> bool {
+    let segments = src.split('/').collect::<Vec<_>>();
+    if segments.iter().any(|segment| {
+        segment.is_empty() || *segment == "." || *segment == ".." || segment.contains('\\')
+    }) {
+        return false;
+    }
+    DUMB_FILES.contains(&src) || (segments.len() > 1 && DUMB_DIRS.contains(&segments[0]))
+}
+
+/// `src` of the `.git` dir `git_dir`, canonicalized when it exists
+///
+/// symlinks may only point inside `git_dir`, missing files are left to the
+/// caller to report as not found
+pub(crate) async fn resolve(git_dir: &Path, src: &str) -> anyhow::Result<PathBuf> {
+    let forbidden = || GitRegistryError::ForbiddenPath {
+        path: src.to_owned(),
+    };
+    if !is_dumb_path(src) {
+        return Err(forbidden().into());
+    }
+
+    let root = tokio::fs::canonicalize(git_dir).await?;
+    let path = git_dir.join(src);
+    match tokio::fs::canonicalize(&path).await {
+        Ok(canonical) if canonical.starts_with(&root) => Ok(canonical),
+        Ok(_) => Err(forbidden().into()),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(path),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_dumb_path_test() {
+        for src in [
+            "HEAD",
+            "info/refs",
+            "packed-refs",
+            "objects/info/packs",
+            "objects/ab/cdef0123456789abcdef0123456789abcdef01",
+            "objects/pack/pack-0123.idx",
+            "refs/heads/main",
+        ] {
+            assert!(is_dumb_path(src), "{}", src);
+        }
+        for src in [
+            "",
+            "config",
+            "hooks/pre-commit",
+            "info/exclude",
+            "objects",
+            "refs/",
+            "../../etc/passwd",
+            "objects/../config",
+            "objects/./info/packs",
+            "/etc/passwd",
+            "objects//info/packs",
+            "refs\\..\\config",
+        ] {
+            assert!(!is_dumb_path(src), "{}", src);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let git_dir = dir.path().join(".git");
+        std::fs::create_dir_all(git_dir.join("objects/info")).unwrap();
+        std::fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        std::fs::write(git_dir.join("config"), "[core]\n").unwrap();
+        std::fs::write(dir.path().join("secret"), "secret\n").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("secret"), git_dir.join("refs/heads/out"))
+            .unwrap();
+        std::os::unix::fs::symlink(dir.path(), git_dir.join("objects/info/up")).unwrap();
+        std::os::unix::fs::symlink(git_dir.join("HEAD"), git_dir.join("refs/heads/head")).unwrap();
+
+        let root = git_dir.canonicalize().unwrap();
+        assert_eq!(resolve(&git_dir, "HEAD").await.unwrap(), root.join("HEAD"));
+        assert_eq!(
+            resolve(&git_dir, "refs/heads/head").await.unwrap(),
+            root.join("HEAD")
+        );
+        // missing objects are not found, not forbidden
+        assert_eq!(
+            resolve(&git_dir, "objects/pack/pack-0123.pack")
+                .await
+                .unwrap(),
+            git_dir.join("objects/pack/pack-0123.pack")
+        );
+
+        for src in [
+            "config",
+            "../secret",
+            "objects/../config",
+            "refs/heads/out",
+            "objects/info/up/secret",
+        ] {
+            let error = resolve(&git_dir, src).await.unwrap_err();
+            assert!(
+                matches!(
+                    GitRegistryError::of(&error),
+                    Some(GitRegistryError::ForbiddenPath { .. })
+                ),
+                "{}: {:?}",
+                src,
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::git_url::GitUrl;
+use std::{fmt, path::PathBuf};
+
+/// Failures callers can react to
+///
+/// Registry methods return `anyhow::Error` with one of these inside (possibly
+/// under extra context), [`GitRegistryError::of`] finds it. Errors without one
+/// are internal
+#[derive(Debug)]
+pub enum GitRegistryError {
+    /// remote host isn't in the allowed hosts
+    NotAllowed { url: GitUrl },
+    /// neither a mirror nor the remote itself could be fetched
+    Unreachable { url: GitUrl },
+    /// branch, tag or commit doesn't exist in the repo
+    RefNotFound { rev: String },
+    /// path doesn't exist at the commit
+    PathNotFound { commit: String, path: String },
+    /// cached clone can't be opened or updated
+    CacheCorrupt { git_dir: PathBuf },
+    /// repo isn't cached and the registry is offline
+    Offline { url: GitUrl },
+    /// dumb protocol path is outside of the served files of a repo
+    ForbiddenPath { path: String },
+}
+
+impl GitRegistryError {
+    pub fn of(error: &anyhow::Error) -> Option<&Self> {
+        error.downcast_ref()
+    }
+
+    /// ref or path doesn't exist, retry won't help
+    pub fn is_not_found(&self) -> bool {
+        matches!(
+            self,
+            GitRegistryError::RefNotFound { .. } | GitRegistryError::PathNotFound { .. }
+        )
+    }
+
+    /// remote can't be reached now
+    pub fn is_unavailable(&self) -> bool {
+        matches!(
+            self,
+            GitRegistryError::Unreachable { .. } | GitRegistryError::Offline { .. }
+        )
+    }
+}
+
+impl fmt::Display for GitRegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitRegistryError::NotAllowed { url } => write!(
+                f,
+                "git remote `{}` is not allowed: host `{}` is not in the allowed hosts",
+                url,
+                url.host().unwrap_or_default()
+            ),
+            GitRegistryError::Unreachable { url } => write!(f, "can't fetch `{}`", url),
+            GitRegistryError::RefNotFound { rev } => {
+                write!(f, "can't normalize `{}` to commit hash", rev)
+            }
+            GitRegistryError::PathNotFound { commit, path } => {
+                write!(f, "path `{}` doesn't exist in `{}`", path, commit)
+            }
+            GitRegistryError::CacheCorrupt { git_dir } => {
+                write!(f, "cached repo {:?} is corrupt", git_dir)
+            }
+            GitRegistryError::Offline { url } => {
+                write!(f, "`{}` is not cached and the registry is offline", url)
+            }
+            GitRegistryError::ForbiddenPath { path } => {
+                write!(f, "path `{}` is not served", path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GitRegistryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn error_of_test() {
+        let error = anyhow::Error::from(GitRegistryError::RefNotFound {
+            rev: "nope".to_owned(),
+        });
+        let error = Err::<(), _>(error)
+            .context("submodule `vendor/lib`")
+            .unwrap_err();
+        assert!(GitRegistryError::of(&error).unwrap().is_not_found());
+
+        let url: GitUrl = "https://github.com/a/b".parse().unwrap();
+        let error = Err::<(), _>(anyhow::anyhow!("exit status: 128"))
+            .context(GitRegistryError::Unreachable { url })
+            .unwrap_err();
+        assert!(GitRegistryError::of(&error).unwrap().is_unavailable());
+        assert_eq!(error.to_string(), "can't fetch `https://github.com/a/b`");
+
+        assert!(GitRegistryError::of(&anyhow::anyhow!("other")).is_none());
+    }
+}
@@ -1,3 +1,5 @@
+use crate::git_url::GitUrl;
+use anyhow::Context;
 use std::str::FromStr;
 
 /// git context
@@ -6,7 +8,7 @@ use std::str::FromStr;
 #[derive(Debug, Clone)]
 pub struct GitContext {
     /// git repository url
-    pub remote: String,
+    pub remote: GitUrl,
     /// git ref, default is master
     pub git_ref: String,
     /// sub dir, default is empty
@@ -22,7 +24,9 @@ impl FromStr for GitContext {
         let (git_ref, sub_dir) = fragment.split_once(':').unwrap_or((fragment, ""));
 
         Ok(GitContext {
-            remote: remote.to_string(),
+            remote: remote
+                .parse()
+                .with_context(|| format!("wrong git context `{}`", s))?,
             git_ref: git_ref.to_string(),
             sub_dir: sub_dir.to_string(),
         })
@@ -32,11 +36,11 @@ impl FromStr for GitContext {
 impl ToString for GitContext {
     fn to_string(&self) -> String {
         match (
-            self.remote.as_str(),
+            self.remote.to_string(),
             self.git_ref.as_str(),
             self.sub_dir.as_str(),
         ) {
-            (remote, "", "") => remote.to_owned(),
+            (remote, "", "") => remote,
             (remote, git_ref, "") => format!("{}#{}", remote, git_ref),
             (remote, git_ref, sub_dir) => format!("{}#{}:{}", remote, git_ref, sub_dir),
         }
@@ -59,15 +63,18 @@ impl From<GitContext> for String {
 mod tests {
     use super::*;
 
+    const REMOTE: &str =
+        "gosh://0:0d5c05d7a63f438b57ede179b7110d3e903f5be3b5f543d3d6743d774698e92c/docker/docker";
+
     #[test]
     fn docker_git_url_test() {
-        let url = "gosh://0:230230293XXXXXXXXXXXX/docker/docker.git#v1.13.0:src/dir";
+        let url = format!("{}#v1.13.0:src/dir", REMOTE);
         let ctx: GitContext = url.parse().unwrap();
 
-        assert_eq!(
-            ctx.remote,
-            "gosh://0:230230293XXXXXXXXXXXX/docker/docker.git"
-        );
+        assert_eq!(ctx.remote.to_string(), REMOTE);
+        let gosh_url = ctx.remote.as_gosh().unwrap();
+        assert_eq!(gosh_url.dao(), "docker");
+        assert_eq!(gosh_url.repo(), "docker");
         assert_eq!(ctx.git_ref, "v1.13.0");
         assert_eq!(ctx.sub_dir, "src/dir");
 
@@ -77,31 +84,47 @@ mod tests {
 
     #[test]
     fn docker_git_url_test2() {
-        let url = "gosh://0:230230293XXXXXXXXXXXX/docker/docker.git#ref/test";
+        let url = format!("{}.git#ref/test", REMOTE);
         let ctx: GitContext = url.parse().unwrap();
 
-        assert_eq!(
-            ctx.remote,
-            "gosh://0:230230293XXXXXXXXXXXX/docker/docker.git"
-        );
+        assert_eq!(ctx.remote.to_string(), REMOTE);
         assert_eq!(ctx.git_ref, "ref/test");
         assert_eq!(ctx.sub_dir, "");
 
-        assert_eq!(String::from(ctx), url);
+        // `.git` suffix is normalized
+        assert_eq!(String::from(ctx), format!("{}#ref/test", REMOTE));
     }
 
     #[test]
     fn docker_git_url_test3() {
-        let url = "gosh://0:230230293XXXXXXXXXXXX/docker/docker.git#:dir/dir";
+        let url = format!("{}#:dir/dir", REMOTE);
         let ctx: GitContext = url.parse().unwrap();
 
-        assert_eq!(
-            ctx.remote,
-            "gosh://0:230230293XXXXXXXXXXXX/docker/docker.git"
-        );
+        assert_eq!(ctx.remote.to_string(), REMOTE);
         assert_eq!(ctx.git_ref, "");
         assert_eq!(ctx.sub_dir, "dir/dir");
 
         assert_eq!(String::from(ctx), url);
     }
+
+    #[test]
+    fn docker_git_url_https_test() {
+        let url = "https://github.com/docker/cli.git#v24.0.2:scripts";
+        let ctx: GitContext = url.parse().unwrap();
+
+        assert_eq!(ctx.remote.to_string(), "https://github.com/docker/cli.git");
+        assert_eq!(ctx.remote.host(), Some("github.com"));
+        assert_eq!(ctx.git_ref, "v24.0.2");
+        assert_eq!(ctx.sub_dir, "scripts");
+
+        assert_eq!(String::from(ctx), url);
+    }
+
+    #[test]
+    fn docker_git_url_error_test() {
+        let error = "gosh://0:230230293XXXXXXXXXXXX/docker/docker.git#v1"
+            .parse::<GitContext>()
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("invalid system contract address"));
+    }
 }
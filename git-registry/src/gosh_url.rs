@@ -0,0 +1,221 @@
+use std::{fmt, str::FromStr};
+
+const SCHEME: &str = "gosh";
+const GIT_SUFFIX: &str = ".git";
+const WORKCHAIN_PREFIX: &str = "0:";
+const ADDRESS_HEX_LEN: usize = 64;
+
+/// Parsed gosh repository url
+///
+/// `gosh://0:<system contract>/<dao>/<repo>` or, with an explicit network,
+/// `gosh::<network>://0:<system contract>/<dao>/<repo>`
+///
+/// Optional `.git` suffix of the repo is dropped, so both forms point to the same repo
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GoshUrl {
+    network: Option<String>,
+    system_contract: String,
+    dao: String,
+    repo: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GoshUrlError {
+    Scheme(String),
+    MissingPart(&'static str),
+    ExtraPath(String),
+    SystemContract(String),
+    Name { kind: &'static str, name: String },
+}
+
+impl fmt::Display for GoshUrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoshUrlError::Scheme(url) => write!(
+                f,
+                "`{}` is not a gosh url: expected `gosh://0:<system contract>/<dao>/<repo>`",
+                url
+            ),
+            GoshUrlError::MissingPart(part) => write!(f, "gosh url has no {}", part),
+            GoshUrlError::ExtraPath(path) => {
+                write!(f, "gosh url has extra path after the repo name: `{}`", path)
+            }
+            GoshUrlError::SystemContract(address) => write!(
+                f,
+                "invalid system contract address `{}`: expected `0:` and 64 hex digits",
+                address
+            ),
+            GoshUrlError::Name { kind, name } => write!(f, "invalid {} name `{}`", kind, name),
+        }
+    }
+}
+
+impl std::error::Error for GoshUrlError {}
+
+impl GoshUrl {
+    pub fn new(
+        system_contract: impl AsRef<str>,
+        dao: impl AsRef<str>,
+        repo: impl AsRef<str>,
+    ) -> Result<Self, GoshUrlError> {
+        let repo = repo.as_ref();
+        Ok(Self {
+            network: None,
+            system_contract: parse_system_contract(system_contract.as_ref())?,
+            dao: parse_name("dao", dao.as_ref())?,
+            repo: parse_name("repo", repo.strip_suffix(GIT_SUFFIX).unwrap_or(repo))?,
+        })
+    }
+
+    pub fn with_network(mut self, network: impl AsRef<str>) -> Result<Self, GoshUrlError> {
+        self.network = Some(parse_name("network", network.as_ref())?);
+        Ok(self)
+    }
+
+    pub fn network(&self) -> Option<&str> {
+        self.network.as_deref()
+    }
+
+    pub fn system_contract(&self) -> &str {
+        &self.system_contract
+    }
+
+    pub fn dao(&self) -> &str {
+        &self.dao
+    }
+
+    pub fn repo(&self) -> &str {
+        &self.repo
+    }
+}
+
+impl FromStr for GoshUrl {
+    type Err = GoshUrlError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((scheme, path)) = s.split_once("://") else {
+            return Err(GoshUrlError::Scheme(s.to_owned()));
+        };
+        let network = match scheme.split_once("::") {
+            None if scheme == SCHEME => None,
+            Some((SCHEME, network)) => Some(network),
+            _ => return Err(GoshUrlError::Scheme(s.to_owned())),
+        };
+
+        let mut parts = path.splitn(4, '/');
+        let system_contract = parts
+            .next()
+            .filter(|part| !part.is_empty())
+            .ok_or(GoshUrlError::MissingPart("system contract address"))?;
+        let dao = parts
+            .next()
+            .filter(|part| !part.is_empty())
+            .ok_or(GoshUrlError::MissingPart("dao"))?;
+        let repo = parts
+            .next()
+            .filter(|part| !part.is_empty())
+            .ok_or(GoshUrlError::MissingPart("repo"))?;
+        if let Some(extra) = parts.next() {
+            return Err(GoshUrlError::ExtraPath(extra.to_owned()));
+        }
+
+        let url = Self::new(system_contract, dao, repo)?;
+        match network {
+            Some(network) => url.with_network(network),
+            None => Ok(url),
+        }
+    }
+}
+
+impl fmt::Display for GoshUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.network {
+            Some(ref network) => write!(f, "{}::{}://", SCHEME, network)?,
+            None => write!(f, "{}://", SCHEME)?,
+        }
+        write!(f, "{}/{}/{}", self.system_contract, self.dao, self.repo)
+    }
+}
+
+impl From<&GoshUrl> for String {
+    fn from(value: &GoshUrl) -> Self {
+        value.to_string()
+    }
+}
+
+fn parse_system_contract(raw: &str) -> Result<String, GoshUrlError> {
+    match raw.strip_prefix(WORKCHAIN_PREFIX) {
+        Some(hex) if hex.len() == ADDRESS_HEX_LEN && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(format!("{}{}", WORKCHAIN_PREFIX, hex.to_ascii_lowercase()))
+        }
+        _ => Err(GoshUrlError::SystemContract(raw.to_owned())),
+    }
+}
+
+fn parse_name(kind: &'static str, raw: &str) -> Result<String, GoshUrlError> {
+    let is_valid = !raw.is_empty()
+        && raw != "."
+        && raw != ".."
+        && raw
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if is_valid {
+        Ok(raw.to_owned())
+    } else {
+        Err(GoshUrlError::Name {
+            kind,
+            name: raw.to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "0:0d5c05d7a63f438b57ede179b7110d3e903f5be3b5f543d3d6743d774698e92c";
+
+    #[test]
+    fn gosh_url_test() {
+        let url: GoshUrl = format!("gosh://{}/docker/docker.git", ADDRESS)
+            .parse()
+            .unwrap();
+        assert_eq!(url.network(), None);
+        assert_eq!(url.system_contract(), ADDRESS);
+        assert_eq!(url.dao(), "docker");
+        assert_eq!(url.repo(), "docker");
+        assert_eq!(url.to_string(), format!("gosh://{}/docker/docker", ADDRESS));
+
+        let same = GoshUrl::new(ADDRESS.to_uppercase(), "docker", "docker").unwrap();
+        assert_eq!(same, url);
+    }
+
+    #[test]
+    fn gosh_url_network_test() {
+        let raw = format!("gosh::network.gosh.sh://{}/dao/repo", ADDRESS);
+        let url: GoshUrl = raw.parse().unwrap();
+        assert_eq!(url.network(), Some("network.gosh.sh"));
+        assert_eq!(url.to_string(), raw);
+    }
+
+    #[test]
+    fn gosh_url_errors_test() {
+        let cases = [
+            ("https://github.com/a/b".to_owned(), "not a gosh url"),
+            ("gosh://".to_owned(), "no system contract address"),
+            (format!("gosh://{}", ADDRESS), "no dao"),
+            (format!("gosh://{}/dao/", ADDRESS), "no repo"),
+            (format!("gosh://{}/dao/repo/tree", ADDRESS), "extra path"),
+            (
+                "gosh://0:230230293XXXX/dao/repo".to_owned(),
+                "system contract",
+            ),
+            (format!("gosh://{}/d a o/repo", ADDRESS), "invalid dao name"),
+            (format!("gosh://{}/dao/..", ADDRESS), "invalid repo name"),
+        ];
+        for (raw, expected) in cases {
+            let error = raw.parse::<GoshUrl>().unwrap_err().to_string();
+            assert!(error.contains(expected), "{}: {}", raw, error);
+        }
+    }
+}
@@ -0,0 +1,475 @@
+use crate::submodule;
+use anyhow::Context;
+use bytes::Bytes;
+use gosh_utils::stream::{self, ByteStream};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Debug,
+    io::{self, Read},
+    path::PathBuf,
+    sync::Arc,
+};
+use tokio::{runtime::Handle, sync::Mutex};
+use tokio_stream::StreamExt;
+use tokio_util::io::{ReaderStream, StreamReader, SyncIoBridge};
+
+const POINTER_VERSION: &str = "https://git-lfs.github.com/spec/v1";
+const OID_PREFIX: &str = "sha256:";
+const OID_HEX_LEN: usize = 64;
+
+/// pointer files are always smaller than this (git-lfs spec)
+pub const MAX_POINTER_SIZE: u64 = 1024;
+
+/// placeholder of the object id in [`HttpLfsStore`] url templates
+pub const OID_PLACEHOLDER: &str = "{oid}";
+
+/// LFS objects resolved while an archive was streamed, complete once the body ends
+pub type LfsObjects = Arc<Mutex<Vec<LfsPointer>>>;
+
+/// Content of a pointer file which stands in the git tree instead of a large file
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LfsPointer {
+    /// sha256 of the object, lowercase hex
+    pub oid: String,
+    pub size: u64,
+}
+
+impl LfsPointer {
+    /// `None` if `content` is a regular file
+    pub fn parse(content: &[u8]) -> Option<Self> {
+        if content.len() as u64 >= MAX_POINTER_SIZE {
+            return None;
+        }
+        let content = std::str::from_utf8(content).ok()?;
+        let mut lines = content.lines();
+        if lines.next()? != format!("version {}", POINTER_VERSION) {
+            return None;
+        }
+        let (mut oid, mut size) = (None, None);
+        for line in lines {
+            let (key, value) = line.split_once(' ')?;
+            match key {
+                "oid" => oid = value.strip_prefix(OID_PREFIX),
+                "size" => size = value.parse().ok(),
+                _ => {}
+            }
+        }
+        let oid = oid.filter(|oid| {
+            oid.len() == OID_HEX_LEN
+                && oid
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+        })?;
+        Some(Self {
+            oid: oid.to_owned(),
+            size: size?,
+        })
+    }
+
+    /// `sha256:<oid>`
+    pub fn digest(&self) -> String {
+        format!("{}{}", OID_PREFIX, self.oid)
+    }
+
+    /// `reader` which fails at the end unless the object matches the pointer
+    pub fn verify<R: Read>(&self, reader: R) -> VerifiedReader<R> {
+        VerifiedReader {
+            inner: reader,
+            pointer: self.clone(),
+            hasher: Sha256::new(),
+            read: 0,
+        }
+    }
+}
+
+pub struct VerifiedReader<R> {
+    inner: R,
+    pointer: LfsPointer,
+    hasher: Sha256,
+    read: u64,
+}
+
+impl<R: Read> Read for VerifiedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.read += len as u64;
+        if self.read > self.pointer.size {
+            return Err(invalid_object(&self.pointer, "is larger than expected"));
+        }
+        self.hasher.update(&buf[..len]);
+        if len == 0 && !buf.is_empty() {
+            if self.read != self.pointer.size {
+                return Err(invalid_object(&self.pointer, "is smaller than expected"));
+            }
+            let oid = self
+                .hasher
+                .finalize_reset()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>();
+            if oid != self.pointer.oid {
+                return Err(invalid_object(
+                    &self.pointer,
+                    &format!("has digest {}{}", OID_PREFIX, oid),
+                ));
+            }
+        }
+        Ok(len)
+    }
+}
+
+fn invalid_object(pointer: &LfsPointer, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "LFS object {} ({} bytes) {}",
+            pointer.digest(),
+            pointer.size,
+            reason
+        ),
+    )
+}
+
+/// Where LFS objects are downloaded from, objects are verified by the caller
+#[async_trait::async_trait]
+pub trait LfsStore: Debug + Send + Sync {
+    async fn fetch(&self, pointer: &LfsPointer) -> anyhow::Result<ByteStream>;
+}
+
+/// `file://<dir>` for [`DirLfsStore`], http(s) url (template) for [`HttpLfsStore`]
+pub fn store_from_url(url: &str) -> anyhow::Result<Arc<dyn LfsStore>> {
+    if let Some(dir) = url.strip_prefix("file://") {
+        Ok(Arc::new(DirLfsStore::new(dir)))
+    } else if url.starts_with("https://") || url.starts_with("http://") {
+        Ok(Arc::new(HttpLfsStore::new(url)?))
+    } else {
+        anyhow::bail!(
+            "unsupported LFS store `{}`: expected `file://`, `https://` or `http://` url",
+            url
+        )
+    }
+}
+
+/// Objects under `<url>/<oid>`, or at `url` with [`OID_PLACEHOLDER`] replaced
+///
+/// e.g. a plain file server or an object bucket keyed by sha256
+#[derive(Debug)]
+pub struct HttpLfsStore {
+    client: reqwest::Client,
+    url_template: String,
+}
+
+impl HttpLfsStore {
+    pub fn new(url: impl Into<String>) -> anyhow::Result<Self> {
+        let mut url_template = url.into();
+        if !url_template.contains(OID_PLACEHOLDER) {
+            url_template = format!("{}/{}", url_template.trim_end_matches('/'), OID_PLACEHOLDER);
+        }
+        Ok(Self {
+            client: reqwest::Client::builder().build()?,
+            url_template,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl LfsStore for HttpLfsStore {
+    async fn fetch(&self, pointer: &LfsPointer) -> anyhow::Result<ByteStream> {
+        let url = self.url_template.replace(OID_PLACEHOLDER, &pointer.oid);
+        tracing::debug!("lfs: GET {}", url);
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("LFS object {}", pointer.digest()))?;
+        Ok(Box::pin(response.bytes_stream().map(|chunk| {
+            chunk.map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+        })))
+    }
+}
+
+/// Objects in `<dir>/<oid[0..2]>/<oid[2..4]>/<oid>`, the `.git/lfs/objects` layout
+#[derive(Debug)]
+pub struct DirLfsStore {
+    dir: PathBuf,
+}
+
+impl DirLfsStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn object_path(&self, pointer: &LfsPointer) -> PathBuf {
+        self.dir
+            .join(&pointer.oid[0..2])
+            .join(&pointer.oid[2..4])
+            .join(&pointer.oid)
+    }
+}
+
+#[async_trait::async_trait]
+impl LfsStore for DirLfsStore {
+    async fn fetch(&self, pointer: &LfsPointer) -> anyhow::Result<ByteStream> {
+        let path = self.object_path(pointer);
+        let file = tokio::fs::File::open(&path)
+            .await
+            .with_context(|| format!("LFS object {} at {:?}", pointer.digest(), path))?;
+        Ok(Box::pin(ReaderStream::with_capacity(
+            file,
+            stream::CHUNK_SIZE,
+        )))
+    }
+}
+
+/// Replace a pointer file `body` with its verified object
+pub async fn resolve_file(
+    mut body: ByteStream,
+    store: &dyn LfsStore,
+) -> anyhow::Result<(ByteStream, Option<LfsPointer>)> {
+    let mut head = Vec::new();
+    while (head.len() as u64) < MAX_POINTER_SIZE {
+        let Some(chunk) = body.next().await else {
+            // the whole file is small enough to be a pointer
+            let Some(pointer) = LfsPointer::parse(&head) else {
+                break;
+            };
+            tracing::debug!("lfs: resolve {}", pointer.digest());
+            let object = SyncIoBridge::new(StreamReader::new(store.fetch(&pointer).await?));
+            let mut object = pointer.verify(object);
+            let body = stream::from_blocking_writer(move |writer| {
+                io::copy(&mut object, writer)?;
+                Ok(())
+            });
+            return Ok((body, Some(pointer)));
+        };
+        head.extend_from_slice(&chunk?);
+    }
+    let head = tokio_stream::once(Ok(Bytes::from(head)));
+    Ok((Box::pin(head.chain(body)), None))
+}
+
+/// Replace pointer files of the tar `body` with their verified objects
+///
+/// every resolved pointer is pushed to `objects`
+pub fn resolve_archive(
+    body: ByteStream,
+    store: Arc<dyn LfsStore>,
+    objects: LfsObjects,
+) -> ByteStream {
+    let reader = SyncIoBridge::new(StreamReader::new(body));
+    stream::from_blocking_writer(move |writer| {
+        let runtime = Handle::current();
+        let mut builder = tar::Builder::new(writer);
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let mut header = entry.header().clone();
+            if header.entry_type() == tar::EntryType::XGlobalHeader {
+                builder.append(&header, &mut entry)?;
+                continue;
+            }
+
+            let path = entry
+                .path()?
+                .to_string_lossy()
+                .trim_end_matches('/')
+                .to_owned();
+            if !header.entry_type().is_file() || header.size()? >= MAX_POINTER_SIZE {
+                submodule::append_entry(&mut builder, &mut header, &path, &mut entry)?;
+                continue;
+            }
+
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            let Some(pointer) = LfsPointer::parse(&content) else {
+                builder.append_data(&mut header, &path, content.as_slice())?;
+                continue;
+            };
+            tracing::debug!("lfs: resolve {} at `{}`", pointer.digest(), path);
+            let object = runtime
+                .block_on(store.fetch(&pointer))
+                .with_context(|| format!("LFS file `{}`", path))?;
+            header.set_size(pointer.size);
+            builder
+                .append_data(
+                    &mut header,
+                    &path,
+                    pointer.verify(SyncIoBridge::new(StreamReader::new(object))),
+                )
+                .with_context(|| format!("LFS file `{}`", path))?;
+            objects.blocking_lock().push(pointer);
+        }
+        builder.finish()?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &[u8] = b"large binary";
+
+    fn pointer_of(content: &[u8]) -> LfsPointer {
+        let oid = Sha256::digest(content)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        LfsPointer {
+            oid,
+            size: content.len() as u64,
+        }
+    }
+
+    fn pointer_file(pointer: &LfsPointer) -> String {
+        format!(
+            "version {}\noid {}\nsize {}\n",
+            POINTER_VERSION,
+            pointer.digest(),
+            pointer.size
+        )
+    }
+
+    fn store_with(dir: &tempfile::TempDir, pointer: &LfsPointer, content: &[u8]) -> DirLfsStore {
+        let store = DirLfsStore::new(dir.path());
+        let path = store.object_path(pointer);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+        store
+    }
+
+    #[test]
+    fn parse_pointer_test() {
+        let pointer = pointer_of(CONTENT);
+        let file = pointer_file(&pointer);
+        assert_eq!(LfsPointer::parse(file.as_bytes()), Some(pointer.clone()));
+
+        let with_extension = file.replace("oid", "ext-0-foo sha256:00\noid");
+        assert_eq!(
+            LfsPointer::parse(with_extension.as_bytes()),
+            Some(pointer.clone())
+        );
+
+        assert_eq!(LfsPointer::parse(CONTENT), None);
+        let cases = [
+            file.replace("spec/v1", "spec/v2"),
+            file.replace("sha256:", "sha1:"),
+            file.replace(&pointer.oid, &pointer.oid.to_uppercase()),
+            file.replace("size 12", "size -1"),
+            file.replace("size 12\n", ""),
+        ];
+        for case in cases {
+            assert_eq!(LfsPointer::parse(case.as_bytes()), None, "{}", case);
+        }
+    }
+
+    #[test]
+    fn verify_test() {
+        let pointer = pointer_of(CONTENT);
+        let mut body = Vec::new();
+        pointer.verify(CONTENT).read_to_end(&mut body).unwrap();
+        assert_eq!(body, CONTENT);
+
+        for tampered in [&b"large binarY"[..], b"large", b"large binary!"] {
+            let error = pointer
+                .verify(tampered)
+                .read_to_end(&mut Vec::new())
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    fn tar(entries: &[(&str, &[u8])]) -> ByteStream {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o664);
+            header.set_size(content.len() as u64);
+            builder.append_data(&mut header, path, *content).unwrap();
+        }
+        let body = Bytes::from(builder.into_inner().unwrap());
+        Box::pin(tokio_stream::once(Ok(body)))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resolve_archive_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let pointer = pointer_of(CONTENT);
+        let store: Arc<dyn LfsStore> = Arc::new(store_with(&dir, &pointer, CONTENT));
+        let pointer_file = pointer_file(&pointer);
+        let body = tar(&[
+            ("README.md", b"readme"),
+            ("assets/model.bin", pointer_file.as_bytes()),
+        ]);
+
+        let objects = LfsObjects::default();
+        let body = resolve_archive(body, store, objects.clone());
+        let body = stream::read_to_end(body).await.unwrap();
+
+        let mut archive = tar::Archive::new(body.as_slice());
+        let files = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).unwrap();
+                (
+                    entry.path().unwrap().to_string_lossy().into_owned(),
+                    content,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec![
+                ("README.md".to_owned(), b"readme".to_vec()),
+                ("assets/model.bin".to_owned(), CONTENT.to_vec()),
+            ]
+        );
+        assert_eq!(*objects.lock().await, vec![pointer]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resolve_tampered_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let pointer = pointer_of(CONTENT);
+        let store: Arc<dyn LfsStore> = Arc::new(store_with(&dir, &pointer, b"tampered"));
+        let pointer_file = pointer_file(&pointer);
+
+        let body = tar(&[("model.bin", pointer_file.as_bytes())]);
+        let objects = LfsObjects::default();
+        let body = resolve_archive(body, store.clone(), objects.clone());
+        assert!(stream::read_to_end(body).await.is_err());
+        assert!(objects.lock().await.is_empty());
+
+        let body = Box::pin(tokio_stream::once(Ok(Bytes::from(pointer_file))));
+        let (body, resolved) = resolve_file(body, store.as_ref()).await.unwrap();
+        assert_eq!(resolved, Some(pointer));
+        assert!(stream::read_to_end(body).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resolve_file_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let pointer = pointer_of(CONTENT);
+        let store = store_with(&dir, &pointer, CONTENT);
+
+        let body = Box::pin(tokio_stream::once(Ok(Bytes::from(pointer_file(&pointer)))));
+        let (body, resolved) = resolve_file(body, &store).await.unwrap();
+        assert_eq!(resolved, Some(pointer));
+        assert_eq!(stream::read_to_end(body).await.unwrap(), CONTENT);
+
+        let large = vec![b'x'; MAX_POINTER_SIZE as usize * 3];
+        let chunks = large
+            .chunks(100)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        let body: ByteStream = Box::pin(tokio_stream::iter(chunks));
+        let (body, resolved) = resolve_file(body, &store).await.unwrap();
+        assert_eq!(resolved, None);
+        assert_eq!(stream::read_to_end(body).await.unwrap(), large);
+    }
+}
@@ -1,3 +1,22 @@
+pub mod archive_filter;
+pub mod backend;
+pub mod bundle;
 pub mod cache;
+pub mod cargo;
+pub mod clone_policy;
+pub mod download;
+pub mod dumb_path;
+pub mod error;
 pub mod git_context;
+pub mod git_url;
+pub mod gosh_url;
+pub mod lfs;
+pub mod refs;
 pub mod registry;
+pub mod sandbox;
+pub mod submodule;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod upload_pack;
+pub mod upstream;
+pub mod url_rewrite;
@@ -0,0 +1,95 @@
+use std::{collections::BTreeMap, fmt};
+
+const HEADS: &str = "refs/heads/";
+const REMOTE_HEADS: &str = "refs/remotes/origin/";
+const TAGS: &str = "refs/tags/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RefKind {
+    Branch,
+    Tag,
+}
+
+impl fmt::Display for RefKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefKind::Branch => write!(f, "branch"),
+            RefKind::Tag => write!(f, "tag"),
+        }
+    }
+}
+
+/// Branch or tag with the commit it points to (annotated tags are peeled)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GitRef {
+    pub kind: RefKind,
+    /// short name, e.g. `main` or `v1.0`
+    pub name: String,
+    pub commit: String,
+}
+
+/// Branches and tags out of `(full ref name, commit)` pairs sorted by kind and name
+///
+/// Cached clones keep upstream branches as `origin/*`, local branches take
+/// precedence over them like they do in `git rev-parse`
+pub fn collect_refs(refs: impl IntoIterator<Item = (String, String)>) -> Vec<GitRef> {
+    let mut collected = BTreeMap::new();
+    for (full_name, commit) in refs {
+        let (kind, name, is_local) = if let Some(name) = full_name.strip_prefix(HEADS) {
+            (RefKind::Branch, name, true)
+        } else if let Some(name) = full_name.strip_prefix(REMOTE_HEADS) {
+            (RefKind::Branch, name, false)
+        } else if let Some(name) = full_name.strip_prefix(TAGS) {
+            (RefKind::Tag, name, true)
+        } else {
+            continue;
+        };
+        if name == "HEAD" {
+            // symbolic `origin/HEAD`
+            continue;
+        }
+        let key = (kind, name.to_owned());
+        if is_local || !collected.contains_key(&key) {
+            collected.insert(key, commit);
+        }
+    }
+    collected
+        .into_iter()
+        .map(|((kind, name), commit)| GitRef { kind, name, commit })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_refs_test() {
+        let raw = [
+            ("refs/remotes/origin/main", "b"),
+            ("refs/remotes/origin/HEAD", "b"),
+            ("refs/remotes/origin/feature/x", "c"),
+            ("refs/heads/main", "a"),
+            ("refs/tags/v1", "d"),
+            ("refs/notes/commits", "e"),
+            ("refs/remotes/upstream/main", "f"),
+        ];
+        let refs = collect_refs(
+            raw.iter()
+                .map(|(name, commit)| (name.to_string(), commit.to_string())),
+        );
+        let git_ref = |kind, name: &str, commit: &str| GitRef {
+            kind,
+            name: name.to_owned(),
+            commit: commit.to_owned(),
+        };
+        assert_eq!(
+            refs,
+            vec![
+                git_ref(RefKind::Branch, "feature/x", "c"),
+                git_ref(RefKind::Branch, "main", "a"),
+                git_ref(RefKind::Tag, "v1", "d"),
+            ]
+        );
+    }
+}
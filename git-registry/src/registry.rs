@@ -1,85 +1,449 @@
-use crate::cache::GitCacheRepo;
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use crate::{
+    archive_filter::ArchiveFilter,
+    backend::{default_backend, GitBackend},
+    cache::{self, BundleRefs, GitCacheRepo},
+    clone_policy::ClonePolicies,
+    dumb_path,
+    error::GitRegistryError,
+    git_url::{AllowedHosts, GitUrl},
+    lfs::{self, LfsObjects, LfsPointer, LfsStore},
+    refs::GitRef,
+    sandbox,
+    submodule::{self, ArchivedSubmodule},
+    upload_pack,
+    upstream::UpstreamCache,
+    url_rewrite::UrlRewrites,
+};
+use anyhow::Context;
+use bytes::Bytes;
+use gosh_utils::{
+    metrics::Metrics,
+    stream::{self, ByteStream},
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::Read,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::Instant,
+};
 use tokio::sync::Mutex;
+use tokio_stream::StreamExt;
+use tokio_util::io::{StreamReader, SyncIoBridge};
 
-#[derive(Debug, Default)]
+/// submodules of submodules of ... are followed up to this depth
+const MAX_SUBMODULE_DEPTH: usize = 8;
+
+const CACHE_LOOKUPS: &str = "gosh_git_cache_lookups_total";
+const CACHE_LOOKUPS_HELP: &str = "Repo lookups by whether the repo was cloned already.";
+const UPDATE_DURATION: &str = "gosh_git_update_duration_seconds";
+const UPDATE_DURATION_HELP: &str = "Clones and fetches of cached repos from their remotes.";
+
+/// Result of [`GitCacheRegistry::git_archive`]
+pub struct GitArchive {
+    /// zstd compressed tar
+    pub body: ByteStream,
+    /// every spliced submodule, nested ones included
+    pub submodules: Vec<ArchivedSubmodule>,
+    /// LFS objects which replaced pointer files, complete once `body` ends
+    pub lfs_objects: LfsObjects,
+}
+
+/// Result of [`GitCacheRegistry::git_show`]
+pub struct GitFile {
+    /// zstd compressed content
+    pub body: ByteStream,
+    /// set if the file is an LFS pointer and `body` is the object
+    pub lfs_object: Option<LfsPointer>,
+}
+
+/// Result of [`GitCacheRegistry::upload_pack`]
+pub struct UploadPack {
+    /// uncompressed `git upload-pack` output
+    pub body: ByteStream,
+    /// commits the client asked for, tags are peeled and other objects (e.g.
+    /// blobs of partial clones) are left out
+    pub commits: Vec<String>,
+}
+
+#[derive(Debug)]
 pub struct GitCacheRegistry {
-    inner: Mutex<HashMap<String, Arc<Mutex<GitCacheRepo>>>>,
+    inner: Mutex<HashMap<GitUrl, Arc<Mutex<GitCacheRepo>>>>,
+    backend: Arc<dyn GitBackend>,
+    cache_dir: PathBuf,
+    allowed_hosts: AllowedHosts,
+    url_rewrites: Arc<UrlRewrites>,
+    lfs_store: Option<Arc<dyn LfsStore>>,
+    upstream: Option<Arc<UpstreamCache>>,
+    clone_policies: ClonePolicies,
+    offline: bool,
+    metrics: Arc<Metrics>,
+}
+
+impl Default for GitCacheRegistry {
+    fn default() -> Self {
+        Self::with_backend(default_backend())
+    }
 }
 
 // TODO: make archivation optional
 
 impl GitCacheRegistry {
-    pub async fn update_server_info(&self, url: impl AsRef<str>) -> anyhow::Result<()> {
-        tracing::debug!("update_server_info: {}", url.as_ref());
+    pub fn with_backend(backend: Arc<dyn GitBackend>) -> Self {
+        Self {
+            inner: Mutex::default(),
+            backend,
+            cache_dir: cache::default_cache_dir(),
+            allowed_hosts: AllowedHosts::default(),
+            url_rewrites: Arc::default(),
+            lfs_store: None,
+            upstream: None,
+            clone_policies: ClonePolicies::default(),
+            offline: false,
+            metrics: Arc::default(),
+        }
+    }
+
+    /// keep clones in `cache_dir` instead of [`cache::default_cache_dir`]
+    pub fn with_cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = cache_dir.into();
+        self
+    }
+
+    /// permit non-gosh remotes from `allowed_hosts`
+    pub fn with_allowed_hosts(mut self, allowed_hosts: AllowedHosts) -> Self {
+        self.allowed_hosts = allowed_hosts;
+        self
+    }
+
+    /// fetch repos from mirrors first
+    pub fn with_url_rewrites(mut self, url_rewrites: UrlRewrites) -> Self {
+        self.url_rewrites = Arc::new(url_rewrites);
+        self
+    }
+
+    /// resolve LFS pointer files from `lfs_store`, without it pointers are served as is
+    pub fn with_lfs_store(mut self, lfs_store: Arc<dyn LfsStore>) -> Self {
+        self.lfs_store = Some(lfs_store);
+        self
+    }
+
+    /// fetch repos from a shared `gosh-git-server` before their remotes
+    pub fn with_upstream(mut self, upstream: UpstreamCache) -> Self {
+        self.upstream = Some(Arc::new(upstream));
+        self
+    }
+
+    /// clone matching repos shallow or without blobs, the rest are cloned in full
+    pub fn with_clone_policies(mut self, clone_policies: ClonePolicies) -> Self {
+        self.clone_policies = clone_policies;
+        self
+    }
+
+    /// serve cached repos as they are and never touch remotes
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// record cache metrics into shared `metrics`, e.g. the server's ones
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// the cache dir is usable and `git` runs
+    pub async fn check_ready(&self) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.cache_dir)
+            .await
+            .with_context(|| format!("cache dir {:?}", self.cache_dir))?;
+        let output = sandbox::git().arg("--version").output().await?;
+        if !output.status.success() {
+            anyhow::bail!("git --version: {}", output.status);
+        }
+        Ok(())
+    }
+
+    /// gosh repos and remotes from the allowed hosts
+    pub fn is_allowed(&self, url: &GitUrl) -> bool {
+        self.allowed_hosts.permits(url)
+    }
+
+    /// regenerate the dumb protocol files of the cached repo, the cache does it
+    /// itself whenever the repo changes
+    pub async fn update_server_info(&self, url: &GitUrl) -> anyhow::Result<()> {
+        tracing::debug!("update_server_info: {}", url);
         let repo = self.get_or_create_repository(url).await?;
 
         let repo_lock = repo.lock().await;
         repo_lock.update_server_info().await
     }
 
-    pub async fn dumb(
-        &self,
-        url: impl AsRef<str>,
-        src: impl AsRef<str>,
-    ) -> anyhow::Result<PathBuf> {
-        tracing::debug!("dumb: {} {}", url.as_ref(), src.as_ref());
+    /// file of the dumb protocol, read without the repo lock so concurrent
+    /// object fetches don't queue behind each other or behind a fetch
+    ///
+    /// repos cloned by older versions have no server info yet, it's generated
+    /// under the lock once
+    pub async fn dumb(&self, url: &GitUrl, src: impl AsRef<str>) -> anyhow::Result<PathBuf> {
+        tracing::debug!("dumb: {} {}", url, src.as_ref());
         let repo = self.get_or_create_repository(url).await?;
 
-        let repo_lock = repo.lock().await;
-        repo_lock.dumb(src).await
+        let git_dir = GitCacheRepo::dir_of(&self.cache_dir, url);
+        if !GitCacheRepo::has_server_info(&git_dir) {
+            let repo_lock = repo.lock().await;
+            // the first clone may have been in flight
+            if !GitCacheRepo::has_server_info(&git_dir) {
+                repo_lock.update_server_info().await?;
+            }
+            return repo_lock.dumb(src).await;
+        }
+        dumb_path::resolve(&git_dir.join(".git"), src.as_ref()).await
     }
 
+    /// zstd compressed tar of the tree at `commit` (only paths matching `filter`)
+    /// with submodules spliced in at their pinned commits and LFS files resolved
     pub async fn git_archive(
         &self,
-        url: impl AsRef<str>,
+        url: &GitUrl,
         commit: impl AsRef<str>,
-    ) -> anyhow::Result<Vec<u8>> {
+        filter: &ArchiveFilter,
+    ) -> anyhow::Result<GitArchive> {
         tracing::debug!(
-            "git_archive: url={:?}, commit={:?}",
-            url.as_ref(),
-            commit.as_ref()
+            "git_archive: url={}, commit={:?}, filter={:?}",
+            url,
+            commit.as_ref(),
+            filter.to_string()
         );
-        let repo = self.get_or_create_repository(url).await?;
+        let mut submodules = Vec::new();
+        let body = self
+            .archive_tree(url, commit.as_ref(), filter, 0, &mut submodules)
+            .await?;
+        let lfs_objects = LfsObjects::default();
+        let body = match self.lfs_store {
+            Some(ref lfs_store) => {
+                lfs::resolve_archive(body, lfs_store.clone(), lfs_objects.clone())
+            }
+            None => body,
+        };
+        Ok(GitArchive {
+            body: stream::zstd_encode(body),
+            submodules,
+            lfs_objects,
+        })
+    }
 
-        let repo_lock = repo.lock().await;
-        // let commit = repo_lock.try_normalize_ref(commit).await?;
-        repo_lock.git_archive(commit).await
+    /// submodules [`GitCacheRegistry::git_archive`] splices in, without
+    /// archiving anything
+    pub async fn archived_submodules(
+        &self,
+        url: &GitUrl,
+        commit: impl AsRef<str>,
+        filter: &ArchiveFilter,
+    ) -> anyhow::Result<Vec<ArchivedSubmodule>> {
+        let mut archived = Vec::new();
+        self.submodule_tree(url, commit.as_ref(), filter, 0, &mut archived)
+            .await?;
+        Ok(archived)
+    }
+
+    /// [`GitCacheRegistry::archived_submodules`], recursive for nested submodules
+    fn submodule_tree<'a>(
+        &'a self,
+        url: &'a GitUrl,
+        commit: &'a str,
+        filter: &'a ArchiveFilter,
+        depth: usize,
+        archived: &'a mut Vec<ArchivedSubmodule>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            let repo = self.repository_with(url, commit).await?;
+            let mounted = repo.lock().await.submodules(commit, filter).await?;
+            if !mounted.is_empty() && depth >= MAX_SUBMODULE_DEPTH {
+                anyhow::bail!(
+                    "submodules of {} are nested deeper than {}",
+                    url,
+                    MAX_SUBMODULE_DEPTH
+                );
+            }
+            for submodule in mounted {
+                let Some(submodule_filter) = filter.under(&submodule.path) else {
+                    continue;
+                };
+                let nested = self
+                    .submodule_tree(
+                        &submodule.url,
+                        &submodule.commit,
+                        &submodule_filter,
+                        depth + 1,
+                        archived,
+                    )
+                    .await;
+                match nested {
+                    Ok(()) => {}
+                    Err(error) if is_skipped_submodule(&error) => {
+                        tracing::warn!(
+                            "submodule `{}` of {} is left empty: {:#}",
+                            submodule.path,
+                            url,
+                            error
+                        );
+                        continue;
+                    }
+                    Err(error) => {
+                        return Err(
+                            error.context(format!("submodule `{}` of {}", submodule.path, url))
+                        )
+                    }
+                }
+                archived.push(ArchivedSubmodule {
+                    parent_url: url.clone(),
+                    parent_commit: commit.to_owned(),
+                    submodule,
+                });
+            }
+            Ok(())
+        })
+    }
+
+    /// uncompressed [`GitCacheRegistry::git_archive`], recursive for nested submodules
+    fn archive_tree<'a>(
+        &'a self,
+        url: &'a GitUrl,
+        commit: &'a str,
+        filter: &'a ArchiveFilter,
+        depth: usize,
+        archived: &'a mut Vec<ArchivedSubmodule>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<ByteStream>> + Send + 'a>> {
+        Box::pin(async move {
+            let repo = self.repository_with(url, commit).await?;
+            let (parent, mounted) = {
+                let repo_lock = repo.lock().await;
+                let mounted = repo_lock
+                    .submodules(commit, filter)
+                    .await?
+                    .into_iter()
+                    .filter_map(|submodule| Some((filter.under(&submodule.path)?, submodule)))
+                    .collect::<Vec<_>>();
+                let mount_points = mounted
+                    .iter()
+                    .map(|(_, submodule)| submodule.path.as_str())
+                    .collect::<Vec<_>>();
+                let parent_filter = filter.outside(&mount_points);
+                if !self.offline {
+                    repo_lock.fetch_blobs(commit, &parent_filter).await?;
+                }
+                let parent = repo_lock.git_archive(commit, &parent_filter).await?;
+                (parent, mounted)
+            };
+
+            if mounted.is_empty() {
+                return Ok(parent);
+            }
+            if depth >= MAX_SUBMODULE_DEPTH {
+                anyhow::bail!(
+                    "submodules of {} are nested deeper than {}",
+                    url,
+                    MAX_SUBMODULE_DEPTH
+                );
+            }
+
+            let mut bodies: Vec<(String, Box<dyn Read + Send>)> = Vec::new();
+            for (submodule_filter, submodule) in mounted {
+                let archive = self
+                    .archive_tree(
+                        &submodule.url,
+                        &submodule.commit,
+                        &submodule_filter,
+                        depth + 1,
+                        archived,
+                    )
+                    .await;
+                let body = match archive {
+                    Ok(body) => body,
+                    Err(error) if is_skipped_submodule(&error) => {
+                        // the gitlink stays an empty dir, as `git archive` leaves it
+                        tracing::warn!(
+                            "submodule `{}` of {} is left empty: {:#}",
+                            submodule.path,
+                            url,
+                            error
+                        );
+                        continue;
+                    }
+                    Err(error) => {
+                        return Err(
+                            error.context(format!("submodule `{}` of {}", submodule.path, url))
+                        )
+                    }
+                };
+                bodies.push((
+                    submodule.path.clone(),
+                    Box::new(SyncIoBridge::new(StreamReader::new(body))),
+                ));
+                archived.push(ArchivedSubmodule {
+                    parent_url: url.clone(),
+                    parent_commit: commit.to_owned(),
+                    submodule,
+                });
+            }
+
+            let parent = SyncIoBridge::new(StreamReader::new(parent));
+            let filter = filter.clone();
+            Ok(stream::from_blocking_writer(move |writer| {
+                submodule::splice(parent, bodies, &filter, writer)
+            }))
+        })
     }
 
+    /// zstd compressed content of `file_path` at `commit`, LFS pointer is resolved
     pub async fn git_show(
         &self,
-        url: impl AsRef<str>,
+        url: &GitUrl,
         commit: impl AsRef<str>,
         file_path: impl AsRef<str>,
-    ) -> anyhow::Result<Vec<u8>> {
+    ) -> anyhow::Result<GitFile> {
         tracing::debug!(
-            "git_show: url={:?} commit={:?} file_path={:?}",
-            url.as_ref(),
+            "git_show: url={} commit={:?} file_path={:?}",
+            url,
             commit.as_ref(),
             file_path.as_ref()
         );
-        self.get_or_create_repository(url)
+        let body = self
+            .repository_with(url, commit.as_ref())
             .await?
             .lock()
             .await
             .git_show(commit, file_path)
-            .await
+            .await?;
+        let (body, lfs_object) = match self.lfs_store {
+            Some(ref lfs_store) => lfs::resolve_file(body, lfs_store.as_ref()).await?,
+            None => (body, None),
+        };
+        Ok(GitFile {
+            body: stream::zstd_encode(body),
+            lfs_object,
+        })
     }
 
     pub async fn git_show_uncompressed(
         &self,
-        url: impl AsRef<str>,
+        url: &GitUrl,
         commit: impl AsRef<str>,
         file_path: impl AsRef<str>,
     ) -> anyhow::Result<Vec<u8>> {
         tracing::debug!(
-            "git_show_uncompressed: url={:?} commit={:?} file_path={:?}",
-            url.as_ref(),
+            "git_show_uncompressed: url={} commit={:?} file_path={:?}",
+            url,
             commit.as_ref(),
             file_path.as_ref()
         );
-        self.get_or_create_repository(url)
+        self.repository_with(url, commit.as_ref())
             .await?
             .lock()
             .await
@@ -87,17 +451,47 @@ impl GitCacheRegistry {
             .await
     }
 
+    fn new_repository(&self, url: &GitUrl) -> GitCacheRepo {
+        GitCacheRepo::from(
+            url.clone(),
+            &self.cache_dir,
+            self.backend.clone(),
+            self.url_rewrites.clone(),
+            self.upstream.clone(),
+            self.clone_policies.policy(url),
+        )
+    }
+
+    /// cached repo with `rev` in it, commits cut off from shallow clones are fetched
+    async fn repository_with(
+        &self,
+        url: &GitUrl,
+        rev: &str,
+    ) -> anyhow::Result<Arc<Mutex<GitCacheRepo>>> {
+        let repo = self.get_or_create_repository(url).await?;
+        if !self.offline {
+            repo.lock().await.fetch_missing(rev).await?;
+        }
+        Ok(repo)
+    }
+
     async fn get_or_create_repository(
         &self,
-        url: impl AsRef<str>,
+        url: &GitUrl,
     ) -> anyhow::Result<Arc<Mutex<GitCacheRepo>>> {
+        if !self.is_allowed(url) {
+            return Err(GitRegistryError::NotAllowed { url: url.clone() }.into());
+        }
+
         let mut registry_guard = self.inner.lock().await;
 
-        if let Some(git_repo) = registry_guard.get(url.as_ref()) {
+        if let Some(git_repo) = registry_guard.get(url) {
+            self.metrics
+                .inc(CACHE_LOOKUPS, CACHE_LOOKUPS_HELP, &[("result", "hit")]);
             Ok(git_repo.clone())
         } else {
-            let git_repo = Arc::new(Mutex::new(GitCacheRepo::from(url.as_ref().to_owned())));
-            registry_guard.insert(url.as_ref().to_owned(), git_repo.clone());
+            let git_repo = Arc::new(Mutex::new(self.new_repository(url)));
+            registry_guard.insert(url.clone(), git_repo.clone());
 
             let git_repo_guard = git_repo.lock().await;
 
@@ -105,18 +499,146 @@ impl GitCacheRegistry {
             // but since git_repo_update can take long time we don't want to block whole registry
             drop(registry_guard);
 
-            git_repo_guard.update().await?;
+            let cloned = git_repo_guard.is_cloned();
+            let result = if cloned { "hit" } else { "miss" };
+            self.metrics
+                .inc(CACHE_LOOKUPS, CACHE_LOOKUPS_HELP, &[("result", result)]);
+
+            let updated = if !self.offline {
+                let started = Instant::now();
+                let updated = git_repo_guard.update().await;
+                let operation = if cloned { "fetch" } else { "clone" };
+                let result = if updated.is_ok() { "ok" } else { "error" };
+                self.metrics.observe(
+                    UPDATE_DURATION,
+                    UPDATE_DURATION_HELP,
+                    &[("operation", operation), ("result", result)],
+                    started.elapsed(),
+                );
+                updated
+            } else if !git_repo_guard.is_cloned() {
+                Err(GitRegistryError::Offline { url: url.clone() }.into())
+            } else {
+                git_repo_guard.verify_origin().await
+            };
+            if let Err(error) = updated {
+                // next request retries instead of getting the broken clone
+                self.inner.lock().await.remove(url);
+                return Err(error);
+            }
 
             Ok(git_repo.clone())
         }
     }
 
+    /// git bundle of `commits` (every ref if `None`) of the repo at `path`
+    pub async fn export_bundle(
+        &self,
+        url: &GitUrl,
+        commits: Option<&[String]>,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        tracing::debug!("export_bundle: url={} commits={:?}", url, commits);
+        self.get_or_create_repository(url)
+            .await?
+            .lock()
+            .await
+            .bundle(commits, path)
+            .await
+    }
+
+    /// put objects of a bundle made by [`GitCacheRegistry::export_bundle`] into
+    /// the cache of `url`, remote itself isn't touched
+    pub async fn import_bundle(
+        &self,
+        url: &GitUrl,
+        path: &Path,
+        commits: &[String],
+    ) -> anyhow::Result<()> {
+        tracing::debug!("import_bundle: url={} path={:?}", url, path);
+        let registered = self.inner.lock().await.get(url).cloned();
+        let git_repo = registered.unwrap_or_else(|| Arc::new(Mutex::new(self.new_repository(url))));
+        let git_repo_guard = git_repo.lock().await;
+        git_repo_guard
+            .unbundle(path, commits, BundleRefs::All)
+            .await
+    }
+
+    /// put the pinned commits of a bundle uploaded by a
+    /// [`GitCacheRegistry::with_upstream`] client into the cache of `url`
+    ///
+    /// anyone with the token could have made the bundle, so its branches and
+    /// tags are ignored and only pins in the history of the remote are taken:
+    /// the repo is cloned from the remote first if needed
+    pub async fn import_upload(&self, url: &GitUrl, path: &Path) -> anyhow::Result<()> {
+        tracing::debug!("import_upload: url={} path={:?}", url, path);
+        self.get_or_create_repository(url)
+            .await?
+            .lock()
+            .await
+            .unbundle(path, &[], BundleRefs::Pinned)
+            .await
+    }
+
+    /// smart http `git-upload-pack` service of the cached repo, the ref
+    /// advertisement if `advertise` and the response to `request` otherwise
+    ///
+    /// `git_protocol` is the `Git-Protocol` header which selects protocol v2
+    pub async fn upload_pack(
+        &self,
+        url: &GitUrl,
+        advertise: bool,
+        git_protocol: Option<&str>,
+        request: Bytes,
+    ) -> anyhow::Result<UploadPack> {
+        tracing::debug!(
+            "upload_pack: url={} advertise={} git_protocol={:?}",
+            url,
+            advertise,
+            git_protocol
+        );
+        let v2 = upload_pack::is_v2(git_protocol);
+        let wants = if advertise {
+            Vec::new()
+        } else {
+            upload_pack::wants(&request)?
+        };
+
+        let repo = self.get_or_create_repository(url).await?;
+        let repo_lock = repo.lock().await;
+        let mut commits = Vec::new();
+        for want in wants {
+            match repo_lock.commit_of(&want).await {
+                Some(commit) if !commits.contains(&commit) => commits.push(commit),
+                Some(_) => {}
+                None => tracing::debug!("upload_pack: {} isn't a commit", want),
+            }
+        }
+        let mut body = repo_lock.upload_pack(advertise, v2, request)?;
+        if advertise && !v2 {
+            let header = Bytes::from(upload_pack::service_header());
+            body = Box::pin(tokio_stream::once(Ok(header)).chain(body));
+        }
+        Ok(UploadPack { body, commits })
+    }
+
+    /// branches and tags of the cached repo with their commits
+    pub async fn list_refs(&self, url: &GitUrl) -> anyhow::Result<Vec<GitRef>> {
+        tracing::debug!("list_refs: url={}", url);
+        self.get_or_create_repository(url)
+            .await?
+            .lock()
+            .await
+            .list_refs()
+            .await
+    }
+
     pub async fn normalized_commit(
         &self,
-        url: impl AsRef<str>,
+        url: &GitUrl,
         raw_commit: impl AsRef<str>,
     ) -> anyhow::Result<String> {
-        self.get_or_create_repository(url)
+        self.repository_with(url, raw_commit.as_ref())
             .await?
             .lock()
             .await
@@ -124,3 +646,233 @@ impl GitCacheRegistry {
             .await
     }
 }
+
+/// submodules from hosts which aren't allowed or can't be reached now don't
+/// fail the archive of their parent
+fn is_skipped_submodule(error: &anyhow::Error) -> bool {
+    match GitRegistryError::of(error) {
+        Some(GitRegistryError::NotAllowed { .. }) => true,
+        Some(error) => error.is_unavailable(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        git_url::FILE_HOST,
+        test_util::{commit_file, git, init},
+    };
+
+    #[tokio::test]
+    async fn dumb_test() {
+        let upstream = tempfile::tempdir().unwrap();
+        init(upstream.path());
+        let first = commit_file(upstream.path(), "README.md", "first\n", "first");
+
+        let url: GitUrl = format!("file://{}", upstream.path().display())
+            .parse()
+            .unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let registry = GitCacheRegistry::default()
+            .with_cache_dir(cache.path())
+            .with_allowed_hosts(AllowedHosts::new([FILE_HOST]));
+
+        // generated by the clone
+        let info_refs = registry.dumb(&url, "info/refs").await.unwrap();
+        let refs = std::fs::read_to_string(&info_refs).unwrap();
+        assert!(
+            refs.contains(&format!("{}\trefs/heads/main", first)),
+            "{}",
+            refs
+        );
+
+        // a cache of an older version
+        std::fs::remove_file(&info_refs).unwrap();
+        let path = registry.dumb(&url, "info/refs").await.unwrap();
+        assert_eq!(path, info_refs);
+        assert!(info_refs.exists());
+
+        // refs which change get into `info/refs`
+        let second = commit_file(upstream.path(), "README.md", "second\n", "second");
+        let pinned = format!("refs/gosh/pinned/{}", second);
+        git(upstream.path(), &["update-ref", &pinned, &second]);
+        let bundle = cache.path().join("second.bundle");
+        git(
+            upstream.path(),
+            &["bundle", "create", bundle.to_str().unwrap(), &pinned],
+        );
+        registry
+            .import_bundle(&url, &bundle, std::slice::from_ref(&second))
+            .await
+            .unwrap();
+        let refs =
+            std::fs::read_to_string(registry.dumb(&url, "info/refs").await.unwrap()).unwrap();
+        assert!(
+            refs.contains(&format!("{}\t{}", second, pinned)),
+            "{}",
+            refs
+        );
+    }
+
+    #[tokio::test]
+    async fn skipped_submodule_test() {
+        let parent = tempfile::tempdir().unwrap();
+        init(parent.path());
+        std::fs::write(parent.path().join("README.md"), "first\n").unwrap();
+        std::fs::write(
+            parent.path().join(".gitmodules"),
+            "[submodule \"lib\"]\n\tpath = vendor/lib\n\turl = https://github.com/gosh/lib.git\n",
+        )
+        .unwrap();
+        git(parent.path(), &["add", "."]);
+        git(
+            parent.path(),
+            &[
+                "update-index",
+                "--add",
+                "--cacheinfo",
+                "160000,1111111111111111111111111111111111111111,vendor/lib",
+            ],
+        );
+        git(parent.path(), &["commit", "--quiet", "-m", "first"]);
+        let commit = git(parent.path(), &["rev-parse", "HEAD"]);
+
+        // github.com isn't allowed
+        let url: GitUrl = format!("file://{}", parent.path().display())
+            .parse()
+            .unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let registry = GitCacheRegistry::default()
+            .with_cache_dir(cache.path())
+            .with_allowed_hosts(AllowedHosts::new([FILE_HOST]));
+        let archive = registry
+            .git_archive(&url, &commit, &ArchiveFilter::default())
+            .await
+            .unwrap();
+        assert!(archive.submodules.is_empty());
+        let body = stream::read_to_end(stream::zstd_decode(archive.body))
+            .await
+            .unwrap();
+        let mut paths = tar::Archive::new(body.as_slice())
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [".gitmodules", "README.md", "vendor/", "vendor/lib/"]
+        );
+    }
+
+    #[tokio::test]
+    async fn unlisted_submodule_test() {
+        let parent = tempfile::tempdir().unwrap();
+        init(parent.path());
+        std::fs::write(parent.path().join("README.md"), "first\n").unwrap();
+        git(parent.path(), &["add", "."]);
+        // no `.gitmodules` at all
+        git(
+            parent.path(),
+            &[
+                "update-index",
+                "--add",
+                "--cacheinfo",
+                "160000,1111111111111111111111111111111111111111,vendor/lib",
+            ],
+        );
+        git(parent.path(), &["commit", "--quiet", "-m", "first"]);
+        let commit = git(parent.path(), &["rev-parse", "HEAD"]);
+
+        let url: GitUrl = format!("file://{}", parent.path().display())
+            .parse()
+            .unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let registry = GitCacheRegistry::default()
+            .with_cache_dir(cache.path())
+            .with_allowed_hosts(AllowedHosts::new([FILE_HOST]));
+        for (paths, expected) in [
+            (&[][..], &["README.md", "vendor", "vendor/lib"][..]),
+            (&["README.md"][..], &["README.md"][..]),
+        ] {
+            let filter = ArchiveFilter::new(paths, [""; 0]).unwrap();
+            let archive = registry.git_archive(&url, &commit, &filter).await.unwrap();
+            assert!(archive.submodules.is_empty());
+            let body = stream::read_to_end(stream::zstd_decode(archive.body))
+                .await
+                .unwrap();
+            let mut paths = tar::Archive::new(body.as_slice())
+                .entries()
+                .unwrap()
+                .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+                .collect::<Vec<_>>();
+            paths.sort();
+            assert_eq!(paths, expected, "{}", filter);
+        }
+    }
+
+    #[tokio::test]
+    async fn import_upload_test() {
+        let upstream = tempfile::tempdir().unwrap();
+        init(upstream.path());
+        let first = commit_file(upstream.path(), "README.md", "first\n", "first");
+
+        // someone else's bundle which tries to move `main`, to pin a commit
+        // the remote doesn't have and to hide it behind the pin of another one
+        let forged = tempfile::tempdir().unwrap();
+        git(
+            forged.path(),
+            &["clone", "--quiet", upstream.path().to_str().unwrap(), "."],
+        );
+        let verified = format!("refs/gosh/pinned/{}", first);
+        git(forged.path(), &["update-ref", &verified, &first]);
+        let forged_commit = commit_file(forged.path(), "README.md", "forged\n", "forged");
+        let pinned = format!("refs/gosh/pinned/{}", forged_commit);
+        let misnamed = format!("refs/gosh/pinned/{}", "0".repeat(40));
+        git(forged.path(), &["update-ref", &pinned, &forged_commit]);
+        git(forged.path(), &["update-ref", &misnamed, &forged_commit]);
+        let bundle = forged.path().join("upload.bundle");
+        git(
+            forged.path(),
+            &[
+                "bundle",
+                "create",
+                bundle.to_str().unwrap(),
+                "refs/heads/main",
+                &verified,
+                &pinned,
+                &misnamed,
+            ],
+        );
+
+        let url: GitUrl = format!("file://{}", upstream.path().display())
+            .parse()
+            .unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let registry = GitCacheRegistry::default()
+            .with_cache_dir(cache.path())
+            .with_allowed_hosts(AllowedHosts::new([FILE_HOST]));
+        registry.import_upload(&url, &bundle).await.unwrap();
+
+        let refs = registry.list_refs(&url).await.unwrap();
+        assert_eq!(refs.len(), 1);
+        assert_eq!(
+            (refs[0].name.as_str(), refs[0].commit.as_str()),
+            ("main", first.as_str())
+        );
+        let git_dir = GitCacheRepo::dir_of(cache.path(), &url);
+        let pins = git(
+            &git_dir,
+            &["for-each-ref", "--format=%(refname)", "refs/gosh"],
+        );
+        assert_eq!(pins, verified);
+        let forged_object = std::process::Command::new("git")
+            .args(["cat-file", "-e", &forged_commit])
+            .current_dir(&git_dir)
+            .status()
+            .unwrap();
+        assert!(!forged_object.success());
+    }
+}
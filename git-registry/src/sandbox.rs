@@ -0,0 +1,182 @@
+use tokio::process::Command;
+
+/// transports `git` may use, remote helpers included: `gosh` for gosh repos and
+/// `http` for the upstream cache, `ext::` and friends are refused
+pub const ALLOWED_PROTOCOLS: &str = "gosh:https:http:ssh:file";
+
+/// env vars which point `git` to other repos, inject config or run programs,
+/// `GIT_SSH*` would win over `core.sshCommand`
+const REDIRECT_ENV: &[&str] = &[
+    "GIT_DIR",
+    "GIT_WORK_TREE",
+    "GIT_COMMON_DIR",
+    "GIT_INDEX_FILE",
+    "GIT_OBJECT_DIRECTORY",
+    "GIT_ALTERNATE_OBJECT_DIRECTORIES",
+    "GIT_NAMESPACE",
+    "GIT_CEILING_DIRECTORIES",
+    "GIT_CONFIG",
+    "GIT_CONFIG_PARAMETERS",
+    "GIT_CONFIG_COUNT",
+    "GIT_ASKPASS",
+    "GIT_SSH_COMMAND",
+    "GIT_SSH",
+];
+
+/// config which would run programs from the repo or the user's setup
+const SANDBOX_CONFIG: &[&str] = &[
+    "core.hooksPath=/dev/null",
+    "core.fsmonitor=false",
+    "core.sshCommand=ssh",
+    "credential.helper=",
+];
+
+/// `git` for cached repos, which come from arbitrary DAOs
+///
+/// system and global configs are ignored, hooks never run, only
+/// [`ALLOWED_PROTOCOLS`] are fetched and nothing prompts for credentials. Repos
+/// are owned by the current user, so `safe.directory` isn't needed at all
+pub fn git() -> Command {
+    let mut command = Command::new("git");
+    for config in SANDBOX_CONFIG {
+        command.arg("-c").arg(config);
+    }
+    for name in REDIRECT_ENV {
+        command.env_remove(name);
+    }
+    command
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_ALLOW_PROTOCOL", ALLOWED_PROTOCOLS)
+        .env("GIT_PROTOCOL_FROM_USER", "0")
+        .env("GIT_TERMINAL_PROMPT", "0");
+    command
+}
+
+/// libgit2 counterpart of [`git`]: cached repos are opened without system,
+/// global and XDG configs
+#[cfg(feature = "libgit2")]
+pub fn open_repository(git_dir: &std::path::Path) -> Result<git2::Repository, git2::Error> {
+    static NO_SEARCH_PATHS: std::sync::Once = std::sync::Once::new();
+    NO_SEARCH_PATHS.call_once(|| {
+        for level in [
+            git2::ConfigLevel::System,
+            git2::ConfigLevel::Global,
+            git2::ConfigLevel::XDG,
+        ] {
+            // SAFETY: libgit2 options are global, they are set once before the
+            // first repo is opened
+            if let Err(error) = unsafe { git2::opts::set_search_path(level, "") } {
+                tracing::warn!("libgit2 {:?} config search path: {}", level, error);
+            }
+        }
+    });
+    git2::Repository::open(git_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn output(args: &[&str], dir: &std::path::Path) -> std::process::Output {
+        git().args(args).current_dir(dir).output().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn config_test() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(output(&["init", "--quiet"], dir.path())
+            .await
+            .status
+            .success());
+
+        let config = output(&["config", "--list", "--show-scope"], dir.path()).await;
+        let config = String::from_utf8(config.stdout).unwrap();
+        assert!(
+            config.contains("command\tcore.hookspath=/dev/null"),
+            "{}",
+            config
+        );
+        assert!(
+            config
+                .lines()
+                .all(|line| line.starts_with("command\t") || line.starts_with("local\t")),
+            "{}",
+            config
+        );
+    }
+
+    #[cfg(feature = "libgit2")]
+    #[tokio::test]
+    async fn libgit2_config_test() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(output(&["init", "--quiet"], dir.path())
+            .await
+            .status
+            .success());
+        assert!(output(&["config", "user.name", "gosh"], dir.path())
+            .await
+            .status
+            .success());
+
+        let repo = open_repository(dir.path()).unwrap();
+        for level in [
+            git2::ConfigLevel::System,
+            git2::ConfigLevel::Global,
+            git2::ConfigLevel::XDG,
+        ] {
+            let path = unsafe { git2::opts::get_search_path(level) }.unwrap();
+            assert!(path.as_bytes().is_empty(), "{:?}: {:?}", level, path);
+        }
+        let config = repo.config().unwrap();
+        assert_eq!(config.get_string("user.name").unwrap(), "gosh");
+        let mut entries = config.entries(None).unwrap();
+        while let Some(entry) = entries.next() {
+            let entry = entry.unwrap();
+            assert_eq!(
+                entry.level(),
+                git2::ConfigLevel::Local,
+                "{:?}",
+                entry.name()
+            );
+        }
+    }
+
+    #[test]
+    fn env_test() {
+        let command = git();
+        let envs = command
+            .as_std()
+            .get_envs()
+            .collect::<std::collections::HashMap<_, _>>();
+        for name in [
+            "GIT_DIR",
+            "GIT_CONFIG_PARAMETERS",
+            "GIT_SSH_COMMAND",
+            "GIT_SSH",
+        ] {
+            assert_eq!(
+                envs.get(std::ffi::OsStr::new(name)),
+                Some(&None),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            envs.get(std::ffi::OsStr::new("GIT_CONFIG_NOSYSTEM")),
+            Some(&Some(std::ffi::OsStr::new("1")))
+        );
+    }
+
+    #[tokio::test]
+    async fn protocol_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let ext = output(&["ls-remote", "ext::sh -c true"], dir.path()).await;
+        assert!(!ext.status.success());
+        assert!(
+            String::from_utf8_lossy(&ext.stderr).contains("not allowed"),
+            "{}",
+            String::from_utf8_lossy(&ext.stderr)
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use crate::{archive_filter::ArchiveFilter, git_url::GitUrl};
+use std::io::{Read, Write};
+
+pub const GITMODULES: &str = ".gitmodules";
+
+/// Tree entry which points to a commit of another repo
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Gitlink {
+    pub path: String,
+    pub commit: String,
+}
+
+/// Gitlink joined with its `.gitmodules` entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submodule {
+    /// relative to the parent repo root
+    pub path: String,
+    pub url: GitUrl,
+    /// pinned commit
+    pub commit: String,
+}
+
+/// Submodule which was spliced into an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedSubmodule {
+    pub parent_url: GitUrl,
+    pub parent_commit: String,
+    pub submodule: Submodule,
+}
+
+/// `(path, url)` of every `[submodule "<name>"]` section
+pub fn parse_gitmodules(content: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut modules = Vec::new();
+    let mut current: Option<(String, Option<String>, Option<String>)> = None;
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') {
+            modules.extend(current.take());
+            let Some(name) = line
+                .strip_prefix("[submodule")
+                .and_then(|rest| rest.strip_suffix(']'))
+            else {
+                // other sections aren't interesting
+                continue;
+            };
+            current = Some((name.trim().trim_matches('"').to_owned(), None, None));
+            continue;
+        }
+        let Some((_, path, url)) = current.as_mut() else {
+            continue;
+        };
+        let Some((key, value)) = line.split_once('=') else {
+            anyhow::bail!("{}:{}: expected `key = value`", GITMODULES, number + 1);
+        };
+        let value = value.trim().trim_matches('"').to_owned();
+        match key.trim() {
+            "path" => *path = Some(value),
+            "url" => *url = Some(value),
+            _ => {}
+        }
+    }
+    modules.extend(current);
+
+    modules
+        .into_iter()
+        .map(|(name, path, url)| match (path, url) {
+            (Some(path), Some(url)) => Ok((path.trim_end_matches('/').to_owned(), url)),
+            _ => anyhow::bail!("submodule `{}` must have both path and url", name),
+        })
+        .collect()
+}
+
+/// absolute url of a submodule, relative ones (`../lib.git`) are resolved
+/// against the parent repo url like `git submodule` does
+pub fn resolve_url(parent: &GitUrl, raw: &str) -> anyhow::Result<GitUrl> {
+    if !raw.starts_with("./") && !raw.starts_with("../") {
+        return Ok(raw.parse()?);
+    }
+    let mut base = parent.to_string();
+    let mut rest = raw;
+    loop {
+        if let Some(tail) = rest.strip_prefix("./") {
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix("../") {
+            let Some((parent_dir, _)) = base.rsplit_once('/') else {
+                anyhow::bail!("`{}` goes above the root of `{}`", raw, parent);
+            };
+            base = parent_dir.to_owned();
+            rest = tail;
+        } else {
+            break;
+        }
+    }
+    Ok(format!("{}/{}", base, rest).parse()?)
+}
+
+/// gitlinks joined with their `.gitmodules` entries, the ones which can't be
+/// resolved are left out with a warning, `git archive` leaves them empty dirs
+pub fn resolve(parent: &GitUrl, gitmodules: &str, gitlinks: Vec<Gitlink>) -> Vec<Submodule> {
+    let modules = parse_gitmodules(gitmodules).unwrap_or_else(|error| {
+        tracing::warn!("{} of {} is ignored: {:#}", GITMODULES, parent, error);
+        Vec::new()
+    });
+    gitlinks
+        .into_iter()
+        .filter_map(|gitlink| {
+            let Some((_, raw_url)) = modules.iter().find(|(path, _)| *path == gitlink.path) else {
+                tracing::warn!(
+                    "submodule `{}` of {} is left empty: not in {}",
+                    gitlink.path,
+                    parent,
+                    GITMODULES
+                );
+                return None;
+            };
+            match resolve_url(parent, raw_url) {
+                Ok(url) => Some(Submodule {
+                    url,
+                    path: gitlink.path,
+                    commit: gitlink.commit,
+                }),
+                Err(error) => {
+                    tracing::warn!(
+                        "submodule `{}` of {} is left empty: {:#}",
+                        gitlink.path,
+                        parent,
+                        error
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Write tar `parent` followed by entries of submodule tars mounted at their paths
+///
+/// `parent` is expected to be filtered already, submodule entries are checked
+/// against `filter` with their full paths
+pub fn splice(
+    parent: impl Read,
+    submodules: Vec<(String, Box<dyn Read + Send>)>,
+    filter: &ArchiveFilter,
+    writer: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let mut builder = tar::Builder::new(writer);
+    copy_entries(&mut builder, parent, None, filter)?;
+    for (mount, submodule) in submodules {
+        copy_entries(&mut builder, submodule, Some(&mount), filter)?;
+    }
+    builder.finish()?;
+    Ok(())
+}
+
+fn copy_entries(
+    builder: &mut tar::Builder<&mut dyn Write>,
+    reader: impl Read,
+    mount: Option<&str>,
+    filter: &ArchiveFilter,
+) -> anyhow::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let mut header = entry.header().clone();
+        if header.entry_type() == tar::EntryType::XGlobalHeader {
+            // commit id, only the parent one is kept
+            if mount.is_none() {
+                builder.append(&header, &mut entry)?;
+            }
+            continue;
+        }
+
+        let relative = entry
+            .path()?
+            .to_string_lossy()
+            .trim_end_matches('/')
+            .to_owned();
+        let path = match mount {
+            Some(mount) => {
+                let path = format!("{}/{}", mount, relative);
+                if filter.is_pruned(&path) || !filter.includes(&path) {
+                    continue;
+                }
+                path
+            }
+            // already filtered
+            None => relative,
+        };
+        append_entry(builder, &mut header, &path, &mut entry)?;
+    }
+    Ok(())
+}
+
+/// Append `entry` (not a global header) to `builder` as `path`
+pub(crate) fn append_entry<W: Write>(
+    builder: &mut tar::Builder<W>,
+    header: &mut tar::Header,
+    path: &str,
+    entry: &mut tar::Entry<impl Read>,
+) -> anyhow::Result<()> {
+    match header.entry_type() {
+        tar::EntryType::Symlink => {
+            let Some(target) = entry.link_name()?.map(|target| target.into_owned()) else {
+                anyhow::bail!("symlink `{}` without target", path);
+            };
+            builder.append_link(header, path, target)?;
+        }
+        tar::EntryType::Directory => {
+            builder.append_data(header, format!("{}/", path), std::io::empty())?;
+        }
+        _ => builder.append_data(header, path, entry)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARENT: &str =
+        "gosh://0:0d5c05d7a63f438b57ede179b7110d3e903f5be3b5f543d3d6743d774698e92c/dao/app";
+
+    #[test]
+    fn parse_gitmodules_test() {
+        let content = r#"
+# comment
+[submodule "lib"]
+	path = vendor/lib
+	url = ../lib.git
+[core]
+	bare = false
+[submodule "cargo"]
+	url = "https://github.com/rust-lang/cargo"
+	path = vendor/cargo/
+	branch = master
+"#;
+        assert_eq!(
+            parse_gitmodules(content).unwrap(),
+            vec![
+                ("vendor/lib".to_owned(), "../lib.git".to_owned()),
+                (
+                    "vendor/cargo".to_owned(),
+                    "https://github.com/rust-lang/cargo".to_owned()
+                ),
+            ]
+        );
+        assert!(parse_gitmodules("[submodule \"x\"]\npath = x\n").is_err());
+    }
+
+    #[test]
+    fn resolve_url_test() {
+        let parent: GitUrl = PARENT.parse().unwrap();
+        let cases = [
+            ("../lib.git", PARENT.replace("/app", "/lib")),
+            (
+                "https://github.com/a/b",
+                "https://github.com/a/b".to_owned(),
+            ),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(resolve_url(&parent, raw).unwrap().to_string(), expected);
+        }
+        assert!(resolve_url(&parent, "../../other/dao/repo").is_err());
+
+        let parent: GitUrl = "https://example.com/group/app".parse().unwrap();
+        assert_eq!(
+            resolve_url(&parent, "./nested").unwrap().to_string(),
+            "https://example.com/group/app/nested"
+        );
+    }
+
+    #[test]
+    fn resolve_test() {
+        let parent: GitUrl = PARENT.parse().unwrap();
+        let gitlink = Gitlink {
+            path: "vendor/lib".to_owned(),
+            commit: "a".repeat(40),
+        };
+        let gitmodules = "[submodule \"lib\"]\npath = vendor/lib\nurl = ../lib\n";
+
+        let submodules = resolve(&parent, gitmodules, vec![gitlink.clone()]);
+        assert_eq!(
+            submodules[0].url.to_string(),
+            PARENT.replace("/app", "/lib")
+        );
+        assert_eq!(submodules[0].commit, gitlink.commit);
+        assert!(resolve(&parent, "", vec![gitlink.clone()]).is_empty());
+        let gitmodules = "[submodule \"lib\"]\npath = vendor/lib\nurl = ../../../../lib\n";
+        assert!(resolve(&parent, gitmodules, vec![gitlink]).is_empty());
+    }
+
+    fn tar(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            if path.ends_with('/') {
+                header.set_entry_type(tar::EntryType::Directory);
+            }
+            header.set_mode(0o664);
+            header.set_size(content.len() as u64);
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn paths(body: &[u8]) -> Vec<String> {
+        tar::Archive::new(body)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                entry
+                    .unwrap()
+                    .path()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn splice_test() {
+        let parent = tar(&[("README.md", "app"), ("vendor/", ""), ("vendor/lib/", "")]);
+        let submodule = tar(&[("src/", ""), ("src/lib.rs", "lib"), ("docs.md", "docs")]);
+
+        let mut body = Vec::new();
+        let filter = ArchiveFilter::new([""; 0], ["*.md"]).unwrap();
+        splice(
+            parent.as_slice(),
+            vec![(
+                "vendor/lib".to_owned(),
+                Box::new(std::io::Cursor::new(submodule)),
+            )],
+            &filter,
+            &mut body,
+        )
+        .unwrap();
+
+        assert_eq!(
+            paths(&body),
+            vec![
+                "README.md",
+                "vendor/",
+                "vendor/lib/",
+                "vendor/lib/src/",
+                "vendor/lib/src/lib.rs"
+            ]
+        );
+    }
+}
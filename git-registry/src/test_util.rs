@@ -0,0 +1,35 @@
+//! Git fixtures for tests, other crates get them with the `test-util` feature
+use std::{path::Path, process::Command};
+
+/// `git <args>` in `dir` as the `gosh` author, trimmed STDOUT
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_AUTHOR_NAME", "gosh")
+        .env("GIT_AUTHOR_EMAIL", "gosh@localhost")
+        .env("GIT_COMMITTER_NAME", "gosh")
+        .env("GIT_COMMITTER_EMAIL", "gosh@localhost")
+        .output()
+        .expect("git spawn");
+    assert!(output.status.success(), "git {:?}", args);
+    String::from_utf8(output.stdout).unwrap().trim().to_owned()
+}
+
+/// empty repo in `dir` on `main`
+pub fn init(dir: &Path) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+}
+
+/// commit `content` of `path` as `message`, the new commit
+pub fn commit_file(dir: &Path, path: &str, content: &str, message: &str) -> String {
+    let file = dir.join(path);
+    if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent).unwrap();
+    }
+    std::fs::write(file, content).unwrap();
+    git(dir, &["add", path]);
+    git(dir, &["commit", "--quiet", "-m", message]);
+    git(dir, &["rev-parse", "HEAD"])
+}
@@ -0,0 +1,119 @@
+/// the only smart http service, pushes aren't served
+pub const SERVICE: &str = "git-upload-pack";
+
+/// `Git-Protocol` header (`key=value` pairs separated by `:`) asks for protocol v2
+pub fn is_v2(git_protocol: Option<&str>) -> bool {
+    git_protocol.map_or(false, |header| {
+        header.split(':').any(|kv| kv == "version=2")
+    })
+}
+
+/// smart http puts `# service=git-upload-pack` before v0 ref advertisements,
+/// v2 capability advertisements go as they are
+pub fn service_header() -> Vec<u8> {
+    let mut header = pkt_line(&format!("# service={}\n", SERVICE));
+    header.extend_from_slice(b"0000");
+    header
+}
+
+fn pkt_line(payload: &str) -> Vec<u8> {
+    format!("{:04x}{}", payload.len() + 4, payload).into_bytes()
+}
+
+/// objects of `want` lines of an upload-pack request, v0 and v2 `fetch` alike
+///
+/// v0 puts capabilities after the first object, v2 sends `ls-refs` without
+/// wants at all
+pub fn wants(request: &[u8]) -> anyhow::Result<Vec<String>> {
+    let mut wants = Vec::new();
+    let mut rest = request;
+    while !rest.is_empty() {
+        let Some(len) = rest
+            .get(..4)
+            .and_then(|len| std::str::from_utf8(len).ok())
+            .and_then(|len| usize::from_str_radix(len, 16).ok())
+        else {
+            anyhow::bail!("malformed pkt-line length");
+        };
+        // flush, delimiter and response end packets have no payload
+        if len < 4 {
+            rest = &rest[4..];
+            continue;
+        }
+        let Some(payload) = rest.get(4..len) else {
+            anyhow::bail!("pkt-line is longer than the request: {}", len);
+        };
+        rest = &rest[len..];
+
+        let Some(want) = payload.strip_prefix(b"want ") else {
+            continue;
+        };
+        let want = String::from_utf8_lossy(want);
+        let Some(object) = want.split_whitespace().next() else {
+            anyhow::bail!("empty want");
+        };
+        if !object.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            anyhow::bail!("want isn't an object id: {:?}", object);
+        }
+        if !wants.iter().any(|known| known == object) {
+            wants.push(object.to_owned());
+        }
+    }
+    Ok(wants)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_v2_test() {
+        assert!(is_v2(Some("version=2")));
+        assert!(is_v2(Some("object-format=sha1:version=2")));
+        assert!(!is_v2(Some("version=1")));
+        assert!(!is_v2(None));
+    }
+
+    #[test]
+    fn service_header_test() {
+        assert_eq!(
+            service_header(),
+            b"001e# service=git-upload-pack\n0000".to_vec()
+        );
+    }
+
+    #[test]
+    fn wants_test() {
+        let a = "a".repeat(40);
+        let b = "b".repeat(40);
+
+        let mut v0 = pkt_line(&format!("want {} multi_ack_detailed side-band-64k\n", a));
+        v0.extend(pkt_line(&format!("want {}\n", b)));
+        v0.extend(pkt_line("deepen 1\n"));
+        v0.extend(b"0000");
+        v0.extend(pkt_line(&format!("have {}\n", "c".repeat(40))));
+        v0.extend(pkt_line("done\n"));
+        assert_eq!(wants(&v0).unwrap(), vec![a.clone(), b.clone()]);
+
+        let mut v2 = pkt_line("command=fetch\n");
+        v2.extend(pkt_line("agent=git/2.39.5\n"));
+        v2.extend(b"0001");
+        v2.extend(pkt_line("thin-pack\n"));
+        v2.extend(pkt_line(&format!("want {}\n", b)));
+        v2.extend(pkt_line(&format!("want {}\n", b)));
+        v2.extend(pkt_line("filter blob:none\n"));
+        v2.extend(pkt_line("done\n"));
+        v2.extend(b"0000");
+        assert_eq!(wants(&v2).unwrap(), vec![b]);
+
+        let mut ls_refs = pkt_line("command=ls-refs\n");
+        ls_refs.extend(b"0001");
+        ls_refs.extend(pkt_line("ref-prefix HEAD\n"));
+        ls_refs.extend(b"0000");
+        assert!(wants(&ls_refs).unwrap().is_empty());
+
+        assert!(wants(b"zzzz").is_err());
+        assert!(wants(b"0100want").is_err());
+        assert!(wants(&pkt_line("want HEAD\n")).is_err());
+    }
+}
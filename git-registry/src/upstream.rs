@@ -0,0 +1,175 @@
+use crate::git_url::GitUrl;
+use anyhow::Context;
+use std::{fmt, path::Path};
+use tokio_util::io::ReaderStream;
+
+/// prefix of the routes which accept git bundles on `gosh-git-server`
+pub const UPLOAD_PREFIX: &str = "upload";
+
+/// Bearer token `gosh-git-server` takes uploads with, kept out of `Debug`
+#[derive(Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(transparent)]
+pub struct UploadToken(String);
+
+impl UploadToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(token.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// `Authorization` header value carries the token, compared in constant time
+    pub fn authorizes(&self, authorization: &str) -> bool {
+        let Some(token) = authorization.strip_prefix("Bearer ") else {
+            return false;
+        };
+        let (expected, token) = (self.0.as_bytes(), token.trim().as_bytes());
+        expected.len() == token.len()
+            && expected
+                .iter()
+                .zip(token)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+impl fmt::Debug for UploadToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "UploadToken(..)")
+    }
+}
+
+/// Shared `gosh-git-server` which is tried before the remote itself
+///
+/// Repos are fetched from it with the dumb http protocol. With an upload
+/// token, repos which had to be fetched from the remote are sent back to it
+/// as git bundles, so the next team member gets their pinned commits from
+/// the shared cache
+#[derive(Debug)]
+pub struct UpstreamCache {
+    client: reqwest::Client,
+    base: String,
+    upload_token: Option<UploadToken>,
+}
+
+impl UpstreamCache {
+    /// `base` is the server root, e.g. `http://git-cache.team.local:8080`
+    pub fn new(base: impl AsRef<str>) -> anyhow::Result<Self> {
+        let base = base.as_ref().trim_end_matches('/');
+        if !base.starts_with("http://") && !base.starts_with("https://") {
+            anyhow::bail!("upstream cache must be an http(s) url: {}", base);
+        }
+        Ok(Self {
+            client: reqwest::Client::builder().build()?,
+            base: base.to_owned(),
+            upload_token: None,
+        })
+    }
+
+    pub fn with_upload_token(mut self, upload_token: Option<UploadToken>) -> Self {
+        self.upload_token = upload_token;
+        self
+    }
+
+    pub fn uploads(&self) -> bool {
+        self.upload_token.is_some()
+    }
+
+    /// dumb http remote of `url` on the server, `None` if the server can't serve it
+    pub fn url_of(&self, url: &GitUrl) -> Option<String> {
+        server_path(url).map(|path| format!("{}/{}", self.base, path))
+    }
+
+    /// send a bundle made by `GitCacheRepo::bundle` to the server cache of `url`
+    pub async fn upload(&self, url: &GitUrl, bundle: &Path) -> anyhow::Result<()> {
+        let Some(token) = &self.upload_token else {
+            anyhow::bail!("no upload token for the upstream cache");
+        };
+        let Some(path) = server_path(url) else {
+            anyhow::bail!("upstream cache can't store {}", url);
+        };
+        let target = format!("{}/{}/{}", self.base, UPLOAD_PREFIX, path);
+        tracing::debug!("upstream: POST {}", target);
+        let file = tokio::fs::File::open(bundle).await?;
+        self.client
+            .post(&target)
+            .bearer_auth(token.as_str())
+            .body(reqwest::Body::wrap_stream(ReaderStream::new(file)))
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("upload {} to the upstream cache", url))?;
+        Ok(())
+    }
+}
+
+/// repo path on `gosh-git-server`: `<system contract>/<dao>/<repo>` for gosh
+/// repos and `git/<scheme>/<repo>` for the rest
+///
+/// gosh repos of other networks aren't served
+pub fn server_path(url: &GitUrl) -> Option<String> {
+    match url {
+        GitUrl::Gosh(gosh) if gosh.network().is_none() => Some(format!(
+            "{}/{}/{}",
+            gosh.system_contract(),
+            gosh.dao(),
+            gosh.repo()
+        )),
+        GitUrl::Gosh(_) => None,
+        GitUrl::File { path } => Some(format!("git/file/{}", path)),
+        url => {
+            let raw = url.to_string();
+            let (scheme, rest) = raw.split_once("://")?;
+            Some(format!("git/{}/{}", scheme, rest))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_of_test() {
+        let upstream = UpstreamCache::new("http://cache.local:8080/").unwrap();
+        let cases = [
+            (
+                "gosh://0:0d5c05d7a63f438b57ede179b7110d3e903f5be3b5f543d3d6743d774698e92c/dao/repo",
+                Some("http://cache.local:8080/0:0d5c05d7a63f438b57ede179b7110d3e903f5be3b5f543d3d6743d774698e92c/dao/repo"),
+            ),
+            (
+                "gosh::devnet://0:0d5c05d7a63f438b57ede179b7110d3e903f5be3b5f543d3d6743d774698e92c/dao/repo",
+                None,
+            ),
+            (
+                "https://github.com/rust-lang/cargo.git",
+                Some("http://cache.local:8080/git/https/github.com/rust-lang/cargo.git"),
+            ),
+            (
+                "ssh://git@example.com:2222/repo",
+                Some("http://cache.local:8080/git/ssh/git@example.com:2222/repo"),
+            ),
+            (
+                "file:///srv/git/repo",
+                Some("http://cache.local:8080/git/file/srv/git/repo"),
+            ),
+        ];
+        for (url, expected) in cases {
+            let url: GitUrl = url.parse().unwrap();
+            assert_eq!(upstream.url_of(&url).as_deref(), expected, "{}", url);
+        }
+        assert!(UpstreamCache::new("file:///srv/cache").is_err());
+    }
+
+    #[test]
+    fn upload_token_test() {
+        let token = UploadToken::new("s3cret");
+        assert!(token.authorizes("Bearer s3cret"));
+        assert!(!token.authorizes("Bearer s3cre"));
+        assert!(!token.authorizes("Bearer s3cretx"));
+        assert!(!token.authorizes("s3cret"));
+        assert_eq!(format!("{:?}", token), "UploadToken(..)");
+    }
+}
@@ -0,0 +1,91 @@
+use crate::git_url::GitUrl;
+
+/// `git config url.<base>.insteadOf <instead_of>` alike rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlRewrite {
+    /// replacement, e.g. `file:///srv/mirrors/`
+    pub base: String,
+    /// prefix of the canonical url, e.g. `gosh://0:<system contract>/dao/`
+    pub instead_of: String,
+}
+
+/// Mirrors for git remotes
+///
+/// Rewritten urls are only used to fetch, cache dirs and SBOM keep the canonical url
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UrlRewrites(Vec<UrlRewrite>);
+
+impl UrlRewrites {
+    pub fn new(rules: impl IntoIterator<Item = UrlRewrite>) -> Self {
+        Self(
+            rules
+                .into_iter()
+                .filter(|rule| !rule.instead_of.is_empty())
+                .collect(),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// urls to fetch `url` from: every matching mirror in the config order and
+    /// the canonical url as the last resort
+    pub fn candidates(&self, url: &GitUrl) -> Vec<String> {
+        let canonical = url.to_string();
+        let mut candidates = self
+            .0
+            .iter()
+            .filter_map(|rule| {
+                canonical
+                    .strip_prefix(&rule.instead_of)
+                    .map(|rest| format!("{}{}", rule.base, rest))
+            })
+            .collect::<Vec<_>>();
+        candidates.push(canonical);
+        candidates.dedup();
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAO: &str =
+        "gosh://0:0d5c05d7a63f438b57ede179b7110d3e903f5be3b5f543d3d6743d774698e92c/dao/";
+
+    fn rule(base: &str, instead_of: &str) -> UrlRewrite {
+        UrlRewrite {
+            base: base.to_owned(),
+            instead_of: instead_of.to_owned(),
+        }
+    }
+
+    #[test]
+    fn candidates_test() {
+        let rewrites = UrlRewrites::new([
+            rule("file:///srv/mirror/", DAO),
+            rule("https://other.example.com/", "https://github.com/"),
+            rule("https://mirror.example.com/dao/", DAO),
+            rule("file:///nowhere", ""),
+        ]);
+
+        let url: GitUrl = format!("{}repo.git", DAO).parse().unwrap();
+        assert_eq!(
+            rewrites.candidates(&url),
+            vec![
+                "file:///srv/mirror/repo".to_owned(),
+                "https://mirror.example.com/dao/repo".to_owned(),
+                format!("{}repo", DAO),
+            ]
+        );
+
+        let url: GitUrl = "https://gitlab.com/a/b".parse().unwrap();
+        assert_eq!(rewrites.candidates(&url), vec!["https://gitlab.com/a/b"]);
+        assert_eq!(
+            UrlRewrites::default().candidates(&url),
+            vec!["https://gitlab.com/a/b"]
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use git_registry::git_url::GitUrl;
+use glob::{MatchOptions, Pattern};
+
+// `*` matches `/` too, so `gosh://*/dao/*` covers every repo of the DAO
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// Repos a server serves: glob patterns matched against the canonical url,
+/// e.g. `gosh://0:<system contract>/dao/*` for a DAO
+///
+/// denied repos are never served, the rest have to be allowed unless the
+/// allowlist is empty
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepoAccess {
+    allow: Vec<Pattern>,
+    deny: Vec<Pattern>,
+}
+
+impl RepoAccess {
+    pub fn new(
+        allow: impl IntoIterator<Item = impl AsRef<str>>,
+        deny: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            allow: patterns(allow)?,
+            deny: patterns(deny)?,
+        })
+    }
+
+    pub fn permits(&self, url: &GitUrl) -> bool {
+        let canonical = url.to_string();
+        let matches = |pattern: &Pattern| pattern.matches_with(&canonical, MATCH_OPTIONS);
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+}
+
+fn patterns(repos: impl IntoIterator<Item = impl AsRef<str>>) -> anyhow::Result<Vec<Pattern>> {
+    repos
+        .into_iter()
+        .map(|repos| {
+            Pattern::new(repos.as_ref())
+                .map_err(|err| anyhow::anyhow!("wrong repos pattern `{}`: {}", repos.as_ref(), err))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAO: &str =
+        "gosh://0:0d5c05d7a63f438b57ede179b7110d3e903f5be3b5f543d3d6743d774698e92c/dao";
+
+    #[test]
+    fn permits_test() {
+        let url = |url: &str| url.parse::<GitUrl>().unwrap();
+        let repo = url(&format!("{}/repo", DAO));
+        let secret = url(&format!("{}/secret", DAO));
+        let other = url("https://github.com/org/repo");
+
+        let open = RepoAccess::default();
+        assert!(open.permits(&repo));
+        assert!(open.permits(&other));
+
+        let access = RepoAccess::new(["gosh://*/dao/*"], ["*/secret"]).unwrap();
+        assert!(access.permits(&repo));
+        assert!(!access.permits(&secret));
+        assert!(!access.permits(&other));
+
+        let access = RepoAccess::new(Vec::<String>::new(), ["https://github.com/*"]).unwrap();
+        assert!(access.permits(&repo));
+        assert!(!access.permits(&other));
+
+        assert!(RepoAccess::new(["gosh://["], Vec::<String>::new()).is_err());
+    }
+}
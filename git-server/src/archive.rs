@@ -0,0 +1,463 @@
+use crate::{
+    error_status,
+    file_response::{none_match, IMMUTABLE, MUTABLE},
+    GitServerState,
+};
+use axum::{
+    body::StreamBody,
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use git_registry::{
+    archive_filter::ArchiveFilter, git_url::GitUrl, gosh_url::GoshUrl, lfs::LfsObjects,
+    submodule::ArchivedSubmodule,
+};
+use gosh_sbom::{
+    gosh_classification::GoshClassification,
+    record::{self, Served},
+    Sbom,
+};
+use gosh_utils::stream::{self, ByteStream};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+pub const GZIP: &str = "application/gzip";
+pub const ZSTD: &str = "application/zstd";
+const TAR: &str = "application/x-tar";
+
+/// `<commit>.tar.gz`, `<commit>.tar.zst` or `<commit>.tar`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveFormat {
+    TarGz,
+    TarZst,
+    Tar,
+}
+
+impl ArchiveFormat {
+    /// `(commit, format)` of an archive name
+    fn split(name: &str) -> Option<(&str, Self)> {
+        [
+            (".tar.gz", Self::TarGz),
+            (".tgz", Self::TarGz),
+            (".tar.zst", Self::TarZst),
+            (".tar", Self::Tar),
+        ]
+        .into_iter()
+        .find_map(|(extension, format)| {
+            let commit = name.strip_suffix(extension)?;
+            (!commit.is_empty()).then_some((commit, format))
+        })
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::TarGz => "tar.gz",
+            Self::TarZst => "tar.zst",
+            Self::Tar => "tar",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::TarGz => GZIP,
+            Self::TarZst => ZSTD,
+            Self::Tar => TAR,
+        }
+    }
+
+    /// `body` is a zstd compressed tar
+    fn encode(self, body: ByteStream) -> ByteStream {
+        match self {
+            Self::TarGz => stream::gzip_encode(stream::zstd_decode(body)),
+            Self::TarZst => body,
+            Self::Tar => stream::zstd_decode(body),
+        }
+    }
+}
+
+/// `/archive/<contract>/<dao>/<repo>/<commit>.tar.gz`: the tree of a commit
+/// (or a branch or tag) with submodules and LFS objects, the same one
+/// `GoshGet.Commit` streams
+pub async fn archive_handler(
+    State(state): State<Arc<GitServerState>>,
+    Path((contract, dao, repo, name)): Path<(String, String, String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    tracing::info!(?contract, ?dao, ?repo, ?name, "archive");
+    let (rev, format) = ArchiveFormat::split(&name).ok_or(StatusCode::NOT_FOUND)?;
+    let git_url = gosh_url(&state, &contract, &dao, &repo)?;
+
+    let commit = state
+        .git_registry
+        .normalized_commit(&git_url, rev)
+        .await
+        .map_err(error_status)?;
+    let etag = etag(&commit, format.extension());
+    let component = format!("{}:{}", git_url, commit);
+    if none_match(&headers, &etag) {
+        if let Some(sbom) = &state.sbom {
+            // the client got the archive earlier, the SBOM of this build still
+            // needs it, LFS objects inside can't be known without building it
+            let submodules = state
+                .git_registry
+                .archived_submodules(&git_url, &commit, &ArchiveFilter::default())
+                .await
+                .map_err(error_status)?;
+            let served = Served {
+                dependencies: dependencies(&git_url, &commit, &component, submodules),
+                component: (GoshClassification::Commit, component),
+                lfs_objects: LfsObjects::default(),
+            };
+            record::append(sbom, served).await;
+        }
+        return Ok(not_modified(rev, &commit, etag));
+    }
+    let archive = state
+        .git_registry
+        .git_archive(&git_url, &commit, &ArchiveFilter::default())
+        .await
+        .map_err(error_status)?;
+
+    let served = Served {
+        dependencies: dependencies(&git_url, &commit, &component, archive.submodules),
+        component: (GoshClassification::Commit, component),
+        lfs_objects: archive.lfs_objects,
+    };
+    let body = recorded(state.sbom.clone(), format.encode(archive.body), served);
+    let disposition = format!(
+        "attachment; filename=\"{}-{}.{}\"",
+        repo,
+        commit,
+        format.extension()
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_owned()),
+            (header::CONTENT_DISPOSITION, disposition),
+            (
+                header::CACHE_CONTROL,
+                cache_control(rev, &commit).to_owned(),
+            ),
+            (header::ETAG, etag),
+        ],
+        StreamBody::new(body),
+    )
+        .into_response())
+}
+
+/// `/raw/<contract>/<dao>/<repo>/<commit>/<path>`: a file of a commit (or a
+/// branch or tag), LFS pointers are resolved, the same one `GoshGet.File`
+/// streams
+pub async fn raw_handler(
+    State(state): State<Arc<GitServerState>>,
+    Path((contract, dao, repo, rev, path)): Path<(String, String, String, String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    tracing::info!(?contract, ?dao, ?repo, ?rev, ?path, "raw");
+    let path = path.trim_start_matches('/');
+    if path.is_empty() || path.ends_with('/') {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let git_url = gosh_url(&state, &contract, &dao, &repo)?;
+
+    let commit = state
+        .git_registry
+        .normalized_commit(&git_url, &rev)
+        .await
+        .map_err(error_status)?;
+    let etag = etag(&commit, path);
+    let is_cached = none_match(&headers, &etag);
+    if is_cached && state.sbom.is_none() {
+        return Ok(not_modified(&rev, &commit, etag));
+    }
+    let file = state
+        .git_registry
+        .git_show(&git_url, &commit, path)
+        .await
+        .map_err(error_status)?;
+
+    let served = Served {
+        component: (
+            GoshClassification::File,
+            format!("{}:{}:{}", git_url, commit, path),
+        ),
+        dependencies: Vec::new(),
+        lfs_objects: Arc::new(Mutex::new(file.lfs_object.into_iter().collect())),
+    };
+    if is_cached {
+        // the client got the file earlier, the SBOM of this build still needs
+        // it, the LFS pointer is known without reading the body
+        if let Some(sbom) = &state.sbom {
+            record::append(sbom, served).await;
+        }
+        return Ok(not_modified(&rev, &commit, etag));
+    }
+    let body = recorded(state.sbom.clone(), stream::zstd_decode(file.body), served);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_owned()),
+            (
+                header::CACHE_CONTROL,
+                cache_control(&rev, &commit).to_owned(),
+            ),
+            (header::ETAG, etag),
+        ],
+        StreamBody::new(body),
+    )
+        .into_response())
+}
+
+fn gosh_url(
+    state: &GitServerState,
+    contract: &str,
+    dao: &str,
+    repo: &str,
+) -> Result<GitUrl, StatusCode> {
+    let git_url: GitUrl = GoshUrl::new(contract, dao, repo)
+        .map_err(|error| {
+            tracing::warn!("{}", error);
+            StatusCode::BAD_REQUEST
+        })?
+        .into();
+    if !state.is_allowed(&git_url) {
+        tracing::warn!("remote is not allowed: {}", git_url);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(git_url)
+}
+
+/// branches and tags move, commits don't
+fn cache_control(rev: &str, commit: &str) -> &'static str {
+    if rev == commit {
+        IMMUTABLE
+    } else {
+        MUTABLE
+    }
+}
+
+/// strong, the content of `name` at `commit` never changes
+fn etag(commit: &str, name: &str) -> String {
+    format!(
+        "\"{}:{}\"",
+        commit,
+        utf8_percent_encode(name, NON_ALPHANUMERIC)
+    )
+}
+
+fn not_modified(rev: &str, commit: &str, etag: String) -> Response {
+    (
+        StatusCode::NOT_MODIFIED,
+        [
+            (header::CACHE_CONTROL, cache_control(rev, commit).to_owned()),
+            (header::ETAG, etag),
+        ],
+    )
+        .into_response()
+}
+
+/// Forward `body` to the client and record it in the SBOM only when the
+/// whole body was sent, as `GoshGetService` does
+fn recorded(sbom: Option<Arc<Mutex<Sbom>>>, body: ByteStream, served: Served) -> ByteStream {
+    match sbom {
+        Some(sbom) => Box::pin(record::forward(sbom, body, |chunk| chunk, served)),
+        None => body,
+    }
+}
+
+/// `(parent, submodule)` components of `submodules` spliced into the archive
+/// of `component`
+fn dependencies(
+    git_url: &GitUrl,
+    commit: &str,
+    component: &str,
+    submodules: Vec<ArchivedSubmodule>,
+) -> Vec<(String, (GoshClassification, String))> {
+    submodules
+        .into_iter()
+        .map(|archived| {
+            let parent = if archived.parent_url == *git_url && archived.parent_commit == commit {
+                component.to_owned()
+            } else {
+                // nested submodule
+                format!("{}:{}", archived.parent_url, archived.parent_commit)
+            };
+            let submodule = format!("{}:{}", archived.submodule.url, archived.submodule.commit);
+            (parent, (GoshClassification::Submodule, submodule))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{access::RepoAccess, dumb_session::DumbSessions};
+    use git_registry::{
+        registry::GitCacheRegistry,
+        test_util::{commit_file, git, init},
+        url_rewrite::{UrlRewrite, UrlRewrites},
+    };
+
+    const DAO: &str =
+        "gosh://0:0d5c05d7a63f438b57ede179b7110d3e903f5be3b5f543d3d6743d774698e92c/dao";
+
+    #[test]
+    fn split_test() {
+        let commit = "9c1b0d4e0d5ec5a2a6c1f1c2a1b6d3e4f5a6b7c8";
+        assert_eq!(
+            ArchiveFormat::split(&format!("{}.tar.gz", commit)),
+            Some((commit, ArchiveFormat::TarGz))
+        );
+        assert_eq!(
+            ArchiveFormat::split("main.tgz"),
+            Some(("main", ArchiveFormat::TarGz))
+        );
+        assert_eq!(
+            ArchiveFormat::split("v1.0.tar.zst"),
+            Some(("v1.0", ArchiveFormat::TarZst))
+        );
+        assert_eq!(
+            ArchiveFormat::split("v1.0.tar"),
+            Some(("v1.0", ArchiveFormat::Tar))
+        );
+        assert_eq!(ArchiveFormat::split(".tar.gz"), None);
+        assert_eq!(ArchiveFormat::split("main.zip"), None);
+        assert_eq!(ArchiveFormat::split("main"), None);
+    }
+
+    #[tokio::test]
+    async fn handlers_test() {
+        // `<DAO>/repo` is cloned from a local mirror
+        let dir = tempfile::tempdir().unwrap();
+        let mirror = dir.path().join("mirror");
+        std::fs::create_dir_all(mirror.join("repo")).unwrap();
+        std::fs::create_dir_all(mirror.join("lib")).unwrap();
+        let lib = mirror.join("lib");
+        init(&lib);
+        let lib_commit = commit_file(&lib, "lib.rs", "", "lib");
+        let repo = mirror.join("repo");
+        init(&repo);
+        commit_file(&repo, "README.md", "# repo\n", "first");
+        git(
+            &repo,
+            &[
+                "update-index",
+                "--add",
+                "--cacheinfo",
+                &format!("160000,{},lib", lib_commit),
+            ],
+        );
+        let commit = commit_file(
+            &repo,
+            ".gitmodules",
+            "[submodule \"lib\"]\n\tpath = lib\n\turl = ../lib\n",
+            "lib",
+        );
+
+        let git_registry = GitCacheRegistry::default()
+            .with_cache_dir(dir.path().join("cache"))
+            .with_url_rewrites(UrlRewrites::new([UrlRewrite {
+                base: format!("file://{}/", mirror.display()),
+                instead_of: format!("{}/", DAO),
+            }]));
+        let sbom = Arc::new(Mutex::new(Sbom::default()));
+        let state = Arc::new(GitServerState {
+            sbom: Some(sbom.clone()),
+            git_registry: Arc::new(git_registry),
+            dumb_sessions: DumbSessions::default(),
+            repos: RepoAccess::default(),
+            max_request_body: None,
+            upload_token: None,
+        });
+        let (contract, dao) = DAO
+            .strip_prefix("gosh://")
+            .and_then(|path| path.split_once('/'))
+            .unwrap();
+        let path = |rev: &str, name: &str| {
+            Path((
+                contract.to_owned(),
+                dao.to_owned(),
+                "repo".to_owned(),
+                rev.to_owned(),
+                name.to_owned(),
+            ))
+        };
+
+        let response = raw_handler(
+            State(state.clone()),
+            path("main", "README.md"),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], MUTABLE);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"# repo\n");
+
+        let response = raw_handler(
+            State(state.clone()),
+            path(&commit, "missing.md"),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(response.unwrap_err(), StatusCode::NOT_FOUND);
+
+        let archive = |name: String, headers: HeaderMap| {
+            archive_handler(
+                State(state.clone()),
+                Path((contract.to_owned(), dao.to_owned(), "repo".to_owned(), name)),
+                headers,
+            )
+        };
+        let response = archive(format!("{}.tar", commit), HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], IMMUTABLE);
+        let etag = response.headers()[header::ETAG].clone();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut tar = tar::Archive::new(&body[..]);
+        let paths = tar
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect::<Vec<_>>();
+        assert!(paths.contains(&"README.md".to_owned()), "{:?}", paths);
+        assert!(paths.contains(&"lib/lib.rs".to_owned()), "{:?}", paths);
+
+        let response = archive("main.tar.gz".to_owned(), HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], GZIP);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(&body[..]));
+        assert!(tar.entries().unwrap().count() > 0);
+
+        let url = format!("{}/repo", DAO);
+        let commit_component = (GoshClassification::Commit, format!("{}:{}", url, commit));
+        let lib_dependency = (
+            format!("{}:{}", url, commit),
+            format!("{}/lib:{}", DAO, lib_commit),
+        );
+        {
+            let mut sbom = sbom.lock().await;
+            assert!(sbom.dependencies.contains(&lib_dependency));
+            sbom.dependencies.clear();
+            assert!(sbom.inner.contains(&commit_component));
+            assert!(sbom.inner.contains(&(
+                GoshClassification::File,
+                format!("{}:{}:README.md", url, commit)
+            )));
+            sbom.inner.clear();
+        }
+
+        // a cached copy is what the build got
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, etag);
+        let response = archive(format!("{}.tar", commit), headers).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        let sbom = sbom.lock().await;
+        assert!(sbom.inner.contains(&commit_component));
+        assert_eq!(sbom.dependencies, [lib_dependency]);
+    }
+}
@@ -0,0 +1,83 @@
+use clap::Parser;
+use git_registry::upstream::UploadToken;
+use git_server::config::{CacheServerConfig, TlsConfig};
+use std::{net::SocketAddr, path::PathBuf};
+
+/// Team git cache: serves gosh repos and allowed remotes over git http
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    /// JSON config file, flags take precedence over it
+    #[arg(short, long, env = "GOSH_GIT_SERVER_CONFIG", value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Listen address, can be repeated [default: 0.0.0.0:8080]
+    #[arg(short, long = "listen", value_name = "HOST:PORT")]
+    pub listen: Vec<SocketAddr>,
+    /// Where repos are cloned [default: ~/.cache/gosh]
+    #[arg(long, value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+    /// Non-gosh git host which is permitted as a remote, can be repeated
+    #[arg(long = "allowed-host", value_name = "HOST")]
+    pub allowed_hosts: Vec<String>,
+    /// Serve only repos matching the url glob, can be repeated
+    #[arg(long = "allow", value_name = "GLOB")]
+    pub allow: Vec<String>,
+    /// Never serve repos matching the url glob, can be repeated
+    #[arg(long = "deny", value_name = "GLOB")]
+    pub deny: Vec<String>,
+    /// Take git bundles of repos fetched by clients which send this token
+    #[arg(long, env = "GOSH_GIT_SERVER_UPLOAD_TOKEN", value_name = "TOKEN")]
+    pub upload_token: Option<String>,
+    /// PEM certificate chain, serves https with --tls-key
+    #[arg(long, value_name = "PATH", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of --tls-cert
+    #[arg(long, value_name = "PATH", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Refuse request bodies over this many bytes
+    #[arg(long, value_name = "BYTES")]
+    pub max_request_body: Option<u64>,
+    /// Answer requests which take longer with 408
+    #[arg(long = "request-timeout", value_name = "SECONDS")]
+    pub request_timeout_secs: Option<u64>,
+    /// Write a SBOM of everything served by this run into the dir
+    #[arg(long, value_name = "DIR")]
+    pub sbom_dir: Option<PathBuf>,
+}
+
+impl Cli {
+    /// `config` with flags which were set
+    pub fn apply(self, mut config: CacheServerConfig) -> CacheServerConfig {
+        if !self.listen.is_empty() {
+            config.listen = self.listen;
+        }
+        if self.cache_dir.is_some() {
+            config.cache_dir = self.cache_dir;
+        }
+        if !self.allowed_hosts.is_empty() {
+            config.allowed_hosts = self.allowed_hosts;
+        }
+        if !self.allow.is_empty() {
+            config.allow = self.allow;
+        }
+        if !self.deny.is_empty() {
+            config.deny = self.deny;
+        }
+        if let Some(upload_token) = self.upload_token {
+            config.upload_token = Some(UploadToken::new(upload_token));
+        }
+        if let (Some(cert), Some(key)) = (self.tls_cert, self.tls_key) {
+            config.tls = Some(TlsConfig { cert, key });
+        }
+        if self.max_request_body.is_some() {
+            config.max_request_body = self.max_request_body;
+        }
+        if self.request_timeout_secs.is_some() {
+            config.request_timeout_secs = self.request_timeout_secs;
+        }
+        if self.sbom_dir.is_some() {
+            config.sbom_dir = self.sbom_dir;
+        }
+        config
+    }
+}
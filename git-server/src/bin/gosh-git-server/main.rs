@@ -0,0 +1,176 @@
+mod cli;
+
+use clap::Parser;
+use cli::Cli;
+use git_registry::{git_url::AllowedHosts, registry::GitCacheRegistry};
+use git_server::{
+    access::RepoAccess,
+    config::CacheServerConfig,
+    tls::{self, TlsIncoming},
+    CacheServerOptions,
+};
+use gosh_sbom::{Sbom, SBOM_DEFAULT_FILE_NAME};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{watch, Mutex},
+    task::JoinSet,
+};
+
+/// how often the SBOM file is rewritten while it grows
+const SBOM_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    gosh_utils::tracing::default_init();
+
+    let cli = Cli::parse();
+    let config = match &cli.config {
+        Some(path) => CacheServerConfig::load(path)?,
+        None => CacheServerConfig::default(),
+    };
+    let config = cli.apply(config);
+    tracing::debug!(?config);
+
+    let mut git_registry =
+        GitCacheRegistry::default().with_allowed_hosts(AllowedHosts::new(&config.allowed_hosts));
+    if let Some(cache_dir) = &config.cache_dir {
+        git_registry = git_registry.with_cache_dir(cache_dir);
+    }
+    if let Some(sbom_dir) = &config.sbom_dir {
+        std::fs::create_dir_all(sbom_dir)?;
+    }
+    let sbom = config
+        .sbom_dir
+        .as_ref()
+        .map(|_| Arc::new(Mutex::new(Sbom::default())));
+    // one SBOM per server session, kept up to date so a crash loses little of it
+    let sbom_file = sbom.clone().zip(config.sbom_dir.as_ref().map(|sbom_dir| {
+        sbom_dir.join(format!(
+            "{}.{}",
+            uuid::Uuid::new_v4(),
+            SBOM_DEFAULT_FILE_NAME
+        ))
+    }));
+    let sbom_flush = sbom_file
+        .clone()
+        .map(|(sbom, path)| tokio::spawn(flush_sbom(sbom, path)));
+    let tls_config = config
+        .tls
+        .as_ref()
+        .map(|tls| tls::server_config(&tls.cert, &tls.key))
+        .transpose()?;
+
+    let router = git_server::cache_router(
+        Arc::new(git_registry),
+        CacheServerOptions {
+            upload_token: config.upload_token.clone(),
+            repos: RepoAccess::new(&config.allow, &config.deny)?,
+            sbom: sbom.clone(),
+            max_request_body: config.max_request_body,
+            request_timeout: config.request_timeout_secs.map(Duration::from_secs),
+        },
+    );
+
+    let (stop, stopped) = watch::channel(());
+    let mut servers = JoinSet::new();
+    for addr in config.listen() {
+        let make_service = router
+            .clone()
+            .into_make_service_with_connect_info::<SocketAddr>();
+        let mut stopped = stopped.clone();
+        let shutdown = async move {
+            let _ = stopped.changed().await;
+        };
+        match &tls_config {
+            Some(tls_config) => {
+                let incoming = TlsIncoming::bind(addr, tls_config.clone()).await?;
+                tracing::info!("listening on https://{}", addr);
+                servers.spawn(
+                    axum::Server::builder(incoming)
+                        .serve(make_service)
+                        .with_graceful_shutdown(shutdown),
+                );
+            }
+            None => {
+                let server = axum::Server::try_bind(&addr)?;
+                tracing::info!("listening on http://{}", addr);
+                servers.spawn(server.serve(make_service).with_graceful_shutdown(shutdown));
+            }
+        }
+    }
+
+    tokio::select! {
+        _ = shutdown_signal() => tracing::info!("shutting down"),
+        Some(result) = servers.join_next() => {
+            if let Err(err) = result? {
+                tracing::error!("server error: {}", err);
+            }
+        }
+    }
+    let _ = stop.send(());
+    while let Some(result) = servers.join_next().await {
+        if let Err(err) = result? {
+            tracing::error!("server error: {}", err);
+        }
+    }
+
+    if let Some(sbom_flush) = sbom_flush {
+        sbom_flush.abort();
+    }
+    if let Some((sbom, path)) = sbom_file {
+        save_sbom(&sbom, &path, &mut None).await?;
+        tracing::info!("SBOM saved to {:?}", path);
+    }
+
+    Ok(())
+}
+
+async fn flush_sbom(sbom: Arc<Mutex<Sbom>>, path: PathBuf) {
+    let mut saved = None;
+    let mut interval = tokio::time::interval(SBOM_FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(error) = save_sbom(&sbom, &path, &mut saved).await {
+            tracing::error!("SBOM {:?}: {:?}", path, error);
+        }
+    }
+}
+
+/// write `sbom` to `path` unless it still has the `saved` number of components
+/// and links, through a temp file so `path` is never half written
+///
+/// only serializing holds the lock, requests which record keep going meanwhile
+async fn save_sbom(
+    sbom: &Mutex<Sbom>,
+    path: &Path,
+    saved: &mut Option<usize>,
+) -> anyhow::Result<()> {
+    let (size, json) = {
+        let sbom = sbom.lock().await;
+        let size = sbom.inner.len() + sbom.dependencies.len();
+        if *saved == Some(size) {
+            return Ok(());
+        }
+        (size, sbom.to_json()?)
+    };
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, json).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    *saved = Some(size);
+    tracing::debug!("SBOM of {} saved to {:?}", size, path);
+    Ok(())
+}
+
+async fn shutdown_signal() {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+}
@@ -0,0 +1,114 @@
+use git_registry::upstream::UploadToken;
+use std::{io::Read, net::SocketAddr, path::PathBuf};
+
+/// `0.0.0.0:8080`
+pub const DEFAULT_LISTEN: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED), 8080);
+
+/// certificate chain and private key, both PEM
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// `gosh-git-server` config file (JSON), command line flags take precedence
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheServerConfig {
+    /// [`DEFAULT_LISTEN`] if empty
+    pub listen: Vec<SocketAddr>,
+
+    /// where repos are cloned, `~/.cache/gosh` if not set
+    #[serde(rename = "cache-dir")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// non-gosh git hosts which are permitted as remotes
+    #[serde(rename = "allowed-hosts")]
+    pub allowed_hosts: Vec<String>,
+
+    /// repo url globs which are served, all repos if empty
+    pub allow: Vec<String>,
+
+    /// repo url globs which are never served, wins over `allow`
+    pub deny: Vec<String>,
+
+    /// take bundles from the team's clients with this `git-upstream-upload-token`
+    #[serde(rename = "upload-token")]
+    pub upload_token: Option<UploadToken>,
+
+    /// https instead of http on every `listen` address
+    pub tls: Option<TlsConfig>,
+
+    /// request bodies (upload-pack requests, bundles) over this are refused
+    #[serde(rename = "max-request-body")]
+    pub max_request_body: Option<u64>,
+
+    /// requests which aren't answered in time get 408
+    #[serde(rename = "request-timeout-secs")]
+    pub request_timeout_secs: Option<u64>,
+
+    /// record what every server session served into a SBOM in this dir
+    #[serde(rename = "sbom-dir")]
+    pub sbom_dir: Option<PathBuf>,
+}
+
+impl CacheServerConfig {
+    pub fn read(reader: impl Read) -> anyhow::Result<Self> {
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let file = std::fs::File::open(&path)
+            .map_err(|err| anyhow::anyhow!("can't open config {:?}: {}", path, err))?;
+        Self::read(std::io::BufReader::new(file))
+            .map_err(|err| anyhow::anyhow!("wrong config {:?}: {}", path, err))
+    }
+
+    pub fn listen(&self) -> Vec<SocketAddr> {
+        if self.listen.is_empty() {
+            vec![DEFAULT_LISTEN]
+        } else {
+            self.listen.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_test() {
+        let config = CacheServerConfig::read(
+            r#"{
+                "listen": ["127.0.0.1:8080", "[::1]:8443"],
+                "cache-dir": "/var/cache/gosh",
+                "allowed-hosts": ["github.com"],
+                "allow": ["gosh://*/dao/*"],
+                "deny": ["*/secret"],
+                "upload-token": "s3cret",
+                "tls": {"cert": "/etc/gosh/cert.pem", "key": "/etc/gosh/key.pem"},
+                "max-request-body": 1048576,
+                "request-timeout-secs": 300,
+                "sbom-dir": "/var/lib/gosh-git-server"
+            }"#
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(config.listen().len(), 2);
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/var/cache/gosh")));
+        assert_eq!(config.deny, vec!["*/secret"]);
+        assert_eq!(config.upload_token, Some(UploadToken::new("s3cret")));
+        assert_eq!(config.tls.unwrap().key, PathBuf::from("/etc/gosh/key.pem"));
+        assert_eq!(config.max_request_body, Some(1048576));
+
+        let config = CacheServerConfig::read("{}".as_bytes()).unwrap();
+        assert_eq!(config, CacheServerConfig::default());
+        assert_eq!(config.listen(), vec![DEFAULT_LISTEN]);
+
+        assert!(CacheServerConfig::read(r#"{"listen": "8080"}"#.as_bytes()).is_err());
+        assert!(CacheServerConfig::read(r#"{"allowed_hosts": []}"#.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,295 @@
+use crate::{archive::GZIP, downloads::DownloadMode, proxy::GOSH_HOST};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use git_registry::cargo::{index_path, CargoCache};
+use gosh_sbom::{cargo_crate_component, gosh_classification::GoshClassification, Sbom};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::sync::Mutex;
+
+/// Cargo sparse registry of a build: crates cargo already downloaded on this
+/// machine, only the SBOM ones once it's [`DownloadMode::Locked`] (by
+/// `<name>@<version>`)
+///
+/// e.g. `[source.gosh] registry = "sparse+http://gosh/cargo/index/"`
+#[derive(Debug)]
+pub struct CrateRegistry {
+    /// pins by lowercase `<name>@<version>` if locked
+    pins: Option<BTreeMap<String, String>>,
+    cache: CargoCache,
+    /// why the build isn't reproducible, e.g. a pinned crate changed
+    failures: parking_lot::Mutex<Vec<String>>,
+}
+
+struct CratesState {
+    registry: Arc<CrateRegistry>,
+    sbom: Option<Arc<Mutex<Sbom>>>,
+}
+
+impl CrateRegistry {
+    pub fn new(mode: DownloadMode, cache: CargoCache) -> Self {
+        let pins = match mode {
+            DownloadMode::Record => None,
+            DownloadMode::Locked(pins) => Some(
+                pins.into_iter()
+                    .map(|(crate_version, sha256)| (crate_version.to_ascii_lowercase(), sha256))
+                    .collect(),
+            ),
+        };
+        Self {
+            pins,
+            cache,
+            failures: parking_lot::Mutex::default(),
+        }
+    }
+
+    pub fn failures(&self) -> Vec<String> {
+        self.failures.lock().clone()
+    }
+
+    fn fail(&self, failure: String) {
+        tracing::error!("{}", failure);
+        self.failures.lock().push(failure);
+    }
+
+    fn pin(&self, name: &str, version: &str) -> Option<&String> {
+        let key = format!("{}@{}", name, version).to_ascii_lowercase();
+        self.pins.as_ref()?.get(&key)
+    }
+
+    /// index lines of crate `name` which can be served: the downloaded
+    /// versions, the pinned ones if locked
+    async fn index(&self, name: &str) -> anyhow::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        for (version, entry) in self.cache.index(name).await? {
+            let served = match &self.pins {
+                None => self.cache.crate_path(name, &version).await?.is_some(),
+                Some(_) => match self.pin(name, &version) {
+                    None => false,
+                    Some(sha256) if *sha256 == entry.cksum => true,
+                    Some(sha256) => {
+                        self.fail(format!(
+                            "checksum of crate {} {} changed: {} instead of pinned {}",
+                            name, version, entry.cksum, sha256
+                        ));
+                        false
+                    }
+                },
+            };
+            if served {
+                lines.push(entry.line);
+            }
+        }
+        Ok(lines)
+    }
+}
+
+/// `/cargo/index/` and the `.crate` downloads it points to
+pub fn router(registry: Arc<CrateRegistry>, sbom: Option<Arc<Mutex<Sbom>>>) -> Router {
+    Router::new()
+        .route("/cargo/index/*path", get(index_handler))
+        .route(
+            "/cargo/crates/:name/:version/download",
+            get(download_handler),
+        )
+        .with_state(Arc::new(CratesState { registry, sbom }))
+}
+
+/// `config.json` or the index file of a crate
+async fn index_handler(
+    State(state): State<Arc<CratesState>>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    if path == "config.json" {
+        // downloads go to whatever host cargo reached the index by
+        let host = headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .unwrap_or(GOSH_HOST);
+        let config = serde_json::json!({ "dl": format!("http://{}/cargo/crates", host) });
+        return Ok(axum::Json(config).into_response());
+    }
+
+    let name = path.rsplit('/').next().unwrap_or_default();
+    if index_path(name).ok().as_deref() != Some(path.as_str()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let lines = state.registry.index(name).await.map_err(|error| {
+        tracing::warn!("crate {}: {:?}", name, error);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if lines.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let mut body = lines.join("\n");
+    body.push('\n');
+    Ok(([(header::CONTENT_TYPE, "text/plain")], body).into_response())
+}
+
+/// `.crate` from the cargo cache, pinned into the SBOM
+async fn download_handler(
+    State(state): State<Arc<CratesState>>,
+    Path((name, version)): Path<(String, String)>,
+) -> Result<Response, StatusCode> {
+    let registry = &state.registry;
+    let path = registry
+        .cache
+        .crate_path(&name, &version)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let pin = registry.pin(&name, &version);
+    if registry.pins.is_some() && pin.is_none() {
+        registry.fail(format!(
+            "crate {} {} isn't pinned in the SBOM",
+            name, version
+        ));
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let Some(path) = path else {
+        if pin.is_some() {
+            registry.fail(format!(
+                "crate {} {} isn't in the cargo cache",
+                name, version
+            ));
+        }
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let content = tokio::fs::read(&path)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let sha256 = Sha256::digest(&content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    if let Some(pin) = pin.filter(|pin| **pin != sha256) {
+        registry.fail(format!(
+            "checksum of crate {} {} changed: {} instead of pinned {}",
+            name, version, sha256, pin
+        ));
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+
+    if let Some(sbom) = &state.sbom {
+        sbom.lock().await.append(
+            GoshClassification::CargoCrate,
+            cargo_crate_component(&name, &version, &sha256),
+        );
+    }
+    Ok(([(header::CONTENT_TYPE, GZIP)], content).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CRATE: &[u8] = b"crate content";
+
+    /// cargo home with `glob` 0.3.0 (not downloaded) and 0.3.1 in its caches
+    fn cargo_home(dir: &std::path::Path) -> String {
+        let cksum = Sha256::digest(CRATE)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        let mut index = vec![3, 2, 0, 0, 0, 0];
+        for (version, cksum) in [("0.3.0", "00"), ("0.3.1", cksum.as_str())] {
+            let line = format!(
+                r#"{{"name":"glob","vers":"{}","cksum":"{}"}}"#,
+                version, cksum
+            );
+            index.extend_from_slice(format!("{}\0{}\0", version, line).as_bytes());
+        }
+        let index_dir = dir.join("registry/index/crates-0/.cache/gl/ob");
+        std::fs::create_dir_all(&index_dir).unwrap();
+        std::fs::write(index_dir.join("glob"), index).unwrap();
+        let cache_dir = dir.join("registry/cache/crates-0");
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        std::fs::write(cache_dir.join("glob-0.3.1.crate"), CRATE).unwrap();
+        cksum
+    }
+
+    fn state(mode: DownloadMode, dir: &std::path::Path) -> Arc<CratesState> {
+        Arc::new(CratesState {
+            registry: Arc::new(CrateRegistry::new(mode, CargoCache::new(dir))),
+            sbom: Some(Arc::default()),
+        })
+    }
+
+    async fn index(state: &Arc<CratesState>, path: &str) -> Result<String, StatusCode> {
+        let response = index_handler(
+            State(state.clone()),
+            Path(path.to_owned()),
+            HeaderMap::new(),
+        )
+        .await?;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        Ok(String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn download(state: &Arc<CratesState>, version: &str) -> Result<Vec<u8>, StatusCode> {
+        let path = Path(("glob".to_owned(), version.to_owned()));
+        let response = download_handler(State(state.clone()), path).await?;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        Ok(body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn record_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let cksum = cargo_home(dir.path());
+        let state = state(DownloadMode::Record, dir.path());
+
+        let config = index(&state, "config.json").await.unwrap();
+        assert_eq!(config, r#"{"dl":"http://gosh/cargo/crates"}"#);
+        let lines = index(&state, "gl/ob/glob").await.unwrap();
+        assert_eq!(lines.lines().count(), 1);
+        assert!(lines.contains("0.3.1"));
+        assert_eq!(index(&state, "3/g/glob").await, Err(StatusCode::NOT_FOUND));
+        assert_eq!(
+            index(&state, "se/rd/serde").await,
+            Err(StatusCode::NOT_FOUND)
+        );
+
+        assert_eq!(download(&state, "0.3.1").await.unwrap(), CRATE);
+        assert_eq!(download(&state, "0.3.0").await, Err(StatusCode::NOT_FOUND));
+        let sbom = state.sbom.as_ref().unwrap().lock().await;
+        assert_eq!(
+            sbom.inner,
+            vec![(
+                GoshClassification::CargoCrate,
+                cargo_crate_component("glob", "0.3.1", &cksum)
+            )]
+        );
+        assert!(state.registry.failures().is_empty());
+    }
+
+    #[tokio::test]
+    async fn locked_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let cksum = cargo_home(dir.path());
+        let pins = BTreeMap::from([("glob@0.3.1".to_owned(), cksum)]);
+        let state = self::state(DownloadMode::Locked(pins), dir.path());
+        assert!(index(&state, "gl/ob/glob").await.unwrap().contains("0.3.1"));
+        assert_eq!(download(&state, "0.3.1").await.unwrap(), CRATE);
+        assert_eq!(download(&state, "0.3.0").await, Err(StatusCode::FORBIDDEN));
+        assert_eq!(state.registry.failures().len(), 1);
+
+        let pins = BTreeMap::from([("glob@0.3.1".to_owned(), "00".repeat(32))]);
+        let state = self::state(DownloadMode::Locked(pins), dir.path());
+        assert_eq!(
+            index(&state, "gl/ob/glob").await,
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            download(&state, "0.3.1").await,
+            Err(StatusCode::BAD_GATEWAY)
+        );
+        assert_eq!(state.registry.failures().len(), 2);
+    }
+}
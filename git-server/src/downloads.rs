@@ -0,0 +1,149 @@
+use crate::file_response::IMMUTABLE;
+use axum::{
+    body::StreamBody,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use git_registry::download::{Download, DownloadStore};
+use gosh_sbom::{gosh_classification::GoshClassification, http_download_component, Sbom};
+use gosh_utils::stream::CHUNK_SIZE;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use tokio_util::io::ReaderStream;
+
+/// How the build proxy treats downloads from other hosts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadMode {
+    /// permitted downloads are kept in the store and pinned into the SBOM
+    Record,
+    /// sha256 of the downloads by their urls, pinned by a previous build:
+    /// they're served from the store, the rest of egress is denied
+    Locked(BTreeMap<String, String>),
+}
+
+/// Downloads of a build, reproducible by their [`DownloadMode::Locked`] pins
+#[derive(Debug)]
+pub struct Downloads {
+    mode: DownloadMode,
+    store: DownloadStore,
+    /// why the build isn't reproducible, e.g. a pinned file changed upstream
+    failures: Mutex<Vec<String>>,
+}
+
+impl Downloads {
+    pub fn new(mode: DownloadMode, store: DownloadStore) -> Self {
+        Self {
+            mode,
+            store,
+            failures: Mutex::default(),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        matches!(self.mode, DownloadMode::Locked(_))
+    }
+
+    pub fn failures(&self) -> Vec<String> {
+        self.failures.lock().clone()
+    }
+
+    fn fail(&self, failure: String) {
+        tracing::error!("{}", failure);
+        self.failures.lock().push(failure);
+    }
+
+    /// `url` in the store, downloaded unless it's pinned and already there,
+    /// pinned into `sbom` too
+    pub async fn resolve(
+        &self,
+        url: &str,
+        sbom: Option<&tokio::sync::Mutex<Sbom>>,
+    ) -> Result<Download, StatusCode> {
+        let download = match &self.mode {
+            DownloadMode::Record => self.store.fetch(url, None).await.map_err(|error| {
+                tracing::warn!("{:?}", error);
+                StatusCode::BAD_GATEWAY
+            })?,
+            DownloadMode::Locked(pins) => {
+                let Some(sha256) = pins.get(url) else {
+                    self.fail(format!("download {} isn't pinned in the SBOM", url));
+                    return Err(StatusCode::FORBIDDEN);
+                };
+                match self.store.stored(url, sha256).await {
+                    Some(download) => download,
+                    None => self.store.fetch(url, Some(sha256)).await.map_err(|error| {
+                        self.fail(format!("{:#}", error));
+                        StatusCode::BAD_GATEWAY
+                    })?,
+                }
+            }
+        };
+        if let Some(sbom) = sbom {
+            sbom.lock().await.append(
+                GoshClassification::HttpDownload,
+                http_download_component(&download.url, &download.sha256),
+            );
+        }
+        Ok(download)
+    }
+
+    /// content of `download` from the store, its sha256 is the ETag
+    pub async fn response(&self, download: &Download) -> Result<Response, StatusCode> {
+        let path = self
+            .store
+            .object_path(&download.sha256)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/octet-stream"),
+        );
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(download.size));
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(IMMUTABLE));
+        headers.insert(
+            header::ETAG,
+            HeaderValue::from_str(&format!("\"{}\"", download.sha256))
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        );
+        let body = ReaderStream::with_capacity(file, CHUNK_SIZE);
+        Ok((headers, StreamBody::new(body)).into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    const URL: &str = "http://example.com/release.tar.gz";
+
+    #[tokio::test]
+    async fn locked_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DownloadStore::new(dir.path()).unwrap();
+        let path = store.object_path(SHA256).unwrap();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, b"hello").unwrap();
+        let pins = BTreeMap::from([(URL.to_owned(), SHA256.to_owned())]);
+        let downloads = Downloads::new(DownloadMode::Locked(pins), store);
+        let sbom = tokio::sync::Mutex::new(Sbom::default());
+
+        let download = downloads.resolve(URL, Some(&sbom)).await.unwrap();
+        assert_eq!(download.size, 5);
+        assert_eq!(
+            sbom.lock().await.inner,
+            vec![(
+                GoshClassification::HttpDownload,
+                http_download_component(URL, SHA256)
+            )]
+        );
+        assert!(downloads.failures().is_empty());
+
+        let unpinned = downloads.resolve("http://example.com/other", None).await;
+        assert_eq!(unpinned, Err(StatusCode::FORBIDDEN));
+        assert_eq!(downloads.failures().len(), 1);
+    }
+}
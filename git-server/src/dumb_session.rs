@@ -0,0 +1,457 @@
+use git_registry::git_url::GitUrl;
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    path::Path,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+const SHA1_LEN: usize = 20;
+/// `\377tOc`, pack index v1 has no header
+const IDX_MAGIC: &[u8] = b"\xfftOc";
+const IDX_HEADER_LEN: usize = 8;
+const IDX_FANOUT_LEN: usize = 256 * 4;
+/// a fetch reads objects right after `info/refs`, sessions idle that long are
+/// of clients which gave up
+const SESSION_TTL: Duration = Duration::from_secs(10 * 60);
+const MAX_SESSIONS: usize = 1024;
+
+/// Commits dumb http clients fetched
+///
+/// Dumb clients read `info/refs` first and then the objects of the tips they
+/// want, loose or in packs. A session is a client (by IP, as git opens several
+/// connections) and a repo, it remembers advertised tips which weren't served yet.
+/// A pack holds more tips than the client wants, so from packs only the tips
+/// it asked for are recorded: `HEAD` or those it tried to get loose first.
+/// Sessions idle for [`SESSION_TTL`] expire, past [`MAX_SESSIONS`] the least
+/// recently used ones are dropped
+#[derive(Debug)]
+pub struct DumbSessions {
+    sessions: Mutex<HashMap<(IpAddr, GitUrl), Session>>,
+    ttl: Duration,
+    max_sessions: usize,
+}
+
+impl Default for DumbSessions {
+    fn default() -> Self {
+        Self {
+            sessions: Mutex::default(),
+            ttl: SESSION_TTL,
+            max_sessions: MAX_SESSIONS,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    /// advertised ref to its object
+    refs: HashMap<String, String>,
+    /// tip object (commit or annotated tag) to its commit
+    tips: HashMap<String, String>,
+    /// tips the client asked for
+    wanted: HashSet<String>,
+    used: Instant,
+}
+
+impl DumbSessions {
+    /// `client` asks for `src` of `url`, whether it's there or not: walkers
+    /// request a wanted object loose before looking for it in packs
+    pub async fn requested(&self, client: IpAddr, url: &GitUrl, src: &str) {
+        let Some(object) = loose_object(src) else {
+            return;
+        };
+        let mut sessions = self.sessions.lock().await;
+        if let Some(session) = self.live(&mut sessions, &(client, url.clone())) {
+            if session.tips.contains_key(&object) {
+                session.wanted.insert(object);
+            }
+        }
+    }
+
+    /// commits `client` got for the first time with `src` of `url` stored at `path`
+    pub async fn served(
+        &self,
+        client: IpAddr,
+        url: &GitUrl,
+        src: &str,
+        path: &Path,
+    ) -> anyhow::Result<Vec<String>> {
+        let key = (client, url.clone());
+        if src == "info/refs" {
+            let session = advertised(&tokio::fs::read_to_string(path).await?);
+            self.start(&mut *self.sessions.lock().await, key, session);
+            return Ok(Vec::new());
+        }
+        if src == "HEAD" {
+            let head = tokio::fs::read_to_string(path).await?;
+            let mut sessions = self.sessions.lock().await;
+            let Some(session) = self.live(&mut sessions, &key) else {
+                return Ok(Vec::new());
+            };
+            let head = head.trim();
+            let object = match head.strip_prefix("ref: ") {
+                Some(name) => session.refs.get(name).cloned(),
+                None => Some(head.to_owned()),
+            };
+            if let Some(object) = object.filter(|object| session.tips.contains_key(object)) {
+                session.wanted.insert(object);
+            }
+            return Ok(Vec::new());
+        }
+
+        let objects = if let Some(object) = loose_object(src) {
+            vec![object]
+        } else if src.starts_with("objects/pack/") && src.ends_with(".pack") {
+            let idx = tokio::fs::read(path.with_extension("idx")).await?;
+            let mut sessions = self.sessions.lock().await;
+            let Some(session) = self.live(&mut sessions, &key) else {
+                return Ok(Vec::new());
+            };
+            session
+                .wanted
+                .iter()
+                .filter(|tip| idx_contains(&idx, tip).unwrap_or(false))
+                .cloned()
+                .collect()
+        } else {
+            return Ok(Vec::new());
+        };
+
+        let mut sessions = self.sessions.lock().await;
+        let Some(session) = self.live(&mut sessions, &key) else {
+            return Ok(Vec::new());
+        };
+        let mut commits = objects
+            .iter()
+            .filter_map(|object| {
+                session.wanted.remove(object);
+                session.tips.remove(object)
+            })
+            .collect::<Vec<_>>();
+        commits.sort();
+        commits.dedup();
+        Ok(commits)
+    }
+
+    /// session of `key` unless it expired, it's used now
+    fn live<'a>(
+        &self,
+        sessions: &'a mut HashMap<(IpAddr, GitUrl), Session>,
+        key: &(IpAddr, GitUrl),
+    ) -> Option<&'a mut Session> {
+        let session = sessions.get_mut(key)?;
+        if session.used.elapsed() >= self.ttl {
+            return None;
+        }
+        session.used = Instant::now();
+        Some(session)
+    }
+
+    /// `session` replaces the one of `key`, expired sessions are dropped and
+    /// then the least recently used ones while there are too many
+    fn start(
+        &self,
+        sessions: &mut HashMap<(IpAddr, GitUrl), Session>,
+        key: (IpAddr, GitUrl),
+        session: Session,
+    ) {
+        sessions.remove(&key);
+        sessions.retain(|_, session| session.used.elapsed() < self.ttl);
+        while sessions.len() >= self.max_sessions {
+            let Some(oldest) = sessions
+                .iter()
+                .min_by_key(|(_, session)| session.used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            sessions.remove(&oldest);
+        }
+        sessions.insert(key, session);
+    }
+}
+
+/// `<object>\t<ref>` lines of `info/refs`, annotated tags are followed by
+/// `<commit>\t<ref>^{}`
+fn advertised(info_refs: &str) -> Session {
+    let mut session = Session {
+        refs: HashMap::new(),
+        tips: HashMap::new(),
+        wanted: HashSet::new(),
+        used: Instant::now(),
+    };
+    for line in info_refs.lines() {
+        let Some((object, name)) = line.split_once('\t') else {
+            continue;
+        };
+        match name.strip_suffix("^{}") {
+            Some(tag) => {
+                if let Some(tag_object) = session.refs.get(tag) {
+                    session.tips.insert(tag_object.clone(), object.to_owned());
+                }
+            }
+            None => {
+                session.refs.insert(name.to_owned(), object.to_owned());
+                session.tips.insert(object.to_owned(), object.to_owned());
+            }
+        }
+    }
+    session
+}
+
+/// `objects/ab/cdef...` to `abcdef...`
+pub(crate) fn loose_object(src: &str) -> Option<String> {
+    let ["objects", dir, file] = src.split('/').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    (dir.len() == 2 && file.len() == SHA1_LEN * 2 - 2).then(|| format!("{}{}", dir, file))
+}
+
+/// `object` is in the pack of the index v2 `idx`: sorted names follow the fanout
+fn idx_contains(idx: &[u8], object: &str) -> anyhow::Result<bool> {
+    if !idx.starts_with(IDX_MAGIC) || idx.get(4..IDX_HEADER_LEN) != Some(&[0, 0, 0, 2]) {
+        anyhow::bail!("unsupported pack index version");
+    }
+    let fanout_end = IDX_HEADER_LEN + IDX_FANOUT_LEN;
+    let Some(count) = idx.get(fanout_end - 4..fanout_end) else {
+        anyhow::bail!("pack index is truncated");
+    };
+    let count = u32::from_be_bytes(count.try_into()?) as usize;
+    let Some(names) = idx.get(fanout_end..fanout_end + count * SHA1_LEN) else {
+        anyhow::bail!("pack index is truncated");
+    };
+
+    let Some(object) = decode_hex(object) else {
+        return Ok(false);
+    };
+    Ok(names
+        .chunks_exact(SHA1_LEN)
+        .collect::<Vec<_>>()
+        .binary_search(&object.as_slice())
+        .is_ok())
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() != SHA1_LEN * 2 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git_registry::test_util::{commit_file, git, init};
+
+    /// `objects/pack/<name>.pack` of the only pack in `git_dir`
+    fn pack(git_dir: &Path) -> String {
+        let pack = std::fs::read_dir(git_dir.join("objects/pack"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .find(|name| name.ends_with(".pack"))
+            .unwrap();
+        format!("objects/pack/{}", pack)
+    }
+
+    #[test]
+    fn advertised_test() {
+        let commit = "a".repeat(40);
+        let tag = "b".repeat(40);
+        let info_refs = format!(
+            "{0}\trefs/heads/main\n{1}\trefs/tags/v1\n{0}\trefs/tags/v1^{{}}\n",
+            commit, tag
+        );
+        let session = advertised(&info_refs);
+        assert_eq!(session.tips.len(), 2);
+        assert_eq!(session.tips[&commit], commit);
+        assert_eq!(session.tips[&tag], commit);
+        assert_eq!(session.refs["refs/tags/v1"], tag);
+    }
+
+    #[test]
+    fn loose_object_test() {
+        let object = format!("ab/{}", "c".repeat(38));
+        assert_eq!(
+            loose_object(&format!("objects/{}", object)),
+            Some(format!("ab{}", "c".repeat(38)))
+        );
+        assert_eq!(loose_object("objects/info/packs"), None);
+        assert_eq!(loose_object("info/refs"), None);
+    }
+
+    #[tokio::test]
+    async fn served_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        init(path);
+        let first = commit_file(path, "README.md", "# repo\n", "first");
+        git(path, &["tag", "-a", "v1", "-m", "v1"]);
+        let tag = git(path, &["rev-parse", "v1"]);
+        git(path, &["repack", "-a", "-d", "--quiet"]);
+        let second = commit_file(path, "README.md", "# repo 2\n", "second");
+        git(path, &["update-server-info"]);
+
+        let git_dir = path.join(".git");
+        let url: GitUrl = "file:///srv/git/repo".parse().unwrap();
+        let client = IpAddr::from([127, 0, 0, 1]);
+        let sessions = DumbSessions::default();
+
+        let served = sessions
+            .served(client, &url, "info/refs", &git_dir.join("info/refs"))
+            .await
+            .unwrap();
+        assert!(served.is_empty());
+
+        // the second commit is loose
+        let loose = format!("objects/{}/{}", &second[..2], &second[2..]);
+        let served = sessions
+            .served(client, &url, &loose, &git_dir.join(&loose))
+            .await
+            .unwrap();
+        assert_eq!(served, vec![second.clone()]);
+
+        // the first one and its tag are packed, the tag is asked for loose first
+        sessions
+            .requested(
+                client,
+                &url,
+                &format!("objects/{}/{}", &tag[..2], &tag[2..]),
+            )
+            .await;
+        let pack = pack(&git_dir);
+        let served = sessions
+            .served(client, &url, &pack, &git_dir.join(&pack))
+            .await
+            .unwrap();
+        assert_eq!(served, vec![first]);
+
+        // already recorded
+        let served = sessions
+            .served(client, &url, &loose, &git_dir.join(&loose))
+            .await
+            .unwrap();
+        assert!(served.is_empty());
+
+        // other clients have their own sessions
+        let served = sessions
+            .served(
+                IpAddr::from([10, 0, 0, 1]),
+                &url,
+                &loose,
+                &git_dir.join(&loose),
+            )
+            .await
+            .unwrap();
+        assert!(served.is_empty());
+    }
+
+    #[tokio::test]
+    async fn served_pack_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        init(path);
+        let main = commit_file(path, "README.md", "# repo\n", "first");
+        git(path, &["checkout", "--quiet", "-b", "feature"]);
+        let feature = commit_file(path, "README.md", "# feature\n", "feature");
+        git(path, &["checkout", "--quiet", "main"]);
+        git(path, &["repack", "-a", "-d", "--quiet"]);
+        git(path, &["update-server-info"]);
+
+        let git_dir = path.join(".git");
+        let url: GitUrl = "file:///srv/git/repo".parse().unwrap();
+        let pack = pack(&git_dir);
+
+        // a clone resolves HEAD, the feature branch is in the pack too
+        let client = IpAddr::from([127, 0, 0, 1]);
+        let sessions = DumbSessions::default();
+        for src in ["info/refs", "HEAD"] {
+            let served = sessions
+                .served(client, &url, src, &git_dir.join(src))
+                .await
+                .unwrap();
+            assert!(served.is_empty());
+        }
+        let served = sessions
+            .served(client, &url, &pack, &git_dir.join(&pack))
+            .await
+            .unwrap();
+        assert_eq!(served, vec![main.clone()]);
+
+        // a fetch of the feature branch asks for its tip loose first
+        let client = IpAddr::from([10, 0, 0, 1]);
+        sessions
+            .served(client, &url, "info/refs", &git_dir.join("info/refs"))
+            .await
+            .unwrap();
+        let loose = format!("objects/{}/{}", &feature[..2], &feature[2..]);
+        sessions.requested(client, &url, &loose).await;
+        let served = sessions
+            .served(client, &url, &pack, &git_dir.join(&pack))
+            .await
+            .unwrap();
+        assert_eq!(served, vec![feature]);
+    }
+
+    #[tokio::test]
+    async fn expiry_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        init(path);
+        let commit = commit_file(path, "README.md", "# repo\n", "first");
+        git(path, &["update-server-info"]);
+        let git_dir = path.join(".git");
+        let info_refs = git_dir.join("info/refs");
+        let loose = format!("objects/{}/{}", &commit[..2], &commit[2..]);
+        let client = IpAddr::from([127, 0, 0, 1]);
+        let urls = [
+            "file:///srv/git/a",
+            "file:///srv/git/b",
+            "file:///srv/git/c",
+        ]
+        .map(|url| url.parse::<GitUrl>().unwrap());
+
+        // the least recently used session is dropped for the third one
+        let sessions = DumbSessions {
+            max_sessions: 2,
+            ..DumbSessions::default()
+        };
+        for url in &urls {
+            sessions
+                .served(client, url, "info/refs", &info_refs)
+                .await
+                .unwrap();
+        }
+        assert_eq!(sessions.sessions.lock().await.len(), 2);
+        for (url, is_live) in urls.iter().zip([false, true, true]) {
+            let served = sessions
+                .served(client, url, &loose, &git_dir.join(&loose))
+                .await
+                .unwrap();
+            assert_eq!(served.contains(&commit), is_live, "{}", url);
+        }
+
+        // idle sessions expire
+        let sessions = DumbSessions {
+            ttl: Duration::ZERO,
+            ..DumbSessions::default()
+        };
+        sessions
+            .served(client, &urls[0], "info/refs", &info_refs)
+            .await
+            .unwrap();
+        let served = sessions
+            .served(client, &urls[0], &loose, &git_dir.join(&loose))
+            .await
+            .unwrap();
+        assert!(served.is_empty());
+        sessions
+            .served(client, &urls[1], "info/refs", &info_refs)
+            .await
+            .unwrap();
+        assert_eq!(sessions.sessions.lock().await.len(), 1);
+    }
+}
@@ -0,0 +1,119 @@
+use glob::{MatchOptions, Pattern};
+use parking_lot::Mutex;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// Hosts builds may reach through the proxy: glob patterns matched against
+/// the host, e.g. `*.crates.io`, or against `host:port` if they have a port
+///
+/// denied hosts are never reached, the rest have to be allowed unless the
+/// allowlist is empty
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EgressPolicy {
+    allow: Vec<Pattern>,
+    deny: Vec<Pattern>,
+}
+
+impl EgressPolicy {
+    pub fn new(
+        allow: impl IntoIterator<Item = impl AsRef<str>>,
+        deny: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            allow: patterns(allow)?,
+            deny: patterns(deny)?,
+        })
+    }
+
+    pub fn permits(&self, host: &str, port: u16) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let authority = format!("{}:{}", host, port);
+        let matches = |pattern: &Pattern| {
+            let target = if pattern.as_str().contains(':') && !host.contains(':') {
+                &authority
+            } else {
+                host
+            };
+            pattern.matches_with(target, MATCH_OPTIONS)
+        };
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+}
+
+fn patterns(hosts: impl IntoIterator<Item = impl AsRef<str>>) -> anyhow::Result<Vec<Pattern>> {
+    hosts
+        .into_iter()
+        .map(|hosts| {
+            Pattern::new(hosts.as_ref())
+                .map_err(|err| anyhow::anyhow!("wrong hosts pattern `{}`: {}", hosts.as_ref(), err))
+        })
+        .collect()
+}
+
+/// An attempt of a build to reach a host which isn't gosh
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Egress {
+    /// `CONNECT` for tunnels (https), the request method otherwise
+    pub method: String,
+    /// `host:port` of tunnels, the absolute url otherwise
+    pub target: String,
+    pub allowed: bool,
+    /// response status, 502 if the host couldn't be reached
+    pub status: u16,
+}
+
+/// Every egress of a build, in order, for the build report
+#[derive(Debug, Default)]
+pub struct EgressLog {
+    entries: Mutex<Vec<Egress>>,
+}
+
+impl EgressLog {
+    pub fn record(&self, egress: Egress) {
+        if egress.allowed {
+            tracing::info!(
+                "egress: {} {} {}",
+                egress.method,
+                egress.target,
+                egress.status
+            );
+        } else {
+            tracing::warn!("egress denied: {} {}", egress.method, egress.target);
+        }
+        self.entries.lock().push(egress);
+    }
+
+    pub fn entries(&self) -> Vec<Egress> {
+        self.entries.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permits_test() {
+        let open = EgressPolicy::default();
+        assert!(open.permits("example.com", 443));
+
+        let policy =
+            EgressPolicy::new(["*.crates.io", "github.com:443"], ["static.crates.io"]).unwrap();
+        assert!(policy.permits("index.crates.io", 443));
+        assert!(policy.permits("INDEX.crates.io", 80));
+        assert!(!policy.permits("static.crates.io", 443));
+        assert!(policy.permits("github.com", 443));
+        assert!(!policy.permits("github.com", 80));
+        assert!(!policy.permits("example.com", 443));
+
+        let closed = EgressPolicy::new(Vec::<String>::new(), ["*"]).unwrap();
+        assert!(!closed.permits("example.com", 80));
+        assert!(!closed.permits("[::1]", 80));
+
+        assert!(EgressPolicy::new(["["], Vec::<String>::new()).is_err());
+    }
+}
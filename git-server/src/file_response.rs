@@ -0,0 +1,310 @@
+use crate::dumb_session::loose_object;
+use axum::{
+    body::StreamBody,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use gosh_utils::stream::CHUNK_SIZE;
+use std::{io::SeekFrom, path::Path};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+
+/// objects and packs are named by their hash, same as `git http-backend`
+pub(crate) const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+pub(crate) const MUTABLE: &str = "no-cache, max-age=0, must-revalidate";
+
+pub const LOOSE_OBJECT: &str = "application/x-git-loose-object";
+pub const PACK: &str = "application/x-git-packed-objects";
+const PACK_INDEX: &str = "application/x-git-packed-objects-toc";
+
+/// content type and strong ETag of the dumb protocol file `src`, only
+/// immutable files have an ETag
+fn describe(src: &str) -> (&'static str, Option<String>) {
+    if let Some(object) = loose_object(src) {
+        return (LOOSE_OBJECT, Some(format!("\"{}\"", object)));
+    }
+    if let Some(pack) = src.strip_prefix("objects/pack/") {
+        if pack.ends_with(".pack") {
+            return (PACK, Some(format!("\"{}\"", pack)));
+        }
+        if pack.ends_with(".idx") {
+            return (PACK_INDEX, Some(format!("\"{}\"", pack)));
+        }
+    }
+    match src {
+        "objects/info/packs" => ("text/plain; charset=utf-8", None),
+        _ => ("text/plain", None),
+    }
+}
+
+/// `Range` of a file of `len` bytes
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// no range, or one which is ignored: malformed or multipart
+    Full,
+    /// inclusive bounds
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+fn byte_range(range: Option<&str>, len: u64) -> ByteRange {
+    let Some(spec) = range.and_then(|range| range.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let (start, end) = (start.trim(), end.trim());
+    // `-<suffix length>`
+    if start.is_empty() {
+        return match end.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial(len.saturating_sub(suffix), len - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+    let Ok(start) = start.parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    if end.is_empty() {
+        return ByteRange::Partial(start, len - 1);
+    }
+    match end.parse::<u64>() {
+        Ok(end) if start <= end => ByteRange::Partial(start, end.min(len - 1)),
+        _ => ByteRange::Full,
+    }
+}
+
+/// `If-None-Match` lists `etag` or is `*`
+pub(crate) fn none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || tag.trim() == etag)
+}
+
+/// file `path` of the dumb protocol `src` streamed from disk, with `Range`
+/// and `If-None-Match` of `headers` handled
+pub async fn file_response(
+    src: &str,
+    path: &Path,
+    headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    let mut file = File::open(path).await.map_err(|error| match error.kind() {
+        // e.g. loose object which is packed, dumb clients try packs next
+        std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+    let metadata = file
+        .metadata()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if metadata.is_dir() {
+        tracing::debug!("requested directory: {:?}", path);
+        // TODO: handle directory listing but we don't have to
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let len = metadata.len();
+
+    let (content_type, etag) = describe(src);
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    match &etag {
+        Some(etag) => {
+            response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(IMMUTABLE));
+            response_headers.insert(
+                header::ETAG,
+                HeaderValue::from_str(etag).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+            );
+            if none_match(headers, etag) {
+                return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+            }
+        }
+        None => {
+            response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(MUTABLE));
+        }
+    }
+
+    // resumed downloads only make sense when the file can't have changed
+    let if_range = headers
+        .get(header::IF_RANGE)
+        .map(|value| Some(value.as_bytes()) == etag.as_deref().map(str::as_bytes));
+    let range = match if_range {
+        Some(false) => ByteRange::Full,
+        _ => byte_range(
+            headers
+                .get(header::RANGE)
+                .and_then(|value| value.to_str().ok()),
+            len,
+        ),
+    };
+
+    let (status, start, body_len) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, len),
+        ByteRange::Partial(start, end) => {
+            response_headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, len))
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+            );
+            (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+        }
+        ByteRange::Unsatisfiable => {
+            response_headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{}", len))
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+            );
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, response_headers).into_response());
+        }
+    };
+    response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body_len));
+
+    if start > 0 {
+        file.seek(SeekFrom::Start(start))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    let body = ReaderStream::with_capacity(file.take(body_len), CHUNK_SIZE);
+    Ok((status, response_headers, StreamBody::new(body)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_test() {
+        let object = format!("objects/ab/{}", "c".repeat(38));
+        assert_eq!(
+            describe(&object),
+            (LOOSE_OBJECT, Some(format!("\"ab{}\"", "c".repeat(38))))
+        );
+        assert_eq!(
+            describe("objects/pack/pack-0123.pack"),
+            (PACK, Some("\"pack-0123.pack\"".to_owned()))
+        );
+        assert_eq!(
+            describe("objects/pack/pack-0123.idx"),
+            (PACK_INDEX, Some("\"pack-0123.idx\"".to_owned()))
+        );
+        assert_eq!(describe("info/refs"), ("text/plain", None));
+        assert_eq!(
+            describe("objects/info/packs"),
+            ("text/plain; charset=utf-8", None)
+        );
+    }
+
+    #[test]
+    fn byte_range_test() {
+        let cases = [
+            (None, ByteRange::Full),
+            (Some("bytes=0-99"), ByteRange::Partial(0, 99)),
+            (Some("bytes=10-"), ByteRange::Partial(10, 99)),
+            (Some("bytes=90-200"), ByteRange::Partial(90, 99)),
+            (Some("bytes=-10"), ByteRange::Partial(90, 99)),
+            (Some("bytes=-200"), ByteRange::Partial(0, 99)),
+            (Some("bytes=100-"), ByteRange::Unsatisfiable),
+            (Some("bytes=-0"), ByteRange::Unsatisfiable),
+            (Some("bytes=0-1,5-6"), ByteRange::Full),
+            (Some("bytes=5-1"), ByteRange::Full),
+            (Some("items=0-1"), ByteRange::Full),
+            (Some("bytes=a-b"), ByteRange::Full),
+        ];
+        for (range, expected) in cases {
+            assert_eq!(byte_range(range, 100), expected, "{:?}", range);
+        }
+        assert_eq!(byte_range(Some("bytes=-1"), 0), ByteRange::Unsatisfiable);
+    }
+
+    async fn get(src: &str, path: &Path, headers: &[(header::HeaderName, &str)]) -> Response {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(name.clone(), HeaderValue::from_str(value).unwrap());
+        }
+        file_response(src, path, &map).await.unwrap()
+    }
+
+    async fn body(response: Response) -> Vec<u8> {
+        hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn file_response_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack-0123.pack");
+        let content = (0..=255).cycle().take(1000).collect::<Vec<u8>>();
+        std::fs::write(&path, &content).unwrap();
+        let src = "objects/pack/pack-0123.pack";
+
+        let response = get(src, &path, &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], PACK);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "1000");
+        assert_eq!(response.headers()[header::ETAG], "\"pack-0123.pack\"");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], IMMUTABLE);
+        assert_eq!(body(response).await, content);
+
+        let response = get(src, &path, &[(header::RANGE, "bytes=900-")]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            "bytes 900-999/1000"
+        );
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "100");
+        assert_eq!(body(response).await, &content[900..]);
+
+        let response = get(
+            src,
+            &path,
+            &[
+                (header::RANGE, "bytes=900-"),
+                (header::IF_RANGE, "\"pack-4567.pack\""),
+            ],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = get(src, &path, &[(header::RANGE, "bytes=1000-")]).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */1000");
+
+        let response = get(src, &path, &[(header::IF_NONE_MATCH, "\"pack-0123.pack\"")]).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert!(body(response).await.is_empty());
+
+        let response = get("info/refs", &path, &[(header::IF_NONE_MATCH, "*")]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], MUTABLE);
+        assert!(response.headers().get(header::ETAG).is_none());
+
+        assert_eq!(
+            file_response(src, &dir.path().join("missing"), &HeaderMap::new())
+                .await
+                .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            file_response(src, dir.path(), &HeaderMap::new())
+                .await
+                .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
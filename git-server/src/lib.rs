@@ -1,77 +1,640 @@
+pub mod access;
+mod archive;
+pub mod config;
+pub mod crates;
 mod directory;
+pub mod downloads;
+mod dumb_session;
+pub mod egress;
+mod file_response;
+mod metrics;
+pub mod proxy;
+mod remote_path;
+pub mod tls;
 
-use axum::http::StatusCode;
+use access::RepoAccess;
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::{
-    extract::{Path, State},
-    routing::get,
-    Router,
+    body::StreamBody,
+    extract::connect_info::IntoMakeServiceWithConnectInfo,
+    extract::{ConnectInfo, Path, Query, RawBody, State},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router, ServiceExt,
+};
+use crates::CrateRegistry;
+use downloads::Downloads;
+use dumb_session::DumbSessions;
+use egress::{EgressLog, EgressPolicy};
+use git_registry::{
+    error::GitRegistryError,
+    git_url::GitUrl,
+    gosh_url::GoshUrl,
+    registry::GitCacheRegistry,
+    upload_pack,
+    upstream::{UploadToken, UPLOAD_PREFIX},
 };
-use git_registry::registry::GitCacheRegistry;
 use gosh_sbom::{gosh_classification::GoshClassification, Sbom};
-use hyper::body::Bytes;
-use std::{net::SocketAddr, sync::Arc};
-use tokio::{fs::File, io::AsyncReadExt, sync::Mutex};
-use tower_http::compression::CompressionLayer;
+use gosh_utils::stream::ByteStream;
+use hyper::body::{Bytes, HttpBody};
+use proxy::{ProxyService, ProxyState};
+use std::{
+    io::Read,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{fs::File, io::AsyncWriteExt, sync::Mutex};
+use tower_http::{
+    compression::{
+        predicate::{DefaultPredicate, NotForContentType, Predicate},
+        CompressionLayer,
+    },
+    timeout::TimeoutLayer,
+};
+
+/// upload-pack requests are haves and wants, much smaller than this unpacked
+const MAX_UPLOAD_PACK_REQUEST: u64 = 64 * 1024 * 1024;
 
 struct GitServerState {
     pub sbom: Option<Arc<Mutex<Sbom>>>,
     pub git_registry: Arc<GitCacheRegistry>,
+    pub dumb_sessions: DumbSessions,
+    pub repos: RepoAccess,
+    pub max_request_body: Option<u64>,
+    pub upload_token: Option<UploadToken>,
 }
 
+impl GitServerState {
+    fn is_allowed(&self, git_url: &GitUrl) -> bool {
+        self.git_registry.is_allowed(git_url) && self.repos.permits(git_url)
+    }
+}
+
+/// [`cache_router`] settings
+#[derive(Debug, Default)]
+pub struct CacheServerOptions {
+    /// take git bundles from `GitCacheRegistry::with_upstream` clients which
+    /// send this bearer token, none are taken without it
+    pub upload_token: Option<UploadToken>,
+    /// repos which are served on top of the registry's allowed hosts
+    pub repos: RepoAccess,
+    /// record served repos and commits
+    pub sbom: Option<Arc<Mutex<Sbom>>>,
+    /// request bodies over this get 413
+    pub max_request_body: Option<u64>,
+    /// requests which aren't answered in time get 408
+    pub request_timeout: Option<Duration>,
+}
+
+/// `?service=` of smart http `info/refs`
+#[derive(Debug, serde::Deserialize)]
+struct InfoRefsQuery {
+    service: Option<String>,
+}
+
+/// dumb protocol files and the smart http `git-upload-pack` service of
+/// `/<contract>/<dao>/<repo>` gosh repos and `/git/<scheme>/<repo>` remotes,
+/// tarballs and raw files of gosh commits for plain http clients, probes and
+/// metrics
+fn routes() -> Router<Arc<GitServerState>> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics::metrics_handler))
+        .route(
+            "/archive/:contract/:dao/:repo/:archive",
+            get(archive::archive_handler),
+        )
+        .route(
+            "/raw/:contract/:dao/:repo/:commit/*path",
+            get(archive::raw_handler),
+        )
+        .route(
+            "/:contract/:dao/:repo/*src",
+            get(handler).post(upload_pack_handler),
+        )
+        .route(
+            "/git/*path",
+            get(remote_handler).post(remote_upload_pack_handler),
+        )
+}
+
+/// bound before it returns, so `/readyz` answers once the server is spawned
 pub fn server(
     addr: SocketAddr,
     sbom: Option<Arc<Mutex<Sbom>>>,
     git_registry: Arc<GitCacheRegistry>,
-) -> hyper::Server<hyper::server::conn::AddrIncoming, axum::routing::IntoMakeService<Router>> {
-    let shared_state = Arc::new(GitServerState { sbom, git_registry });
-    let router = Router::new()
-        .route("/:contract/:dao/:repo/*src", get(handler))
-        .with_state(shared_state)
-        .layer(CompressionLayer::new());
+) -> anyhow::Result<
+    hyper::Server<
+        hyper::server::conn::AddrIncoming,
+        IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    >,
+> {
+    let (router, shared_state) = builder_router(sbom, git_registry);
+    let router = router.layer(middleware::from_fn_with_state(shared_state, metrics::track));
+
+    Ok(axum::Server::try_bind(&addr)?
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>()))
+}
+
+/// [`server`] which is the http forward proxy of a build too: requests to
+/// `http://gosh/...` (or the server itself) are served, `CONNECT` tunnels and
+/// requests to other hosts are passed on per `egress` and logged to
+/// `egress_log`, files they download are kept and pinned by `downloads`, cargo
+/// gets `crates` at `http://gosh/cargo/index/`
+///
+/// tunnels aren't pinned, locked builds deny them: https files are pinned when
+/// they come through `http://gosh/download/<url>`
+pub fn proxy_server(
+    addr: SocketAddr,
+    sbom: Option<Arc<Mutex<Sbom>>>,
+    git_registry: Arc<GitCacheRegistry>,
+    egress: EgressPolicy,
+    egress_log: Arc<EgressLog>,
+    downloads: Arc<Downloads>,
+    crates: Arc<CrateRegistry>,
+) -> anyhow::Result<
+    hyper::Server<
+        hyper::server::conn::AddrIncoming,
+        IntoMakeServiceWithConnectInfo<ProxyService, SocketAddr>,
+    >,
+> {
+    let (router, shared_state) = builder_router(sbom.clone(), git_registry);
+    let router = router
+        .merge(crates::router(crates, sbom.clone()))
+        .layer(middleware::from_fn_with_state(shared_state, metrics::track));
+    let state = ProxyState::new(addr, egress, egress_log, downloads, sbom);
+    let proxy = ProxyService::new(router, state);
+
+    Ok(axum::Server::try_bind(&addr)?
+        .serve(proxy.into_make_service_with_connect_info::<SocketAddr>()))
+}
+
+fn builder_router(
+    sbom: Option<Arc<Mutex<Sbom>>>,
+    git_registry: Arc<GitCacheRegistry>,
+) -> (Router, Arc<GitServerState>) {
+    let shared_state = Arc::new(GitServerState {
+        sbom,
+        git_registry,
+        dumb_sessions: DumbSessions::default(),
+        repos: RepoAccess::default(),
+        max_request_body: None,
+        upload_token: None,
+    });
+    let router = routes()
+        .with_state(shared_state.clone())
+        .layer(compression());
+    (router, shared_state)
+}
+
+/// Team cache: [`server`] routes which also take git bundles from
+/// `GitCacheRegistry::with_upstream` clients when `upload_token` is set,
+/// serve it on as many listeners as needed
+///
+/// uploaded objects are checked by `git fsck` and only their pinned commits
+/// are taken, branches and tags come from the server's own fetches
+pub fn cache_router(git_registry: Arc<GitCacheRegistry>, options: CacheServerOptions) -> Router {
+    let accepts_uploads = options.upload_token.is_some();
+    let shared_state = Arc::new(GitServerState {
+        sbom: options.sbom,
+        git_registry,
+        dumb_sessions: DumbSessions::default(),
+        repos: options.repos,
+        max_request_body: options.max_request_body,
+        upload_token: options.upload_token,
+    });
+    let mut router = routes();
+    if accepts_uploads {
+        router = router.route(&format!("/{}/*path", UPLOAD_PREFIX), post(upload_handler));
+    }
+    let mut router = router.with_state(shared_state.clone()).layer(compression());
+    if let Some(request_timeout) = options.request_timeout {
+        router = router.layer(TimeoutLayer::new(request_timeout));
+    }
+    router.layer(middleware::from_fn_with_state(shared_state, metrics::track))
+}
+
+/// text resources are compressed, objects, packs and tarballs already are
+fn compression() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(
+        DefaultPredicate::new()
+            .and(NotForContentType::const_new(file_response::LOOSE_OBJECT))
+            .and(NotForContentType::const_new(file_response::PACK))
+            .and(NotForContentType::const_new(archive::GZIP))
+            .and(NotForContentType::const_new(archive::ZSTD)),
+    )
+}
+
+/// the process is up
+async fn healthz() -> &'static str {
+    "ok"
+}
 
-    axum::Server::bind(&addr).serve(router.into_make_service())
+/// the registry can serve repos
+async fn readyz(State(state): State<Arc<GitServerState>>) -> Result<&'static str, StatusCode> {
+    state.git_registry.check_ready().await.map_err(|error| {
+        tracing::warn!("not ready: {:?}", error);
+        StatusCode::SERVICE_UNAVAILABLE
+    })?;
+    Ok("ok")
 }
 
 async fn handler(
     State(state): State<Arc<GitServerState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
     Path((contract, dao, repo, src)): Path<(String, String, String, String)>,
-) -> Result<Bytes, StatusCode> {
-    let gosh_url = format!("gosh://{contract}/{dao}/{repo}");
+    Query(query): Query<InfoRefsQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
     tracing::info!(?contract, ?dao, ?repo, ?src);
+    let gosh_url = GoshUrl::new(&contract, &dao, &repo).map_err(|error| {
+        tracing::warn!("{}", error);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    match query.service {
+        Some(service) => advertise(&state, gosh_url.into(), &src, &service, &headers).await,
+        None => serve(&state, client.ip(), gosh_url.into(), &src, &headers).await,
+    }
+}
+
+/// non-gosh remotes: `/git/<scheme>/<repo>/<src>`
+async fn remote_handler(
+    State(state): State<Arc<GitServerState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Path(path): Path<String>,
+    Query(query): Query<InfoRefsQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    tracing::info!(?path);
+    let (git_url, src) = remote_path::split_remote_path(&path).map_err(|error| {
+        tracing::warn!("{}", error);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    match query.service {
+        Some(service) => advertise(&state, git_url, src, &service, &headers).await,
+        None => serve(&state, client.ip(), git_url, src, &headers).await,
+    }
+}
+
+async fn upload_pack_handler(
+    State(state): State<Arc<GitServerState>>,
+    Path((contract, dao, repo, src)): Path<(String, String, String, String)>,
+    headers: HeaderMap,
+    RawBody(body): RawBody,
+) -> Result<Response, StatusCode> {
+    tracing::info!(?contract, ?dao, ?repo, ?src, "upload-pack");
+    let gosh_url = GoshUrl::new(&contract, &dao, &repo).map_err(|error| {
+        tracing::warn!("{}", error);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    upload_pack(&state, gosh_url.into(), &src, &headers, body).await
+}
+
+async fn remote_upload_pack_handler(
+    State(state): State<Arc<GitServerState>>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    RawBody(body): RawBody,
+) -> Result<Response, StatusCode> {
+    tracing::info!(?path, "upload-pack");
+    let (git_url, src) = remote_path::split_remote_path(&path).map_err(|error| {
+        tracing::warn!("{}", error);
+        StatusCode::BAD_REQUEST
+    })?;
+
+    upload_pack(&state, git_url, src, &headers, body).await
+}
+
+/// git bundle for the repo at `path`, see [`cache_router`]
+async fn upload_handler(
+    State(state): State<Arc<GitServerState>>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    RawBody(mut body): RawBody,
+) -> Result<StatusCode, StatusCode> {
+    tracing::info!(?path, "upload");
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .zip(state.upload_token.as_ref())
+        .map(|(authorization, token)| token.authorizes(authorization))
+        .unwrap_or(false);
+    if !authorized {
+        tracing::warn!("upload {}: wrong or missing token", path);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let git_url = remote_path::split_repo_path(&path).map_err(|error| {
+        tracing::warn!("{}", error);
+        StatusCode::BAD_REQUEST
+    })?;
+    if !state.is_allowed(&git_url) {
+        tracing::warn!("remote is not allowed: {}", git_url);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let internal = |error: &dyn std::fmt::Debug| {
+        tracing::warn!("upload {}: {:?}", path, error);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let dir = tempfile::tempdir().map_err(|error| internal(&error))?;
+    let bundle = dir.path().join("upload.bundle");
+    let mut file = File::create(&bundle)
+        .await
+        .map_err(|error| internal(&error))?;
+    let mut len = 0;
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|error| {
+            tracing::warn!("upload {}: {}", path, error);
+            StatusCode::BAD_REQUEST
+        })?;
+        len += chunk.len();
+        check_body_limit(len, state.max_request_body)?;
+        file.write_all(&chunk)
+            .await
+            .map_err(|error| internal(&error))?;
+    }
+    file.sync_all().await.map_err(|error| internal(&error))?;
 
     state
         .git_registry
-        .update_server_info(&gosh_url)
+        .import_upload(&git_url, &bundle)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|error| match GitRegistryError::of(&error) {
+            Some(_) => error_status(error),
+            None => {
+                // mostly broken or malicious bundles
+                tracing::warn!("upload {}: {:?}", git_url, error);
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+        })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// smart http `info/refs?service=git-upload-pack`
+async fn advertise(
+    state: &GitServerState,
+    git_url: GitUrl,
+    src: &str,
+    service: &str,
+    headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    if src != "info/refs" {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if service != upload_pack::SERVICE {
+        tracing::warn!("service is not supported: {}", service);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if !state.is_allowed(&git_url) {
+        tracing::warn!("remote is not allowed: {}", git_url);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let advertisement = state
+        .git_registry
+        .upload_pack(&git_url, true, git_protocol(headers), Bytes::new())
+        .await
+        .map_err(error_status)?;
+    Ok(smart_response(
+        "application/x-git-upload-pack-advertisement",
+        advertisement.body,
+    ))
+}
+
+/// smart http `POST git-upload-pack`, wanted commits go to the SBOM
+async fn upload_pack(
+    state: &GitServerState,
+    git_url: GitUrl,
+    src: &str,
+    headers: &HeaderMap,
+    body: hyper::Body,
+) -> Result<Response, StatusCode> {
+    if src != upload_pack::SERVICE {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if !state.is_allowed(&git_url) {
+        tracing::warn!("remote is not allowed: {}", git_url);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let request = read_request(headers, body, state.max_request_body).await?;
+    if let Err(error) = upload_pack::wants(&request) {
+        tracing::warn!("upload-pack request of {}: {}", git_url, error);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let upload_pack = state
+        .git_registry
+        .upload_pack(&git_url, false, git_protocol(headers), request)
+        .await
+        .map_err(error_status)?;
+
+    if let Some(ref s) = state.sbom {
+        let mut sbom = s.lock().await;
+        for commit in &upload_pack.commits {
+            sbom.append(
+                GoshClassification::Commit,
+                format!("{}:{}", git_url, commit),
+            );
+        }
+    };
+
+    Ok(smart_response(
+        "application/x-git-upload-pack-result",
+        upload_pack.body,
+    ))
+}
+
+fn git_protocol(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("git-protocol")
+        .and_then(|value| value.to_str().ok())
+}
+
+/// request body up to `limit` bytes, git gzips the large ones
+async fn read_request(
+    headers: &HeaderMap,
+    mut body: hyper::Body,
+    limit: Option<u64>,
+) -> Result<Bytes, StatusCode> {
+    let limit = request_limit(limit);
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|error| {
+            tracing::warn!("upload-pack request: {}", error);
+            StatusCode::BAD_REQUEST
+        })?;
+        buf.extend_from_slice(&chunk);
+        check_body_limit(buf.len(), Some(limit))?;
+    }
+    let body = Bytes::from(buf);
+    let encoding = headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok());
+    let compressed = match encoding {
+        None | Some("identity") => return Ok(body),
+        Some("gzip" | "x-gzip") => body,
+        Some(encoding) => {
+            tracing::warn!("unsupported content encoding: {}", encoding);
+            return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+    };
+    gunzip(&compressed, MAX_UPLOAD_PACK_REQUEST).map(Bytes::from)
+}
+
+/// upload-pack requests are buffered, so they're capped even if the server
+/// has no `max_request_body`
+fn request_limit(max_request_body: Option<u64>) -> u64 {
+    max_request_body.map_or(MAX_UPLOAD_PACK_REQUEST, |limit| {
+        limit.min(MAX_UPLOAD_PACK_REQUEST)
+    })
+}
+
+/// `compressed` is up to `limit` bytes once decompressed, 413 otherwise
+fn gunzip(compressed: &[u8], limit: u64) -> Result<Vec<u8>, StatusCode> {
+    let mut request = Vec::new();
+    // a byte over the limit tells a too large request from one of the limit
+    flate2::read::GzDecoder::new(compressed)
+        .take(limit + 1)
+        .read_to_end(&mut request)
+        .map_err(|error| {
+            tracing::warn!("upload-pack request: {}", error);
+            StatusCode::BAD_REQUEST
+        })?;
+    check_body_limit(request.len(), Some(limit))?;
+    Ok(request)
+}
+
+fn check_body_limit(len: usize, limit: Option<u64>) -> Result<(), StatusCode> {
+    match limit {
+        Some(limit) if len as u64 > limit => {
+            tracing::warn!("request body is over {} bytes", limit);
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn smart_response(content_type: &'static str, body: ByteStream) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        StreamBody::new(body),
+    )
+        .into_response()
+}
+
+/// dumb protocol files, commits of fetched objects go to the SBOM
+async fn serve(
+    state: &GitServerState,
+    client: IpAddr,
+    git_url: GitUrl,
+    src: &str,
+    headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    if !state.is_allowed(&git_url) {
+        tracing::warn!("remote is not allowed: {}", git_url);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if state.sbom.is_some() {
+        state.dumb_sessions.requested(client, &git_url, src).await;
+    }
 
     let path = state
         .git_registry
-        .dumb(&gosh_url, src)
+        .dumb(&git_url, src)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(error_status)?;
 
     tracing::debug!(?path);
 
     if let Some(ref s) = state.sbom {
         s.lock()
             .await
-            .append(GoshClassification::Repository, gosh_url)
+            .append(GoshClassification::Repository, git_url.to_string())
     };
 
-    if path.is_dir() {
-        tracing::debug!("requested directory: {:?}", path);
-        // TODO: handle directory listing but we don't have to
-        Err(StatusCode::NOT_FOUND)
-    } else {
-        tracing::debug!(?path, "serve file");
-        let mut buf = Vec::new();
-        File::open(path)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-            .read_to_end(&mut buf)
+    tracing::debug!(?path, "serve file");
+    let response = file_response::file_response(src, &path, headers).await?;
+
+    if let Some(ref s) = state.sbom {
+        let commits = state
+            .dumb_sessions
+            .served(client, &git_url, src, &path)
             .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        Ok(Bytes::from(buf))
+            .unwrap_or_else(|error| {
+                tracing::warn!("dumb session of {} {}: {:?}", client, git_url, error);
+                Vec::new()
+            });
+        let mut sbom = s.lock().await;
+        for commit in commits {
+            sbom.append(
+                GoshClassification::Commit,
+                format!("{}:{}", git_url, commit),
+            );
+        }
+    }
+    Ok(response)
+}
+
+/// 403, 404 and 502 for registry failures clients can react to, 500 for the rest
+fn error_status(error: anyhow::Error) -> StatusCode {
+    tracing::warn!("{:?}", error);
+    match GitRegistryError::of(&error) {
+        Some(GitRegistryError::NotAllowed { .. } | GitRegistryError::ForbiddenPath { .. }) => {
+            StatusCode::FORBIDDEN
+        }
+        Some(error) if error.is_not_found() => StatusCode::NOT_FOUND,
+        Some(error) if error.is_unavailable() => StatusCode::BAD_GATEWAY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn read_request_test() {
+        assert_eq!(request_limit(None), MAX_UPLOAD_PACK_REQUEST);
+        assert_eq!(request_limit(Some(1024)), 1024);
+        assert_eq!(request_limit(Some(u64::MAX)), MAX_UPLOAD_PACK_REQUEST);
+
+        let headers = HeaderMap::new();
+        let body = || hyper::Body::from(vec![b'0'; 1024]);
+        let request = read_request(&headers, body(), Some(1024)).await.unwrap();
+        assert_eq!(request.len(), 1024);
+        assert_eq!(
+            read_request(&headers, body(), Some(1023))
+                .await
+                .unwrap_err(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[test]
+    fn gunzip_test() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&[b'0'; 1024]).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(gunzip(&compressed, 1024).unwrap().len(), 1024);
+        assert_eq!(
+            gunzip(&compressed, 1023).unwrap_err(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            gunzip(b"not gzip", 1024).unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
     }
 }
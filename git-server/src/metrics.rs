@@ -0,0 +1,125 @@
+use crate::GitServerState;
+use axum::{
+    body::{boxed, BoxBody},
+    extract::{MatchedPath, State},
+    http::{header, HeaderMap, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use gosh_utils::metrics::Metrics;
+use hyper::body::{Bytes, HttpBody, SizeHint};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+const REQUESTS: &str = "gosh_git_server_requests_total";
+const REQUESTS_HELP: &str = "HTTP requests by route and status.";
+const REQUEST_DURATION: &str = "gosh_git_server_request_duration_seconds";
+const REQUEST_DURATION_HELP: &str = "Time to the response head by route.";
+const BYTES_SERVED: &str = "gosh_git_server_response_bytes_total";
+const BYTES_SERVED_HELP: &str = "Response body bytes sent by route, compressed ones included.";
+
+/// `/metrics` in the Prometheus text format
+pub async fn metrics_handler(State(state): State<Arc<GitServerState>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.git_registry.metrics().render(),
+    )
+        .into_response()
+}
+
+/// counts requests by their route pattern, so repos don't blow up the
+/// series, and bytes of response bodies once they're sent
+pub async fn track<B>(
+    State(state): State<Arc<GitServerState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| "unmatched".to_owned(), |path| path.as_str().to_owned());
+    let method = request.method().clone();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    let metrics = state.git_registry.metrics().clone();
+    metrics.inc(
+        REQUESTS,
+        REQUESTS_HELP,
+        &[
+            ("method", method.as_str()),
+            ("route", &route),
+            ("status", response.status().as_str()),
+        ],
+    );
+    metrics.observe(
+        REQUEST_DURATION,
+        REQUEST_DURATION_HELP,
+        &[("method", method.as_str()), ("route", &route)],
+        started.elapsed(),
+    );
+
+    let (parts, body) = response.into_parts();
+    let body = CountedBody {
+        inner: body,
+        metrics,
+        route,
+        bytes: 0,
+    };
+    Response::from_parts(parts, boxed(body))
+}
+
+/// body which reports its sent bytes when dropped, aborted downloads included
+struct CountedBody {
+    inner: BoxBody,
+    metrics: Arc<Metrics>,
+    route: String,
+    bytes: u64,
+}
+
+impl HttpBody for CountedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(data))) = &polled {
+            self.bytes += data.len() as u64;
+        }
+        polled
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for CountedBody {
+    fn drop(&mut self) {
+        self.metrics.inc_by(
+            BYTES_SERVED,
+            BYTES_SERVED_HELP,
+            &[("route", &self.route)],
+            self.bytes,
+        );
+    }
+}
@@ -0,0 +1,438 @@
+use crate::{
+    downloads::Downloads,
+    egress::{Egress, EgressLog, EgressPolicy},
+};
+use axum::{
+    body::{boxed, Body, Empty},
+    http::{header, uri::Authority, HeaderMap, Method, Request, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Router,
+};
+use gosh_sbom::Sbom;
+use hyper::{client::HttpConnector, service::Service};
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::{net::TcpStream, sync::Mutex};
+
+/// host of the gosh server behind the proxy, e.g. `http://gosh/<contract>/<dao>/<repo>`
+pub const GOSH_HOST: &str = "gosh";
+
+/// path prefix on the gosh host for downloads which are pinned even if they're
+/// https, e.g. `http://gosh/download/https://example.com/release.tar.gz`
+pub const DOWNLOAD_PREFIX: &str = "/download/";
+
+/// headers of a single connection, never forwarded
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+pub struct ProxyState {
+    /// where the server listens, requests to it aren't egress
+    pub local: SocketAddr,
+    pub policy: EgressPolicy,
+    pub log: Arc<EgressLog>,
+    /// plain http GETs and [`DOWNLOAD_PREFIX`] ones
+    pub downloads: Arc<Downloads>,
+    /// where downloads are pinned
+    pub sbom: Option<Arc<Mutex<Sbom>>>,
+    pub client: hyper::Client<HttpConnector>,
+}
+
+impl ProxyState {
+    pub fn new(
+        local: SocketAddr,
+        policy: EgressPolicy,
+        log: Arc<EgressLog>,
+        downloads: Arc<Downloads>,
+        sbom: Option<Arc<Mutex<Sbom>>>,
+    ) -> Self {
+        Self {
+            local,
+            policy,
+            log,
+            downloads,
+            sbom,
+            client: hyper::Client::new(),
+        }
+    }
+
+    fn is_local(&self, authority: &Authority) -> bool {
+        let host = authority.host();
+        if host.eq_ignore_ascii_case(GOSH_HOST) {
+            return true;
+        }
+        if authority.port_u16().unwrap_or(80) != self.local.port() {
+            return false;
+        }
+        match host.trim_start_matches('[').trim_end_matches(']').parse() {
+            Ok(ip) => ip == self.local.ip() || IpAddr::is_loopback(&ip),
+            Err(_) => host.eq_ignore_ascii_case("localhost"),
+        }
+    }
+}
+
+/// Http forward proxy in front of the routes: `CONNECT` tunnels and
+/// absolute-form requests to other hosts are checked against the policy,
+/// logged and passed on, the rest (gosh repos) is served by the routes
+///
+/// `GET`s of files go through [`Downloads`], tunnels can't be pinned so
+/// they're denied once downloads are locked, https files are pinned when
+/// they're fetched with [`DOWNLOAD_PREFIX`]
+///
+/// it wraps the whole router, as routes would put `Content-Length` on the
+/// response to `CONNECT`, which isn't allowed there
+#[derive(Clone)]
+pub struct ProxyService {
+    router: Router,
+    state: Arc<ProxyState>,
+}
+
+impl ProxyService {
+    pub fn new(router: Router, state: ProxyState) -> Self {
+        Self {
+            router,
+            state: Arc::new(state),
+        }
+    }
+}
+
+impl Service<Request<Body>> for ProxyService {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // so is the router
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let state = self.state.clone();
+        let mut router = self.router.clone();
+        Box::pin(async move {
+            if request.method() == Method::CONNECT {
+                return Ok(connect(&state, request).await);
+            }
+            match request.uri().authority() {
+                Some(authority) if !state.is_local(authority) => {
+                    let authority = authority.clone();
+                    if request.method() == Method::GET && request.uri().scheme_str() == Some("http")
+                    {
+                        let url = request.uri().to_string();
+                        Ok(download(&state, url).await)
+                    } else if state.downloads.is_locked() {
+                        Ok(deny(&state, request.method(), request.uri().to_string()))
+                    } else {
+                        Ok(forward(&state, authority, request).await)
+                    }
+                }
+                _ => match download_url(&request) {
+                    Some(url) if request.method() == Method::GET => Ok(download(&state, url).await),
+                    _ => router.call(request).await,
+                },
+            }
+        })
+    }
+}
+
+/// `CONNECT host:port`, the tunnel is opened before 200 so failures are 502
+///
+/// what goes through a tunnel isn't pinned, so locked builds, which are
+/// reproduced from pins, deny tunnels
+async fn connect(state: &ProxyState, request: Request<Body>) -> Response {
+    let target = request
+        .uri()
+        .authority()
+        .map(ToString::to_string)
+        .unwrap_or_default();
+    let mut egress = Egress {
+        method: Method::CONNECT.to_string(),
+        target,
+        allowed: false,
+        status: StatusCode::FORBIDDEN.as_u16(),
+    };
+    let Some((host, port)) = request
+        .uri()
+        .authority()
+        .and_then(|authority| Some((authority.host().to_owned(), authority.port_u16()?)))
+    else {
+        egress.status = StatusCode::BAD_REQUEST.as_u16();
+        state.log.record(egress);
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    if state.downloads.is_locked() {
+        state.log.record(egress);
+        let hint = format!(
+            "tunnels aren't pinned, GET http://{}{}<url> instead\n",
+            GOSH_HOST, DOWNLOAD_PREFIX
+        );
+        return (StatusCode::FORBIDDEN, hint).into_response();
+    }
+    if !state.policy.permits(&host, port) {
+        state.log.record(egress);
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    egress.allowed = true;
+    tracing::warn!(
+        "CONNECT {} isn't pinned, locked builds deny it, use {}<url> for downloads",
+        egress.target,
+        DOWNLOAD_PREFIX
+    );
+
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_owned();
+    let mut upstream = match TcpStream::connect((host.as_str(), port)).await {
+        Ok(upstream) => upstream,
+        Err(error) => {
+            tracing::warn!("CONNECT {}: {}", egress.target, error);
+            egress.status = StatusCode::BAD_GATEWAY.as_u16();
+            state.log.record(egress);
+            return StatusCode::BAD_GATEWAY.into_response();
+        }
+    };
+    egress.status = StatusCode::OK.as_u16();
+    let target = egress.target.clone();
+    state.log.record(egress);
+
+    tokio::spawn(async move {
+        match hyper::upgrade::on(request).await {
+            Ok(mut upgraded) => {
+                if let Err(error) =
+                    tokio::io::copy_bidirectional(&mut upgraded, &mut upstream).await
+                {
+                    tracing::debug!("tunnel to {}: {}", target, error);
+                }
+            }
+            Err(error) => tracing::warn!("CONNECT {} upgrade: {}", target, error),
+        }
+    });
+    Response::new(boxed(Empty::new()))
+}
+
+/// absolute-form `http://host/path` requests other than `GET`s
+async fn forward(state: &ProxyState, authority: Authority, mut request: Request<Body>) -> Response {
+    let mut egress = Egress {
+        method: request.method().to_string(),
+        target: request.uri().to_string(),
+        allowed: false,
+        status: StatusCode::FORBIDDEN.as_u16(),
+    };
+    if request.uri().scheme_str() != Some("http") {
+        egress.status = StatusCode::BAD_REQUEST.as_u16();
+        state.log.record(egress);
+        return (StatusCode::BAD_REQUEST, "https is proxied with CONNECT").into_response();
+    }
+    if !state
+        .policy
+        .permits(authority.host(), authority.port_u16().unwrap_or(80))
+    {
+        state.log.record(egress);
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    egress.allowed = true;
+
+    strip_hop_by_hop(request.headers_mut());
+    match state.client.request(request).await {
+        Ok(mut response) => {
+            egress.status = response.status().as_u16();
+            state.log.record(egress);
+            strip_hop_by_hop(response.headers_mut());
+            response.map(boxed)
+        }
+        Err(error) => {
+            tracing::warn!("{} {}: {}", egress.method, egress.target, error);
+            egress.status = StatusCode::BAD_GATEWAY.as_u16();
+            state.log.record(egress);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+/// `<url>` of `http://gosh/download/<url>`
+fn download_url(request: &Request<Body>) -> Option<String> {
+    let url = request
+        .uri()
+        .path_and_query()?
+        .as_str()
+        .strip_prefix(DOWNLOAD_PREFIX)?;
+    Some(url.to_owned())
+}
+
+/// `GET url` served by [`Downloads`]: from the store when it's pinned,
+/// downloaded and recorded otherwise
+async fn download(state: &ProxyState, url: String) -> Response {
+    let mut egress = Egress {
+        method: Method::GET.to_string(),
+        target: url,
+        allowed: false,
+        status: StatusCode::FORBIDDEN.as_u16(),
+    };
+    let target = egress
+        .target
+        .parse::<Uri>()
+        .ok()
+        .filter(|uri| matches!(uri.scheme_str(), Some("http" | "https")))
+        .and_then(|uri| {
+            let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+                Some("https") => 443,
+                _ => 80,
+            });
+            Some((uri.host()?.to_owned(), port))
+        });
+    let Some((host, port)) = target else {
+        egress.status = StatusCode::BAD_REQUEST.as_u16();
+        state.log.record(egress);
+        return (StatusCode::BAD_REQUEST, "expected http(s) url").into_response();
+    };
+    if !state.policy.permits(&host, port) {
+        state.log.record(egress);
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    egress.allowed = true;
+
+    let response = match state
+        .downloads
+        .resolve(&egress.target, state.sbom.as_deref())
+        .await
+    {
+        Ok(download) => state.downloads.response(&download).await,
+        Err(status) => Err(status),
+    };
+    let response = response.unwrap_or_else(IntoResponse::into_response);
+    egress.status = response.status().as_u16();
+    // unpinned ones of locked builds
+    egress.allowed = response.status() != StatusCode::FORBIDDEN;
+    state.log.record(egress);
+    response
+}
+
+/// requests which can't be pinned, once downloads are locked
+fn deny(state: &ProxyState, method: &Method, target: String) -> Response {
+    state.log.record(Egress {
+        method: method.to_string(),
+        target,
+        allowed: false,
+        status: StatusCode::FORBIDDEN.as_u16(),
+    });
+    StatusCode::FORBIDDEN.into_response()
+}
+
+/// hop-by-hop headers, the standard ones and the ones `Connection` names
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let named = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    for name in HOP_BY_HOP
+        .iter()
+        .copied()
+        .chain(named.iter().map(String::as_str))
+    {
+        headers.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloads::DownloadMode;
+    use git_registry::download::DownloadStore;
+
+    fn state(dir: &std::path::Path, mode: DownloadMode, policy: EgressPolicy) -> ProxyState {
+        let downloads = Downloads::new(mode, DownloadStore::new(dir).unwrap());
+        ProxyState::new(
+            "127.0.0.1:6054".parse().unwrap(),
+            policy,
+            Arc::default(),
+            Arc::new(downloads),
+            None,
+        )
+    }
+
+    #[test]
+    fn is_local_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path(), DownloadMode::Record, EgressPolicy::default());
+        let is_local = |authority: &str| state.is_local(&authority.parse().unwrap());
+        assert!(is_local("gosh"));
+        assert!(is_local("GOSH:80"));
+        assert!(is_local("127.0.0.1:6054"));
+        assert!(is_local("localhost:6054"));
+        assert!(is_local("[::1]:6054"));
+        assert!(!is_local("127.0.0.1:8080"));
+        assert!(!is_local("example.com:6054"));
+        assert!(!is_local("example.com"));
+    }
+
+    #[tokio::test]
+    async fn connect_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = upstream.local_addr().unwrap().to_string();
+        let request = |target: &str| {
+            Request::builder()
+                .method(Method::CONNECT)
+                .uri(target)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let egress = |target: &str, allowed, status| Egress {
+            method: "CONNECT".to_owned(),
+            target: target.to_owned(),
+            allowed,
+            status,
+        };
+
+        // recording builds tunnel to the permitted hosts
+        let policy = EgressPolicy::new(["127.0.0.1"], ["example.com"]).unwrap();
+        let recording = state(dir.path(), DownloadMode::Record, policy.clone());
+        let response = connect(&recording, request(&target)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = connect(&recording, request("example.com:443")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            recording.log.entries(),
+            vec![
+                egress(&target, true, 200),
+                egress("example.com:443", false, 403)
+            ]
+        );
+
+        // locked ones are replayed from pins, tunnels aren't pinned
+        let locked = state(dir.path(), DownloadMode::Locked(Default::default()), policy);
+        let response = connect(&locked, request(&target)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(locked.log.entries(), vec![egress(&target, false, 403)]);
+    }
+
+    #[test]
+    fn strip_hop_by_hop_test() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, "close, x-trace".parse().unwrap());
+        headers.insert("x-trace", "1".parse().unwrap());
+        headers.insert("proxy-authorization", "Basic Zm9v".parse().unwrap());
+        headers.insert(header::ACCEPT, "*/*".parse().unwrap());
+        strip_hop_by_hop(&mut headers);
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key(header::ACCEPT));
+    }
+}
@@ -0,0 +1,115 @@
+use git_registry::{git_url::GitUrl, gosh_url::GoshUrl};
+
+/// Split `<scheme>/<repo>/<src>` of the `/git/*path` route into the remote url
+/// and the dumb protocol file (or the smart `git-upload-pack` endpoint)
+///
+/// e.g. `https/github.com/rust-lang/cargo.git/info/refs`
+pub fn split_remote_path(path: &str) -> anyhow::Result<(GitUrl, &str)> {
+    let Some((scheme, rest)) = path.trim_start_matches('/').split_once('/') else {
+        anyhow::bail!("expected `<scheme>/<repo>/<path>`: {}", path);
+    };
+
+    // dumb files are 1 (`HEAD`), 2 (`info/refs`) or 3 (`objects/..`) levels deep
+    let parts = rest.split('/').collect::<Vec<_>>();
+    let Some(depth) = (1..=3)
+        .rev()
+        .filter(|depth| *depth < parts.len())
+        .find(|depth| is_dumb_file(&parts[parts.len() - depth..]))
+    else {
+        anyhow::bail!("no git file requested: {}", path);
+    };
+
+    let repo = parts[..parts.len() - depth].join("/");
+    let src_start = rest.len() - parts[parts.len() - depth..].join("/").len();
+
+    Ok((remote_url(scheme, &repo)?, &rest[src_start..]))
+}
+
+/// Repo of the `/upload/*path` route: `<contract>/<dao>/<repo>` or
+/// `git/<scheme>/<repo>`, the same paths the repos are served under
+pub fn split_repo_path(path: &str) -> anyhow::Result<GitUrl> {
+    let path = path.trim_matches('/');
+    if let Some(rest) = path.strip_prefix("git/") {
+        let Some((scheme, repo)) = rest.split_once('/') else {
+            anyhow::bail!("expected `git/<scheme>/<repo>`: {}", path);
+        };
+        return remote_url(scheme, repo);
+    }
+    let [contract, dao, repo] = path.split('/').collect::<Vec<_>>()[..] else {
+        anyhow::bail!("expected `<contract>/<dao>/<repo>`: {}", path);
+    };
+    Ok(GoshUrl::new(contract, dao, repo)?.into())
+}
+
+fn remote_url(scheme: &str, repo: &str) -> anyhow::Result<GitUrl> {
+    let url = match scheme {
+        "file" => format!("file:///{}", repo),
+        scheme => format!("{}://{}", scheme, repo),
+    };
+    Ok(url.parse()?)
+}
+
+fn is_dumb_file(parts: &[&str]) -> bool {
+    let is_hex = |part: &str, len| part.len() == len && part.chars().all(|c| c.is_ascii_hexdigit());
+    match parts {
+        ["HEAD"] | ["git-upload-pack"] => true,
+        ["info", "refs"] => true,
+        ["objects", "info", "packs" | "alternates" | "http-alternates"] => true,
+        ["objects", dir, file] if is_hex(dir, 2) && is_hex(file, 38) => true,
+        ["objects", "pack", file] => file
+            .strip_prefix("pack-")
+            .and_then(|name| {
+                name.strip_suffix(".pack")
+                    .or_else(|| name.strip_suffix(".idx"))
+            })
+            .map_or(false, |hash| is_hex(hash, 40)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_remote_path_test() {
+        let (url, src) =
+            split_remote_path("https/github.com/rust-lang/cargo.git/info/refs").unwrap();
+        assert_eq!(url.to_string(), "https://github.com/rust-lang/cargo.git");
+        assert_eq!(src, "info/refs");
+
+        let (url, src) = split_remote_path("file/srv/git/info/objects/info/packs").unwrap();
+        assert_eq!(url.to_string(), "file:///srv/git/info");
+        assert_eq!(src, "objects/info/packs");
+
+        let pack = format!("objects/pack/pack-{}.idx", "a".repeat(40));
+        let path = format!("ssh/git@example.com:2222/repo/{}", pack);
+        let (url, src) = split_remote_path(&path).unwrap();
+        assert_eq!(url.to_string(), "ssh://git@example.com:2222/repo");
+        assert_eq!(src, pack);
+
+        let (url, src) = split_remote_path("https/github.com/a/b/git-upload-pack").unwrap();
+        assert_eq!(url.to_string(), "https://github.com/a/b");
+        assert_eq!(src, "git-upload-pack");
+
+        assert!(split_remote_path("https/github.com/a/b/HEAD").is_ok());
+        assert!(split_remote_path("https/github.com/a/b/README.md").is_err());
+        assert!(split_remote_path("https/HEAD").is_err());
+        assert!(split_remote_path("http/github.com/a/b/HEAD").is_err());
+    }
+
+    #[test]
+    fn split_repo_path_test() {
+        let contract = "0:0d5c05d7a63f438b57ede179b7110d3e903f5be3b5f543d3d6743d774698e92c";
+        let url = split_repo_path(&format!("{}/dao/repo", contract)).unwrap();
+        assert_eq!(url.to_string(), format!("gosh://{}/dao/repo", contract));
+
+        let url = split_repo_path("git/https/github.com/rust-lang/cargo.git").unwrap();
+        assert_eq!(url.to_string(), "https://github.com/rust-lang/cargo.git");
+        let url = split_repo_path("git/file/srv/git/repo/").unwrap();
+        assert_eq!(url.to_string(), "file:///srv/git/repo");
+
+        assert!(split_repo_path("dao/repo").is_err());
+        assert!(split_repo_path("git/https").is_err());
+    }
+}
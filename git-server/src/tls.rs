@@ -0,0 +1,149 @@
+use axum::extract::connect_info::Connected;
+use hyper::server::accept::Accept;
+use std::{
+    io::BufReader,
+    net::SocketAddr,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{rustls, server::TlsStream, TlsAcceptor};
+
+/// clients which don't finish the handshake in time are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const PENDING_CONNECTIONS: usize = 64;
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// rustls config of a PEM certificate chain and private key
+pub fn server_config(cert: &Path, key: &Path) -> anyhow::Result<Arc<rustls::ServerConfig>> {
+    let read = |path: &Path| -> anyhow::Result<BufReader<std::fs::File>> {
+        let file = std::fs::File::open(path)
+            .map_err(|err| anyhow::anyhow!("can't open {:?}: {}", path, err))?;
+        Ok(BufReader::new(file))
+    };
+    let certs = rustls_pemfile::certs(&mut read(cert)?)?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        anyhow::bail!("no certificates in {:?}", cert);
+    }
+    let key = rustls_pemfile::read_all(&mut read(key)?)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("no private key in {:?}", key))?;
+
+    let mut config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// TLS connection with the client address for `ConnectInfo`
+pub struct TlsConnection {
+    stream: TlsStream<TcpStream>,
+    client: SocketAddr,
+}
+
+impl Connected<&TlsConnection> for SocketAddr {
+    fn connect_info(target: &TlsConnection) -> Self {
+        target.client
+    }
+}
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Connections which finished the TLS handshake, handshakes run concurrently
+/// so a slow client doesn't hold up the others
+pub struct TlsIncoming(mpsc::Receiver<TlsConnection>);
+
+impl TlsIncoming {
+    pub async fn bind(addr: SocketAddr, config: Arc<rustls::ServerConfig>) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let acceptor = TlsAcceptor::from(config);
+        let (sender, receiver) = mpsc::channel(PENDING_CONNECTIONS);
+        tokio::spawn(async move {
+            loop {
+                let (stream, client) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(error) => {
+                        // e.g. out of file descriptors
+                        tracing::warn!("accept on {}: {}", addr, error);
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+                // the server is gone
+                if sender.is_closed() {
+                    break;
+                }
+                let acceptor = acceptor.clone();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send(TlsConnection { stream, client }).await;
+                        }
+                        Ok(Err(error)) => {
+                            tracing::debug!("TLS handshake with {}: {}", client, error)
+                        }
+                        Err(_) => tracing::debug!("TLS handshake with {} timed out", client),
+                    }
+                });
+            }
+        });
+        Ok(Self(receiver))
+    }
+}
+
+impl Accept for TlsIncoming {
+    type Conn = TlsConnection;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.0.poll_recv(cx).map(|connection| connection.map(Ok))
+    }
+}
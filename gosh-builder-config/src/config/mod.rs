@@ -36,7 +36,7 @@ impl GoshConfig {
         let raw_config = RawGoshConfig::try_from_reader(
             git_cache_registry
                 .git_show_uncompressed(
-                    git_context.remote.as_str(),
+                    &git_context.remote,
                     git_context.git_ref.as_str(),
                     file_path.to_string_lossy(),
                 )
@@ -54,7 +54,7 @@ impl GoshConfig {
                 String::from_utf8(
                     git_cache_registry
                         .git_show_uncompressed(
-                            git_context.remote.as_str(),
+                            &git_context.remote,
                             git_context.git_ref.as_str(),
                             dockerfile_path.to_string_lossy(),
                         )
@@ -1,7 +1,16 @@
 fn main() {
+    let mut config = prost_build::Config::new();
+    // stream chunks as `Bytes` to avoid copying them
+    config.bytes([".builder.CommitResponse.body", ".builder.FileResponse.body"]);
+
     tonic_build::configure()
-        .compile(
-            &["proto/gosh-get.proto", "proto/git-remote-gosh.proto"],
+        .compile_with_config(
+            config,
+            &[
+                "proto/gosh-get.proto",
+                "proto/git-remote-gosh.proto",
+                "proto/health.proto",
+            ],
             &["proto"],
         )
         .unwrap_or_else(|e| {
@@ -1,3 +1,8 @@
 pub mod proto {
     tonic::include_proto!("builder");
 }
+
+/// standard `grpc.health.v1` health checks
+pub mod health {
+    tonic::include_proto!("grpc.health.v1");
+}
@@ -1,3 +1,7 @@
+mod network;
+
+pub use network::BuildNetwork;
+
 use gosh_builder_config::GoshConfig;
 use std::{
     net::SocketAddr,
@@ -10,7 +14,13 @@ use tokio::{
 
 #[async_trait::async_trait]
 pub trait ImageBuilder {
-    async fn run(&self, quiet: bool, proxy_socket: SocketAddr) -> anyhow::Result<GoshBuildResult>;
+    /// build on `network`, the build server listens at `proxy_socket` on its gateway
+    async fn run(
+        &self,
+        quiet: bool,
+        network: &BuildNetwork,
+        proxy_socket: SocketAddr,
+    ) -> anyhow::Result<GoshBuildResult>;
 }
 
 #[derive(Debug, Clone)]
@@ -26,13 +36,20 @@ pub struct GoshBuildResult {
 
 #[async_trait::async_trait]
 impl ImageBuilder for GoshBuilder {
-    async fn run(&self, quiet: bool, proxy_socket: SocketAddr) -> anyhow::Result<GoshBuildResult> {
+    async fn run(
+        &self,
+        quiet: bool,
+        network: &BuildNetwork,
+        proxy_socket: SocketAddr,
+    ) -> anyhow::Result<GoshBuildResult> {
         let mut command = Command::new("docker");
-        command.arg("buildx");
+        // BuildKit runs RUN steps on the default, host or no network only, the
+        // classic builder takes the internal one: clients ignoring `http_proxy`
+        // reach nothing
+        command.env("DOCKER_BUILDKIT", "0");
         command.arg("build");
-        // command.arg("--progress=plain");
         command.arg("--no-cache");
-        command.arg("--network=host"); // TODO: fix network access
+        command.arg("--network").arg(&network.name);
         if let Some(ref tag) = self.config.tag {
             command.arg("--tag").arg(tag);
         }
@@ -49,17 +66,22 @@ impl ImageBuilder for GoshBuilder {
             command.arg(key).arg(value);
         }
 
-        let proxy_addr = format!("{}:{}", proxy_socket.ip(), proxy_socket.port());
+        let proxy_addr = proxy_socket.to_string();
 
-        command
-            .arg("--build-arg")
-            .arg(format!("http_proxy=http://{}", proxy_addr));
-        command
-            .arg("--build-arg")
-            .arg(format!("https_proxy=http://{}", proxy_addr));
+        // both cases, tools read either of them
+        for proxy_var in ["http_proxy", "https_proxy", "HTTP_PROXY", "HTTPS_PROXY"] {
+            command
+                .arg("--build-arg")
+                .arg(format!("{}=http://{}", proxy_var, proxy_addr));
+        }
         command
             .arg("--build-arg")
             .arg(format!("GOSH_HTTP_PROXY=http://{}", proxy_addr));
+        // cargo source replacement, e.g. `images/rust`
+        command.arg("--build-arg").arg(format!(
+            "GOSH_CARGO_REGISTRY=sparse+http://{}/cargo/index/",
+            proxy_addr
+        ));
 
         command.arg("-"); // use stdin
         tracing::debug!("{:?}", command);
@@ -0,0 +1,69 @@
+use std::net::IpAddr;
+use tokio::process::Command;
+
+/// `--internal` docker network a build runs in: its containers reach nothing
+/// but the host on the network's gateway, where the build server listens
+#[derive(Debug)]
+pub struct BuildNetwork {
+    pub name: String,
+    pub gateway: IpAddr,
+}
+
+impl BuildNetwork {
+    pub async fn create() -> anyhow::Result<Self> {
+        let name = format!("gosh-build-{}", uuid::Uuid::new_v4());
+        docker([
+            "network",
+            "create",
+            "--internal",
+            "--driver",
+            "bridge",
+            &name,
+        ])
+        .await?;
+        let gateway = docker([
+            "network",
+            "inspect",
+            "--format",
+            "{{range .IPAM.Config}}{{.Gateway}} {{end}}",
+            &name,
+        ])
+        .await
+        .and_then(|gateways| {
+            // the first one, IPv4 unless the daemon defaults to IPv6
+            gateways
+                .split_whitespace()
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("docker network {} has no gateway", name))?
+                .parse::<IpAddr>()
+                .map_err(anyhow::Error::from)
+        });
+        let network = match gateway {
+            Ok(gateway) => Self { name, gateway },
+            Err(error) => {
+                docker(["network", "rm", &name]).await.ok();
+                return Err(error);
+            }
+        };
+        tracing::info!("build network {} via {}", network.name, network.gateway);
+        Ok(network)
+    }
+
+    pub async fn remove(&self) -> anyhow::Result<()> {
+        docker(["network", "rm", &self.name]).await?;
+        Ok(())
+    }
+}
+
+/// run `docker`, STDOUT if it succeeded
+async fn docker<const N: usize>(args: [&str; N]) -> anyhow::Result<String> {
+    let output = Command::new("docker").args(args).output().await?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "docker {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().to_owned())
+}
@@ -1,32 +1,88 @@
 use git_registry::registry::GitCacheRegistry;
+pub use git_server::{
+    crates::CrateRegistry,
+    downloads::{DownloadMode, Downloads},
+    egress::{Egress, EgressLog, EgressPolicy},
+};
 use gosh_sbom::Sbom;
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::Mutex;
 
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// git server of the build which is its http proxy too, hosts other than gosh
+/// are reached per `egress` and every attempt goes to `egress_log`, files
+/// are downloaded through `downloads` and crates come from `crates`
 pub fn run(
     address: SocketAddr,
     sbom: Arc<Mutex<Sbom>>,
     git_cache_registry: Arc<GitCacheRegistry>,
+    egress: EgressPolicy,
+    egress_log: Arc<EgressLog>,
+    downloads: Arc<Downloads>,
+    crates: Arc<CrateRegistry>,
 ) -> anyhow::Result<Box<dyn FnOnce()>> {
     tracing::info!("Start Git Server on {}", address);
 
     // for shutdown
     let (tx, rx) = tokio::sync::oneshot::channel::<()>();
 
-    let server = git_server::server(address, Some(sbom), git_cache_registry)
-        .with_graceful_shutdown(async move {
-            rx.await.ok();
-            tracing::info!("gRPC received shutdown");
-        });
+    let server = git_server::proxy_server(
+        address,
+        Some(sbom),
+        git_cache_registry,
+        egress,
+        egress_log,
+        downloads,
+        crates,
+    )?
+    .with_graceful_shutdown(async move {
+        rx.await.ok();
+        tracing::info!("Git Server received shutdown");
+    });
 
-    tracing::info!("gRPC ready");
+    tracing::info!("Git Server listening");
 
     tokio::spawn(async move {
         server.await.ok();
-        tracing::info!("gRPC stopped");
+        tracing::info!("Git Server stopped");
     });
 
     Ok(Box::new(move || {
         tx.send(()).ok();
     }))
 }
+
+/// polls `/readyz` of the server started by [`run`] until it answers 200
+pub async fn wait_ready(address: SocketAddr) -> anyhow::Result<()> {
+    // a wildcard listener is reachable on loopback
+    let ip = match address.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    let uri: hyper::Uri =
+        format!("http://{}/readyz", SocketAddr::new(ip, address.port())).parse()?;
+    let client = hyper::Client::new();
+    let started = Instant::now();
+    loop {
+        let last_error = match client.get(uri.clone()).await {
+            Ok(response) if response.status().is_success() => {
+                tracing::info!("Git Server ready");
+                return Ok(());
+            }
+            Ok(response) => format!("{}", response.status()),
+            Err(error) => error.to_string(),
+        };
+        if started.elapsed() > READY_TIMEOUT {
+            anyhow::bail!("Git Server on {} isn't ready: {}", address, last_error);
+        }
+        tracing::debug!("Git Server not ready: {}", last_error);
+        tokio::time::sleep(READY_POLL_INTERVAL).await;
+    }
+}
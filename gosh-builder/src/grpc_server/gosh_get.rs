@@ -1,10 +1,35 @@
-use git_registry::registry::GitCacheRegistry;
+use git_registry::{
+    archive_filter::ArchiveFilter, error::GitRegistryError, git_url::GitUrl, refs::RefKind,
+    registry::GitCacheRegistry,
+};
 use gosh_builder_grpc_api::proto::{
-    gosh_get_server::GoshGet, CommitRequest, CommitResponse, FileRequest, FileResponse,
+    gosh_get_server::GoshGet, r#ref, CommitRequest, CommitResponse, FileRequest, FileResponse,
+    ListRefsRequest, ListRefsResponse, Ref, ResolveRequest, ResolveResponse,
+};
+use gosh_sbom::{
+    gosh_classification::GoshClassification,
+    record::{self, Served},
+    Sbom,
 };
-use gosh_sbom::{gosh_classification::GoshClassification, Sbom};
-use std::sync::Arc;
+use gosh_utils::stream::ByteStream;
+use std::{pin::Pin, sync::Arc};
 use tokio::sync::Mutex;
+use tokio_stream::Stream;
+
+/// NOT_FOUND and UNAVAILABLE for registry failures clients can react to,
+/// INTERNAL for the rest
+fn error_status(error: anyhow::Error) -> tonic::Status {
+    let message = format!("{:?}", error);
+    match GitRegistryError::of(&error) {
+        Some(GitRegistryError::NotAllowed { .. }) => tonic::Status::permission_denied(message),
+        Some(GitRegistryError::CacheCorrupt { .. }) => tonic::Status::data_loss(message),
+        Some(error) if error.is_not_found() => tonic::Status::not_found(message),
+        Some(error) if error.is_unavailable() => tonic::Status::unavailable(message),
+        _ => tonic::Status::internal(message),
+    }
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, tonic::Status>> + Send>>;
 
 #[derive(Debug)]
 pub struct GoshGetService {
@@ -19,61 +44,184 @@ impl GoshGetService {
             git_cache_registry,
         }
     }
+
+    /// Forward `body` to the client as `into_response` makes messages of its
+    /// chunks, `served` goes to the SBOM only when the whole body was sent
+    fn forward<T, F>(&self, body: ByteStream, into_response: F, served: Served) -> ResponseStream<T>
+    where
+        T: Send + 'static,
+        F: Fn(bytes::Bytes) -> T + Send + 'static,
+    {
+        Box::pin(record::forward(
+            self.sbom.clone(),
+            body,
+            move |chunk| {
+                chunk
+                    .map(&into_response)
+                    .map_err(|error| tonic::Status::internal(format!("{:?}", error)))
+            },
+            served,
+        ))
+    }
 }
 
 #[tonic::async_trait]
 impl GoshGet for GoshGetService {
+    type CommitStream = ResponseStream<CommitResponse>;
+    type FileStream = ResponseStream<FileResponse>;
+
     async fn commit(
         &self,
         grpc_request: tonic::Request<CommitRequest>,
-    ) -> std::result::Result<tonic::Response<CommitResponse>, tonic::Status> {
+    ) -> std::result::Result<tonic::Response<Self::CommitStream>, tonic::Status> {
         let request = grpc_request.into_inner();
 
         tracing::debug!("{:?}", request);
 
+        let gosh_url = request
+            .gosh_url
+            .parse::<GitUrl>()
+            .map_err(|error| tonic::Status::invalid_argument(error.to_string()))?;
+
         let commit_hash = self
             .git_cache_registry
-            .normalized_commit(&request.gosh_url, &request.commit)
+            .normalized_commit(&gosh_url, &request.commit)
             .await
-            .map_err(|error| tonic::Status::internal(format!("{:?}", error)))?;
+            .map_err(error_status)?;
 
-        let body = self
+        let filter = ArchiveFilter::new(&request.paths, &request.excludes)
+            .map_err(|error| tonic::Status::invalid_argument(format!("{:?}", error)))?;
+
+        let archive = self
             .git_cache_registry
-            .git_archive(&request.gosh_url, &commit_hash)
+            .git_archive(&gosh_url, &commit_hash, &filter)
             .await
-            .map_err(|error| tonic::Status::internal(format!("{:?}", error)))?;
+            .map_err(error_status)?;
+
+        let component = if filter.is_empty() {
+            (
+                GoshClassification::Commit,
+                format!("{}:{}", &gosh_url, &commit_hash),
+            )
+        } else {
+            (
+                GoshClassification::FilteredCommit,
+                format!("{}:{}:{}", &gosh_url, &commit_hash, filter),
+            )
+        };
 
-        self.sbom.lock().await.append(
-            GoshClassification::Commit,
-            format!("{}:{}", &request.gosh_url, &commit_hash),
-        );
+        let submodules = archive
+            .submodules
+            .into_iter()
+            .map(|archived| {
+                let parent =
+                    if archived.parent_url == gosh_url && archived.parent_commit == commit_hash {
+                        component.1.clone()
+                    } else {
+                        // nested submodule
+                        format!("{}:{}", archived.parent_url, archived.parent_commit)
+                    };
+                let submodule = format!("{}:{}", archived.submodule.url, archived.submodule.commit);
+                (parent, (GoshClassification::Submodule, submodule))
+            })
+            .collect();
 
-        return Ok(tonic::Response::new(CommitResponse { body }));
+        return Ok(tonic::Response::new(self.forward(
+            archive.body,
+            |body| CommitResponse { body },
+            Served {
+                component,
+                dependencies: submodules,
+                lfs_objects: archive.lfs_objects,
+            },
+        )));
     }
 
     async fn file(
         &self,
         grpc_request: tonic::Request<FileRequest>,
-    ) -> std::result::Result<tonic::Response<FileResponse>, tonic::Status> {
+    ) -> std::result::Result<tonic::Response<Self::FileStream>, tonic::Status> {
         let request = grpc_request.into_inner();
 
+        let gosh_url = request
+            .gosh_url
+            .parse::<GitUrl>()
+            .map_err(|error| tonic::Status::invalid_argument(error.to_string()))?;
+
         let commit_hash = self
             .git_cache_registry
-            .normalized_commit(&request.gosh_url, &request.commit)
+            .normalized_commit(&gosh_url, &request.commit)
+            .await
+            .map_err(error_status)?;
+
+        let file = self
+            .git_cache_registry
+            .git_show(&gosh_url, &commit_hash, &request.path)
             .await
-            .map_err(|error| tonic::Status::internal(format!("{:?}", error)))?;
+            .map_err(error_status)?;
+
+        Ok(tonic::Response::new(self.forward(
+            file.body,
+            |body| FileResponse { body },
+            Served {
+                component: (
+                    GoshClassification::File,
+                    format!("{}:{}:{}", &gosh_url, &commit_hash, &request.path),
+                ),
+                dependencies: Vec::new(),
+                lfs_objects: Arc::new(Mutex::new(file.lfs_object.into_iter().collect())),
+            },
+        )))
+    }
+
+    async fn list_refs(
+        &self,
+        grpc_request: tonic::Request<ListRefsRequest>,
+    ) -> std::result::Result<tonic::Response<ListRefsResponse>, tonic::Status> {
+        let request = grpc_request.into_inner();
 
-        let body = self
+        let gosh_url = request
+            .gosh_url
+            .parse::<GitUrl>()
+            .map_err(|error| tonic::Status::invalid_argument(error.to_string()))?;
+
+        let refs = self
             .git_cache_registry
-            .git_show(&request.gosh_url, &commit_hash, &request.path)
+            .list_refs(&gosh_url)
             .await
-            .map_err(|error| tonic::Status::internal(format!("{:?}", error)))?;
+            .map_err(error_status)?
+            .into_iter()
+            .map(|git_ref| Ref {
+                kind: match git_ref.kind {
+                    RefKind::Branch => r#ref::Kind::Branch,
+                    RefKind::Tag => r#ref::Kind::Tag,
+                }
+                .into(),
+                name: git_ref.name,
+                commit: git_ref.commit,
+            })
+            .collect();
 
-        self.sbom.lock().await.append(
-            GoshClassification::File,
-            format!("{}:{}:{}", &request.gosh_url, &commit_hash, &request.path),
-        );
+        Ok(tonic::Response::new(ListRefsResponse { refs }))
+    }
+
+    async fn resolve(
+        &self,
+        grpc_request: tonic::Request<ResolveRequest>,
+    ) -> std::result::Result<tonic::Response<ResolveResponse>, tonic::Status> {
+        let request = grpc_request.into_inner();
+
+        let gosh_url = request
+            .gosh_url
+            .parse::<GitUrl>()
+            .map_err(|error| tonic::Status::invalid_argument(error.to_string()))?;
+
+        let commit = self
+            .git_cache_registry
+            .normalized_commit(&gosh_url, &request.rev)
+            .await
+            .map_err(error_status)?;
 
-        Ok(tonic::Response::new(FileResponse { body }))
+        Ok(tonic::Response::new(ResolveResponse { commit }))
     }
 }
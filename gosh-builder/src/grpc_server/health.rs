@@ -0,0 +1,60 @@
+use git_registry::registry::GitCacheRegistry;
+use gosh_builder_grpc_api::health::{
+    health_check_response::ServingStatus, health_server::Health, HealthCheckRequest,
+    HealthCheckResponse,
+};
+use std::{pin::Pin, sync::Arc};
+use tokio_stream::Stream;
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, tonic::Status>> + Send>>;
+
+/// `grpc.health.v1` for the whole server: SERVING once the git cache can
+/// take requests, services aren't checked one by one
+#[derive(Debug)]
+pub struct HealthService {
+    pub git_cache_registry: Arc<GitCacheRegistry>,
+}
+
+impl HealthService {
+    pub fn new(git_cache_registry: Arc<GitCacheRegistry>) -> Self {
+        Self { git_cache_registry }
+    }
+
+    async fn status(&self) -> HealthCheckResponse {
+        let status = match self.git_cache_registry.check_ready().await {
+            Ok(()) => ServingStatus::Serving,
+            Err(error) => {
+                tracing::warn!("not ready: {:?}", error);
+                ServingStatus::NotServing
+            }
+        };
+        HealthCheckResponse {
+            status: status.into(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    type WatchStream = ResponseStream<HealthCheckResponse>;
+
+    async fn check(
+        &self,
+        grpc_request: tonic::Request<HealthCheckRequest>,
+    ) -> Result<tonic::Response<HealthCheckResponse>, tonic::Status> {
+        tracing::debug!("{:?}", grpc_request.into_inner());
+        Ok(tonic::Response::new(self.status().await))
+    }
+
+    /// the current status only, clients poll [`Self::check`] for changes
+    async fn watch(
+        &self,
+        grpc_request: tonic::Request<HealthCheckRequest>,
+    ) -> Result<tonic::Response<Self::WatchStream>, tonic::Status> {
+        tracing::debug!("{:?}", grpc_request.into_inner());
+        let status = self.status().await;
+        Ok(tonic::Response::new(Box::pin(tokio_stream::once(Ok(
+            status,
+        )))))
+    }
+}
@@ -1,15 +1,19 @@
 mod git_remote_gosh;
 mod gosh_get;
+mod health;
 
-use crate::grpc_server::{git_remote_gosh::GitRemoteGoshService, gosh_get::GoshGetService};
+use crate::grpc_server::{
+    git_remote_gosh::GitRemoteGoshService, gosh_get::GoshGetService, health::HealthService,
+};
 use git_registry::registry::GitCacheRegistry;
-use gosh_builder_grpc_api::proto::{
-    git_remote_gosh_server::GitRemoteGoshServer, gosh_get_server::GoshGetServer,
+use gosh_builder_grpc_api::{
+    health::health_server::HealthServer,
+    proto::{git_remote_gosh_server::GitRemoteGoshServer, gosh_get_server::GoshGetServer},
 };
 use gosh_sbom::Sbom;
 use std::{net::SocketAddr, sync::Arc};
 use tokio::sync::Mutex;
-use tonic::transport::Server;
+use tonic::transport::{server::TcpIncoming, Server};
 
 pub fn run(
     address: SocketAddr,
@@ -17,21 +21,26 @@ pub fn run(
     git_cache_registry: Arc<GitCacheRegistry>,
 ) -> anyhow::Result<Box<dyn FnOnce()>> {
     let git_remote_gosh_service = GitRemoteGoshService::new(sbom.clone());
-    let gosh_get_service = GoshGetService::new(sbom, git_cache_registry);
+    let gosh_get_service = GoshGetService::new(sbom, git_cache_registry.clone());
+    let health_service = HealthService::new(git_cache_registry);
 
     // for shutdown
     let (tx, rx) = tokio::sync::oneshot::channel::<()>();
 
     tracing::info!("Start gRPC on {}", address);
+    // bound here, so clients connecting after `run` aren't refused
+    let incoming = TcpIncoming::new(address, true, None)
+        .map_err(|error| anyhow::anyhow!("gRPC bind {}: {}", address, error))?;
     let server = Server::builder()
+        .add_service(HealthServer::new(health_service))
         .add_service(GitRemoteGoshServer::new(git_remote_gosh_service))
         .add_service(GoshGetServer::new(gosh_get_service))
-        .serve_with_shutdown(address, async move {
+        .serve_with_incoming_shutdown(incoming, async move {
             rx.await.ok();
             tracing::info!("gRPC received shutdown");
         });
 
-    tracing::info!("gRPC ready");
+    tracing::info!("gRPC listening");
 
     tokio::spawn(async move {
         server.await.ok();
@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand};
+use git_registry::git_url::GitUrl;
 
 const DEFAULT_GOSH_HTTP_PROXY: &str = "127.0.0.1:6054";
 
@@ -16,11 +17,38 @@ pub struct Cli {
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     /// Get commit state as a file tree without git history
-    Commit { gosh_url: String, commit: String },
+    Commit {
+        /// gosh:// or allowed https://, ssh://, file:// repository url
+        #[arg(value_name = "URL")]
+        gosh_url: GitUrl,
+        commit: String,
+        /// Get only this path (relative to the git root), can be repeated
+        #[arg(long = "path", value_name = "PATH")]
+        paths: Vec<String>,
+        /// Skip paths matching the glob pattern, can be repeated
+        #[arg(long = "exclude", value_name = "GLOB")]
+        excludes: Vec<String>,
+    },
     /// Get the single file from specific commit
     File {
-        gosh_url: String,
+        /// gosh:// or allowed https://, ssh://, file:// repository url
+        #[arg(value_name = "URL")]
+        gosh_url: GitUrl,
         commit: String,
         path: String,
     },
+    /// List branches and tags with their commits
+    Refs {
+        /// gosh:// or allowed https://, ssh://, file:// repository url
+        #[arg(value_name = "URL")]
+        gosh_url: GitUrl,
+    },
+    /// Print the full commit hash of a branch, tag or short commit hash
+    Resolve {
+        /// gosh:// or allowed https://, ssh://, file:// repository url
+        #[arg(value_name = "URL")]
+        gosh_url: GitUrl,
+        #[arg(value_name = "REF")]
+        rev: String,
+    },
 }
@@ -2,8 +2,31 @@ mod cli;
 
 use clap::Parser;
 use cli::{Cli, Commands};
-use gosh_builder_grpc_api::proto::{gosh_get_client::GoshGetClient, CommitRequest, FileRequest};
+use gosh_builder_grpc_api::proto::{
+    gosh_get_client::GoshGetClient, r#ref, CommitRequest, FileRequest, ListRefsRequest,
+    ResolveRequest,
+};
 use std::{fs::File, io::Write};
+use tokio_stream::StreamExt;
+use tokio_util::io::{StreamReader, SyncIoBridge};
+
+/// Blocking reader over the chunks of a gRPC stream
+///
+/// IMPORTANT: should be used only inside of `tokio::task::spawn_blocking`
+fn blocking_reader<T>(
+    stream: tonic::Streaming<T>,
+    body: fn(T) -> bytes::Bytes,
+) -> SyncIoBridge<impl tokio::io::AsyncRead>
+where
+    T: Send + 'static,
+{
+    let chunks = stream.map(move |message| {
+        message
+            .map(body)
+            .map_err(|status| std::io::Error::new(std::io::ErrorKind::Other, status))
+    });
+    SyncIoBridge::new(StreamReader::new(chunks))
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -15,22 +38,32 @@ async fn main() -> anyhow::Result<()> {
     let mut grpc_client = GoshGetClient::connect(app_cli.proxy_addr).await?;
 
     match &app_cli.command {
-        Commands::Commit { gosh_url, commit } => {
+        Commands::Commit {
+            gosh_url,
+            commit,
+            paths,
+            excludes,
+        } => {
             tracing::info!("Get commit...");
             let res = grpc_client
                 .commit(CommitRequest {
-                    gosh_url: gosh_url.to_owned(),
+                    gosh_url: gosh_url.to_string(),
                     commit: commit.to_owned(),
+                    paths: paths.to_owned(),
+                    excludes: excludes.to_owned(),
                 })
                 .await?;
 
-            tracing::trace!("decode tarball");
-            let zstd_content = res.get_ref().body.as_slice();
-            let tar = zstd::Decoder::new(zstd_content)?;
-            let mut archive = tar::Archive::new(tar);
-            tracing::trace!("unpack tarball");
+            let reader = blocking_reader(res.into_inner(), |message| message.body);
             let local_git_dir = std::env::current_dir()?;
-            archive.unpack(&local_git_dir)?;
+            tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+                tracing::trace!("decode and unpack tarball");
+                let tar = zstd::Decoder::new(reader)?;
+                let mut archive = tar::Archive::new(tar);
+                archive.unpack(&local_git_dir)?;
+                Ok(())
+            })
+            .await??;
         }
         Commands::File {
             gosh_url,
@@ -51,17 +84,45 @@ async fn main() -> anyhow::Result<()> {
             tracing::info!("Get file...");
             let res = grpc_client
                 .file(FileRequest {
-                    gosh_url: gosh_url.to_owned(),
+                    gosh_url: gosh_url.to_string(),
                     commit: commit.to_owned(),
                     path: path.to_owned(),
                 })
                 .await?;
 
-            let mut target_file = std::io::BufWriter::new(File::create(filename)?);
-            let zstd_content = res.get_ref().body.as_slice();
-            let content = zstd::stream::decode_all(zstd_content)?;
-            tracing::trace!("After decode {}", String::from_utf8(content)?);
-            target_file.flush()?;
+            let reader = blocking_reader(res.into_inner(), |message| message.body);
+            let target_path = filename.to_owned();
+            tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+                let mut target_file = std::io::BufWriter::new(File::create(target_path)?);
+                let size = std::io::copy(&mut zstd::Decoder::new(reader)?, &mut target_file)?;
+                tracing::trace!("After decode {} bytes", size);
+                target_file.flush()?;
+                Ok(())
+            })
+            .await??;
+        }
+        Commands::Refs { gosh_url } => {
+            let res = grpc_client
+                .list_refs(ListRefsRequest {
+                    gosh_url: gosh_url.to_string(),
+                })
+                .await?;
+            for git_ref in res.into_inner().refs {
+                let kind = match git_ref.kind() {
+                    r#ref::Kind::Branch => "branch",
+                    r#ref::Kind::Tag => "tag",
+                };
+                println!("{}\t{}\t{}", git_ref.commit, kind, git_ref.name);
+            }
+        }
+        Commands::Resolve { gosh_url, rev } => {
+            let res = grpc_client
+                .resolve(ResolveRequest {
+                    gosh_url: gosh_url.to_string(),
+                    rev: rev.to_owned(),
+                })
+                .await?;
+            println!("{}", res.into_inner().commit);
         }
     }
 
@@ -4,7 +4,17 @@ use cyclonedx_bom::models::component::Classification;
 pub enum GoshClassification {
     File,
     Commit,
+    /// part of a commit tree (path prefixes and exclude globs)
+    FilteredCommit,
     Repository,
+    /// commit of a submodule spliced into a parent commit
+    Submodule,
+    /// `sha256:<oid>` of an LFS object which replaced a pointer file
+    LfsObject,
+    /// `sha256:<digest>:<url>` of a file a build downloaded through the proxy
+    HttpDownload,
+    /// `sha256:<checksum>:cargo:<name>@<version>` of a crate from the build registry
+    CargoCrate,
 }
 
 impl GoshClassification {
@@ -12,7 +22,12 @@ impl GoshClassification {
         match self {
             GoshClassification::File => Classification::File,
             GoshClassification::Commit => Classification::Library,
+            GoshClassification::FilteredCommit => Classification::Library,
             GoshClassification::Repository => Classification::Library,
+            GoshClassification::Submodule => Classification::Library,
+            GoshClassification::LfsObject => Classification::File,
+            GoshClassification::HttpDownload => Classification::File,
+            GoshClassification::CargoCrate => Classification::Library,
         }
     }
 }
@@ -1,19 +1,27 @@
 pub mod gosh_classification;
+pub mod record;
+pub mod source_scheme;
 
+use cyclonedx_bom::models::dependency::{Dependencies, Dependency};
+use cyclonedx_bom::models::hash::{Hash, HashAlgorithm, HashValue, Hashes};
 use cyclonedx_bom::models::tool::{Tool, Tools};
-use cyclonedx_bom::prelude::{
-    Bom, Component, Components, Metadata, NormalizedString, Purl, UrnUuid,
-};
+pub use cyclonedx_bom::prelude::Bom;
+use cyclonedx_bom::prelude::{Component, Components, Metadata, NormalizedString, Purl, UrnUuid};
 use gosh_classification::GoshClassification;
+use source_scheme::{SourceScheme, SHA256_PREFIX};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
 
 pub const SBOM_DEFAULT_FILE_NAME: &str = "sbom.spdx.json";
+const CARGO_PREFIX: &str = "cargo:";
 
 #[derive(Debug, Default)]
 pub struct Sbom {
     pub inner: Vec<(GoshClassification, String)>,
+    /// `(parent, child)` raw components, e.g. a commit and its submodule
+    pub dependencies: Vec<(String, String)>,
 }
 
 impl Sbom {
@@ -25,6 +33,20 @@ impl Sbom {
         }
     }
 
+    /// append `raw_component` as a dependency of already appended `parent`
+    pub fn append_dependency(
+        &mut self,
+        parent: &str,
+        component_type: GoshClassification,
+        raw_component: String,
+    ) {
+        let link = (parent.to_owned(), raw_component.clone());
+        if !self.dependencies.contains(&link) {
+            self.dependencies.push(link);
+        }
+        self.append(component_type, raw_component);
+    }
+
     pub fn get_bom(&self) -> anyhow::Result<Bom> {
         // Note: Every BOM generated should have a unique serial number,
         // even if the contents of the BOM being generated have not changed
@@ -38,18 +60,48 @@ impl Sbom {
         for (component_type, component) in &self.inner {
             let name = component;
             let version = "1.0.0";
-            let bom_ref = None;
+            // only linked components need a reference
+            let bom_ref = self
+                .dependencies
+                .iter()
+                .any(|(parent, child)| parent == name || child == name)
+                .then(|| name.clone());
             let mut component =
                 Component::new(component_type.to_component_type(), name, version, bom_ref);
             component.purl = Some(Purl::new(
-                "gosh",
+                SourceScheme::of_component(name).purl_type(),
                 &component.name.to_string(),
                 &component.version.to_string(),
             )?);
+            if let Some(digest) = name
+                .strip_prefix(SHA256_PREFIX)
+                .map(|digest| digest.split_once(':').map_or(digest, |(digest, _)| digest))
+            {
+                component.hashes = Some(Hashes(vec![Hash {
+                    alg: HashAlgorithm::SHA_256,
+                    content: HashValue(digest.to_owned()),
+                }]));
+            }
             components.push(component);
         }
+        let mut dependencies = BTreeMap::<&str, Vec<String>>::new();
+        for (parent, child) in &self.dependencies {
+            dependencies.entry(parent).or_default().push(child.clone());
+        }
+        let dependencies = (!dependencies.is_empty()).then(|| {
+            Dependencies(
+                dependencies
+                    .into_iter()
+                    .map(|(parent, children)| Dependency {
+                        dependency_ref: parent.to_owned(),
+                        dependencies: children,
+                    })
+                    .collect(),
+            )
+        });
         Ok(Bom {
             serial_number: Some(serial_number),
+            dependencies,
             metadata: Some(Metadata {
                 tools: Some(Tools(vec![Tool {
                     name: Some(NormalizedString::new("gosh-docker-build")),
@@ -62,13 +114,18 @@ impl Sbom {
         })
     }
 
-    pub async fn save_to(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
-        // TODO: refactor this: write directly to file, not to a string
+    /// CycloneDX 1.3 JSON of [`Sbom::get_bom`]
+    pub fn to_json(&self) -> anyhow::Result<Vec<u8>> {
         let mut output = Vec::<u8>::new();
-
         let bom = self.get_bom()?;
         bom.output_as_json_v1_3(&mut output)
             .expect("Failed to write BOM");
+        Ok(output)
+    }
+
+    pub async fn save_to(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        // TODO: refactor this: write directly to file, not to a string
+        let output = self.to_json()?;
         let mut sbom_file = File::create(path)?;
         sbom_file.write_all(&output)?;
         Ok(())
@@ -78,3 +135,101 @@ impl Sbom {
 pub fn load_bom(reader: impl std::io::Read) -> anyhow::Result<Bom> {
     Bom::parse_from_json_v1_3(reader).map_err(anyhow::Error::from)
 }
+
+/// raw [`GoshClassification::HttpDownload`] component
+pub fn http_download_component(url: &str, sha256: &str) -> String {
+    format!("{}{}:{}", SHA256_PREFIX, sha256, url)
+}
+
+/// `(url, sha256)` of a raw [`GoshClassification::HttpDownload`] component
+pub fn parse_http_download(raw_component: &str) -> Option<(&str, &str)> {
+    let (sha256, url) = raw_component.strip_prefix(SHA256_PREFIX)?.split_once(':')?;
+    url.contains("://").then_some((url, sha256))
+}
+
+/// sha256 of the downloads pinned in `bom` by their urls
+pub fn http_downloads(bom: &Bom) -> BTreeMap<String, String> {
+    bom.components
+        .iter()
+        .flat_map(|components| components.0.iter())
+        .filter_map(|component| {
+            let name = component.name.to_string();
+            let (url, sha256) = parse_http_download(&name)?;
+            Some((url.to_owned(), sha256.to_owned()))
+        })
+        .collect()
+}
+
+/// raw [`GoshClassification::CargoCrate`] component
+pub fn cargo_crate_component(name: &str, version: &str, sha256: &str) -> String {
+    format!(
+        "{}{}:{}{}@{}",
+        SHA256_PREFIX, sha256, CARGO_PREFIX, name, version
+    )
+}
+
+/// `(name, version, sha256)` of a raw [`GoshClassification::CargoCrate`] component
+pub fn parse_cargo_crate(raw_component: &str) -> Option<(&str, &str, &str)> {
+    let (sha256, name_version) = raw_component.strip_prefix(SHA256_PREFIX)?.split_once(':')?;
+    let (name, version) = name_version.strip_prefix(CARGO_PREFIX)?.split_once('@')?;
+    Some((name, version, sha256))
+}
+
+/// sha256 of the crates pinned in `bom` by their `<name>@<version>`
+pub fn cargo_crates(bom: &Bom) -> BTreeMap<String, String> {
+    bom.components
+        .iter()
+        .flat_map(|components| components.0.iter())
+        .filter_map(|component| {
+            let name = component.name.to_string();
+            let (name, version, sha256) = parse_cargo_crate(&name)?;
+            Some((format!("{}@{}", name, version), sha256.to_owned()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn http_downloads_test() {
+        let url = "https://example.com/v1.0/release.tar.gz";
+        let raw = http_download_component(url, SHA256);
+        assert_eq!(parse_http_download(&raw), Some((url, SHA256)));
+        assert_eq!(parse_http_download(&format!("sha256:{}", SHA256)), None);
+        assert_eq!(parse_http_download("gosh://0:00/dao/repo"), None);
+
+        let mut sbom = Sbom::default();
+        sbom.append(GoshClassification::HttpDownload, raw);
+        sbom.append(GoshClassification::LfsObject, format!("sha256:{}", SHA256));
+        let bom = sbom.get_bom().unwrap();
+        assert_eq!(
+            http_downloads(&bom),
+            BTreeMap::from([(url.to_owned(), SHA256.to_owned())])
+        );
+    }
+
+    #[test]
+    fn cargo_crates_test() {
+        let raw = cargo_crate_component("serde_json", "1.0.97", SHA256);
+        assert_eq!(
+            parse_cargo_crate(&raw),
+            Some(("serde_json", "1.0.97", SHA256))
+        );
+        assert_eq!(parse_http_download(&raw), None);
+        let download = http_download_component("https://example.com/a.tgz", SHA256);
+        assert_eq!(parse_cargo_crate(&download), None);
+
+        let mut sbom = Sbom::default();
+        sbom.append(GoshClassification::CargoCrate, raw);
+        sbom.append(GoshClassification::HttpDownload, download);
+        let bom = sbom.get_bom().unwrap();
+        assert_eq!(
+            cargo_crates(&bom),
+            BTreeMap::from([("serde_json@1.0.97".to_owned(), SHA256.to_owned())])
+        );
+    }
+}
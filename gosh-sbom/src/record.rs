@@ -0,0 +1,119 @@
+use crate::{gosh_classification::GoshClassification, Sbom};
+use bytes::Bytes;
+use git_registry::lfs::LfsObjects;
+use gosh_utils::stream::ByteStream;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+
+/// Raw component served to a client, `(parent, dependency)` components and LFS
+/// objects found while serving it go along
+#[derive(Debug)]
+pub struct Served {
+    pub component: (GoshClassification, String),
+    pub dependencies: Vec<(String, (GoshClassification, String))>,
+    pub lfs_objects: LfsObjects,
+}
+
+/// Forward chunks of `body` to the client as `into_message` makes them and
+/// append `served` to `sbom` only when the whole body was sent
+pub fn forward<T, F>(
+    sbom: Arc<Mutex<Sbom>>,
+    mut body: ByteStream,
+    into_message: F,
+    served: Served,
+) -> ReceiverStream<T>
+where
+    T: Send + 'static,
+    F: Fn(std::io::Result<Bytes>) -> T + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(1);
+    tokio::spawn(async move {
+        while let Some(chunk) = body.next().await {
+            let is_err = chunk.is_err();
+            if tx.send(into_message(chunk)).await.is_err() {
+                tracing::debug!("client is gone: {:?}", served.component);
+                return;
+            }
+            if is_err {
+                return;
+            }
+        }
+        append(&sbom, served).await;
+    });
+    ReceiverStream::new(rx)
+}
+
+/// Append `served` to `sbom` right away, e.g. when the client has it cached
+pub async fn append(sbom: &Mutex<Sbom>, served: Served) {
+    let Served {
+        component: (component_type, raw_component),
+        dependencies,
+        lfs_objects,
+    } = served;
+    let mut sbom = sbom.lock().await;
+    sbom.append(component_type, raw_component.clone());
+    for pointer in lfs_objects.lock().await.iter() {
+        sbom.append_dependency(
+            &raw_component,
+            GoshClassification::LfsObject,
+            pointer.digest(),
+        );
+    }
+    for (parent, (component_type, raw_component)) in dependencies {
+        sbom.append_dependency(&parent, component_type, raw_component);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn served(raw_component: &str) -> Served {
+        Served {
+            component: (GoshClassification::Commit, raw_component.to_owned()),
+            dependencies: vec![(
+                raw_component.to_owned(),
+                (
+                    GoshClassification::Submodule,
+                    format!("{}/sub", raw_component),
+                ),
+            )],
+            lfs_objects: LfsObjects::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn forward_test() {
+        let sbom = Arc::new(Mutex::new(Sbom::default()));
+
+        let body: ByteStream = Box::pin(tokio_stream::iter([
+            Ok(Bytes::from_static(b"a")),
+            Ok(Bytes::from_static(b"b")),
+        ]));
+        let chunks = forward(sbom.clone(), body, |chunk| chunk.unwrap(), served("sent"))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(chunks, [&b"a"[..], &b"b"[..]]);
+
+        // a broken body isn't recorded
+        let body: ByteStream = Box::pin(tokio_stream::iter([
+            Ok(Bytes::from_static(b"a")),
+            Err(std::io::Error::other("broken")),
+        ]));
+        let chunks = forward(sbom.clone(), body, |chunk| chunk.is_ok(), served("broken"))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(chunks, [true, false]);
+
+        let sbom = sbom.lock().await;
+        assert_eq!(sbom.inner.len(), 2, "{:?}", sbom.inner);
+        assert!(sbom
+            .inner
+            .contains(&(GoshClassification::Commit, "sent".to_owned())));
+        assert_eq!(
+            sbom.dependencies,
+            [("sent".to_owned(), "sent/sub".to_owned())]
+        );
+    }
+}
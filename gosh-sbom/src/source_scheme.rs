@@ -0,0 +1,66 @@
+use git_registry::git_url::GitScheme;
+
+pub const SHA256_PREFIX: &str = "sha256:";
+
+/// Where a component was fetched from, detected by the url the component starts with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SourceScheme {
+    Git(GitScheme),
+    /// content addressed object (`sha256:<digest>`), e.g. from an LFS store
+    Digest,
+}
+
+impl SourceScheme {
+    pub fn of_component(raw_component: &str) -> Self {
+        if raw_component.starts_with(SHA256_PREFIX) {
+            SourceScheme::Digest
+        } else {
+            SourceScheme::Git(GitScheme::of_url(raw_component).unwrap_or(GitScheme::Gosh))
+        }
+    }
+
+    pub fn purl_type(&self) -> &'static str {
+        match self {
+            SourceScheme::Git(GitScheme::Gosh) => "gosh",
+            SourceScheme::Git(GitScheme::Https) => "git+https",
+            SourceScheme::Git(GitScheme::Ssh) => "git+ssh",
+            SourceScheme::Git(GitScheme::File) => "git+file",
+            SourceScheme::Digest => "generic",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_scheme_test() {
+        let cases = [
+            (
+                "gosh://0:00/dao/repo:abc",
+                SourceScheme::Git(GitScheme::Gosh),
+            ),
+            (
+                "https://github.com/a/b:abc",
+                SourceScheme::Git(GitScheme::Https),
+            ),
+            (
+                "ssh://git@github.com/a/b:abc:README.md",
+                SourceScheme::Git(GitScheme::Ssh),
+            ),
+            ("file:///srv/repo", SourceScheme::Git(GitScheme::File)),
+            (
+                "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e",
+                SourceScheme::Digest,
+            ),
+            (
+                "origin:gosh://0:00/dao/repo",
+                SourceScheme::Git(GitScheme::Gosh),
+            ),
+        ];
+        for (raw, scheme) in cases {
+            assert_eq!(SourceScheme::of_component(raw), scheme, "{}", raw);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+pub mod metrics;
+pub mod stream;
 pub mod tracing;
 pub mod tracing_pipe;
 pub mod zstd;
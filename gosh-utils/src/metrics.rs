@@ -0,0 +1,223 @@
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+/// upper bounds of [`Metrics::observe`] buckets in seconds, from a cached
+/// file to a clone of a large repo
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+/// Counters and duration histograms with labels, rendered in the Prometheus
+/// text format
+///
+/// series are created on first use, a metric name is either a counter or a
+/// histogram
+#[derive(Debug, Default)]
+pub struct Metrics {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+}
+
+#[derive(Debug)]
+struct Family {
+    help: &'static str,
+    kind: Kind,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Counter(BTreeMap<String, u64>),
+    Histogram(BTreeMap<String, Histogram>),
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    /// add `value` to the counter `name` with `labels`
+    pub fn inc_by(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: u64,
+    ) {
+        let mut families = self.families.lock();
+        let family = families.entry(name).or_insert_with(|| Family {
+            help,
+            kind: Kind::Counter(BTreeMap::new()),
+        });
+        match &mut family.kind {
+            Kind::Counter(series) => *series.entry(label_set(labels)).or_default() += value,
+            Kind::Histogram(_) => tracing::warn!("metric {} is not a counter", name),
+        }
+    }
+
+    pub fn inc(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)]) {
+        self.inc_by(name, help, labels, 1)
+    }
+
+    /// put `duration` into the histogram `name` with `labels`
+    pub fn observe(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        duration: Duration,
+    ) {
+        let seconds = duration.as_secs_f64();
+        let mut families = self.families.lock();
+        let family = families.entry(name).or_insert_with(|| Family {
+            help,
+            kind: Kind::Histogram(BTreeMap::new()),
+        });
+        match &mut family.kind {
+            Kind::Histogram(series) => {
+                let histogram = series.entry(label_set(labels)).or_default();
+                if histogram.buckets.is_empty() {
+                    histogram.buckets = vec![0; DURATION_BUCKETS.len()];
+                }
+                for (bucket, bound) in histogram.buckets.iter_mut().zip(DURATION_BUCKETS) {
+                    if seconds <= *bound {
+                        *bucket += 1;
+                    }
+                }
+                histogram.sum += seconds;
+                histogram.count += 1;
+            }
+            Kind::Counter(_) => tracing::warn!("metric {} is not a histogram", name),
+        }
+    }
+
+    /// Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let families = self.families.lock();
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            match &family.kind {
+                Kind::Counter(series) => {
+                    let _ = writeln!(out, "# HELP {} {}", name, family.help);
+                    let _ = writeln!(out, "# TYPE {} counter", name);
+                    for (labels, value) in series {
+                        let _ = writeln!(out, "{}{} {}", name, braced(labels), value);
+                    }
+                }
+                Kind::Histogram(series) => {
+                    let _ = writeln!(out, "# HELP {} {}", name, family.help);
+                    let _ = writeln!(out, "# TYPE {} histogram", name);
+                    for (labels, histogram) in series {
+                        for (bucket, bound) in histogram.buckets.iter().zip(DURATION_BUCKETS) {
+                            let le = with_label(labels, "le", &bound.to_string());
+                            let _ = writeln!(out, "{}_bucket{} {}", name, braced(&le), bucket);
+                        }
+                        let le = with_label(labels, "le", "+Inf");
+                        let _ = writeln!(out, "{}_bucket{} {}", name, braced(&le), histogram.count);
+                        let _ = writeln!(out, "{}_sum{} {}", name, braced(labels), histogram.sum);
+                        let _ =
+                            writeln!(out, "{}_count{} {}", name, braced(labels), histogram.count);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// `a="1",b="2"`
+fn label_set(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn with_label(labels: &str, name: &str, value: &str) -> String {
+    let label = label_set(&[(name, value)]);
+    if labels.is_empty() {
+        label
+    } else {
+        format!("{},{}", labels, label)
+    }
+}
+
+fn braced(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_test() {
+        let metrics = Metrics::default();
+        metrics.inc("requests_total", "Requests.", &[("status", "200")]);
+        metrics.inc("requests_total", "Requests.", &[("status", "200")]);
+        metrics.inc_by("bytes_total", "Bytes.", &[], 42);
+        metrics.inc("requests_total", "Requests.", &[("path", "a\"b")]);
+        metrics.observe(
+            "duration_seconds",
+            "Durations.",
+            &[("op", "clone")],
+            Duration::from_millis(300),
+        );
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE requests_total counter\n"), "{}", text);
+        assert!(
+            text.contains("requests_total{status=\"200\"} 2\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("requests_total{path=\"a\\\"b\"} 1\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("bytes_total 42\n"), "{}", text);
+        assert!(
+            text.contains("# TYPE duration_seconds histogram\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("duration_seconds_bucket{op=\"clone\",le=\"0.25\"} 0\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("duration_seconds_bucket{op=\"clone\",le=\"0.5\"} 1\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("duration_seconds_bucket{op=\"clone\",le=\"+Inf\"} 1\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("duration_seconds_count{op=\"clone\"} 1\n"),
+            "{}",
+            text
+        );
+
+        // kinds don't mix
+        metrics.observe("bytes_total", "Bytes.", &[], Duration::from_secs(1));
+        assert!(metrics.render().contains("bytes_total 42\n"));
+    }
+}
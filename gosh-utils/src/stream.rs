@@ -0,0 +1,86 @@
+use async_compression::tokio::bufread::{GzipEncoder, ZstdDecoder, ZstdEncoder};
+use bytes::Bytes;
+use std::{
+    io::{BufWriter, Write},
+    pin::Pin,
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// max size of a single chunk (and of a single gRPC message built from it)
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// how many chunks producer can get ahead of consumer
+const CHANNEL_CAPACITY: usize = 16;
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>>;
+
+/// Compress stream with zstd on the fly
+pub fn zstd_encode(stream: ByteStream) -> ByteStream {
+    let encoder = ZstdEncoder::new(StreamReader::new(stream));
+    Box::pin(ReaderStream::with_capacity(encoder, CHUNK_SIZE))
+}
+
+/// Decompress zstd stream on the fly
+pub fn zstd_decode(stream: ByteStream) -> ByteStream {
+    let decoder = ZstdDecoder::new(StreamReader::new(stream));
+    Box::pin(ReaderStream::with_capacity(decoder, CHUNK_SIZE))
+}
+
+/// Compress stream with gzip on the fly, for clients which don't speak zstd
+pub fn gzip_encode(stream: ByteStream) -> ByteStream {
+    let encoder = GzipEncoder::new(StreamReader::new(stream));
+    Box::pin(ReaderStream::with_capacity(encoder, CHUNK_SIZE))
+}
+
+/// Collect the whole stream, only for small bodies (e.g. configs)
+pub async fn read_to_end(mut stream: ByteStream) -> std::io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        body.extend_from_slice(&chunk?);
+    }
+    Ok(body)
+}
+
+/// Run blocking `producer` on the blocking thread pool and stream everything it writes
+///
+/// Error returned by `producer` becomes the last item of the stream
+pub fn from_blocking_writer<F>(producer: F) -> ByteStream
+where
+    F: FnOnce(&mut dyn Write) -> anyhow::Result<()> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+    tokio::task::spawn_blocking(move || {
+        let mut writer = BufWriter::with_capacity(CHUNK_SIZE, ChannelWriter { tx: tx.clone() });
+        let result = producer(&mut writer).and_then(|_| Ok(writer.flush()?));
+        if let Err(error) = result {
+            tracing::debug!("blocking writer failed: {:?}", error);
+            tx.blocking_send(Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("{:#}", error),
+            )))
+            .ok();
+        }
+    });
+    Box::pin(ReceiverStream::new(rx))
+}
+
+struct ChannelWriter {
+    tx: mpsc::Sender<std::io::Result<Bytes>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.tx
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::BrokenPipe, "stream consumer is gone")
+            })?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,17 +1,39 @@
+use crate::config::Config;
 use clap::ArgMatches;
-use git_registry::{git_context::GitContext, registry::GitCacheRegistry};
+use git_registry::{
+    cargo::CargoCache,
+    clone_policy::{ClonePolicies, ClonePolicyRule},
+    download::DownloadStore,
+    git_context::GitContext,
+    git_url::AllowedHosts,
+    lfs,
+    registry::GitCacheRegistry,
+    upstream::UpstreamCache,
+    url_rewrite::{UrlRewrite, UrlRewrites},
+};
 use gosh_builder::{
-    docker_builder::{GoshBuilder, ImageBuilder},
-    git_server,
+    docker_builder::{BuildNetwork, GoshBuilder, ImageBuilder},
+    git_server::{self, CrateRegistry, DownloadMode, Downloads, Egress, EgressLog, EgressPolicy},
 };
 use gosh_builder_config::GoshConfig;
-use gosh_sbom::{load_bom, Sbom, SBOM_DEFAULT_FILE_NAME};
+use gosh_sbom::{cargo_crates, http_downloads, load_bom, Bom, Sbom, SBOM_DEFAULT_FILE_NAME};
 use std::{fs::File, net::SocketAddr, path::PathBuf, sync::Arc};
 use tokio::sync::Mutex;
 
 pub const COMMAND: &str = "build";
 pub const DEFAULT_CONFIG_PATH: &str = "Gosh.yaml";
 pub const DEFAULT_SOCKET_ADDR: &str = "127.0.0.1:6054";
+pub const BUILD_REPORT_DEFAULT_FILE_NAME: &str = "build-report.json";
+
+/// what a build did besides its image, written even if the build fails
+#[derive(Debug, serde::Serialize)]
+pub struct BuildReport {
+    pub success: bool,
+    /// every attempt to reach a host other than gosh, denied ones included
+    pub egress: Vec<Egress>,
+    /// downloads and crates which don't match the SBOM, e.g. changed upstream
+    pub download_failures: Vec<String>,
+}
 
 #[derive(Debug, Clone)]
 pub struct BuildSettings {
@@ -21,6 +43,7 @@ pub struct BuildSettings {
     pub quiet: bool,
     pub git_context: Option<GitContext>,
     pub sbom_proxy_socket: SocketAddr,
+    pub offline: bool,
 }
 
 pub fn command() -> clap::Command {
@@ -44,11 +67,17 @@ pub fn command() -> clap::Command {
                 .action(clap::ArgAction::Count)
                 .help("Validate the result image"),
         )
+        .arg(
+            clap::Arg::new("offline")
+                .long("offline")
+                .action(clap::ArgAction::Count)
+                .help("Use only git repos already in the cache (see `gosh cache import`)"),
+        )
         .arg(
             clap::Arg::new("socket")
                 .short('s')
                 .long("socket")
-                .help("Socket address for the SBOM proxy server")
+                .help("SBOM proxy server port (IP:PORT), it listens on the gateway of the build network")
                 .value_name("IP:PORT")
                 .default_value(DEFAULT_SOCKET_ADDR),
         )
@@ -60,11 +89,7 @@ pub fn command() -> clap::Command {
                 .help("Config path (in case of GOSH url context it should be relative to the root)")
                 .default_value(DEFAULT_CONFIG_PATH),
         )
-        .arg(
-            clap::Arg::new("url")
-                .value_name("gosh://0:...")
-                .required(false),
-        )
+        .arg(clap::Arg::new("url").value_name("URL").required(false))
 }
 
 pub fn build_settings(matches: &ArgMatches) -> anyhow::Result<BuildSettings> {
@@ -112,6 +137,7 @@ pub fn build_settings(matches: &ArgMatches) -> anyhow::Result<BuildSettings> {
 
     let validate = matches.get_count("validate") > 0;
     let quiet = matches.get_count("quiet") > 0;
+    let offline = matches.get_count("offline") > 0;
 
     let settings = BuildSettings {
         config_path: gosh_configfile,
@@ -120,6 +146,7 @@ pub fn build_settings(matches: &ArgMatches) -> anyhow::Result<BuildSettings> {
         quiet,
         git_context,
         sbom_proxy_socket,
+        offline,
     };
 
     tracing::debug!("{:?}", settings);
@@ -127,41 +154,129 @@ pub fn build_settings(matches: &ArgMatches) -> anyhow::Result<BuildSettings> {
     Ok(settings)
 }
 
+/// git cache with allowed hosts, mirrors, upstream cache, clone policies and LFS store
+/// from the gosh config, `offline` one never fetches remotes
+pub fn git_cache_registry(offline: bool) -> anyhow::Result<GitCacheRegistry> {
+    let config = Config::load_or_default()?;
+    let url_rewrites = config.git_url_rewrites().iter().map(|rule| UrlRewrite {
+        base: rule.base.clone(),
+        instead_of: rule.instead_of.clone(),
+    });
+    let clone_policies = config
+        .git_clone_policies()
+        .iter()
+        .map(|rule| ClonePolicyRule::new(&rule.repos, rule.policy.parse()?))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut registry = GitCacheRegistry::default()
+        .with_allowed_hosts(AllowedHosts::new(config.git_allowed_hosts()))
+        .with_url_rewrites(UrlRewrites::new(url_rewrites))
+        .with_clone_policies(ClonePolicies::new(clone_policies))
+        .with_offline(offline);
+    if let Some(lfs_store) = config.git_lfs_store() {
+        registry = registry.with_lfs_store(lfs::store_from_url(lfs_store)?);
+    }
+    if let Some(upstream) = config.git_upstream_cache() {
+        registry = registry.with_upstream(
+            UpstreamCache::new(upstream)?
+                .with_upload_token(config.git_upstream_upload_token().cloned()),
+        );
+    }
+    Ok(registry)
+}
+
+/// hosts builds may reach through the build proxy, from the gosh config
+pub fn egress_policy() -> anyhow::Result<EgressPolicy> {
+    let config = Config::load_or_default()?;
+    EgressPolicy::new(config.build_allowed_hosts(), config.build_denied_hosts())
+}
+
+fn write_build_report(report: &BuildReport) -> anyhow::Result<()> {
+    let report_path =
+        std::env::var("BUILD_REPORT_OUT").unwrap_or(BUILD_REPORT_DEFAULT_FILE_NAME.to_owned());
+    tracing::info!("Writing build report to {}", report_path);
+    serde_json::to_writer_pretty(File::create(report_path)?, report)?;
+    Ok(())
+}
+
 pub async fn build_image(
     gosh_config: GoshConfig,
     quiet: bool,
     sbom_proxy_socket: SocketAddr,
     sbom: Arc<Mutex<Sbom>>,
     git_registry: Arc<GitCacheRegistry>,
+    egress: EgressPolicy,
+    old_bom: Option<&Bom>,
 ) -> anyhow::Result<String> {
-    // TODO: merge GRPC and Git servers
-    // let stop_grpc_server = grpc_server::run(sbom_proxy_socket, sbom.clone(), git_registry).await?;
-    let stop_git_server = git_server::run(sbom_proxy_socket, sbom.clone(), git_registry)?;
+    let egress_log = Arc::new(EgressLog::default());
+    // a reproduced SBOM pins what the build may download, the first build records it
+    let (download_mode, crate_mode) = match old_bom {
+        Some(old_bom) => (
+            DownloadMode::Locked(http_downloads(old_bom)),
+            DownloadMode::Locked(cargo_crates(old_bom)),
+        ),
+        None => (DownloadMode::Record, DownloadMode::Record),
+    };
+    let downloads = Arc::new(Downloads::new(
+        download_mode,
+        DownloadStore::new(DownloadStore::default_dir())?,
+    ));
+    let crates = Arc::new(CrateRegistry::new(
+        crate_mode,
+        CargoCache::new(CargoCache::default_cargo_home()),
+    ));
+    // the build reaches only the build server, on the gateway of its network
+    let network = BuildNetwork::create().await?;
+    let proxy_socket = SocketAddr::new(network.gateway, sbom_proxy_socket.port());
+    let build_result = async {
+        // TODO: merge GRPC and Git servers
+        // let stop_grpc_server = grpc_server::run(sbom_proxy_socket, sbom.clone(), git_registry).await?;
+        let stop_git_server = git_server::run(
+            proxy_socket,
+            sbom.clone(),
+            git_registry,
+            egress,
+            egress_log.clone(),
+            downloads.clone(),
+            crates.clone(),
+        )?;
+        if let Err(error) = git_server::wait_ready(proxy_socket).await {
+            stop_git_server();
+            return Err(error);
+        }
 
-    let build_result = tokio::spawn(async move {
         tracing::info!("Start build...");
-
         let gosh_builder = GoshBuilder {
             config: gosh_config,
         };
-
-        let inner_build_result = gosh_builder.run(quiet, sbom_proxy_socket).await;
-
+        let build_result = gosh_builder.run(quiet, &network, proxy_socket).await;
         tracing::info!("End build...");
-        inner_build_result
-    })
-    .await
-    .expect("gosh builder subprocess join")?;
 
-    tracing::info!("Stoping build server...");
-    // stop_grpc_server();
-    stop_git_server();
+        tracing::info!("Stoping build server...");
+        // stop_grpc_server();
+        stop_git_server();
+        build_result
+    }
+    .await;
+    if let Err(error) = network.remove().await {
+        tracing::warn!("{:?}", error);
+    }
+    let build_result = build_result?;
 
-    if build_result.status.success() {
-        tracing::info!("Build successful");
-    } else {
+    let mut download_failures = downloads.failures();
+    download_failures.extend(crates.failures());
+    write_build_report(&BuildReport {
+        success: build_result.status.success() && download_failures.is_empty(),
+        egress: egress_log.entries(),
+        download_failures: download_failures.clone(),
+    })?;
+
+    if !build_result.status.success() {
         let exit_code = build_result.status.code().unwrap_or(1);
         anyhow::bail!("Docker build failed with exit code: {}", exit_code);
+    } else if !download_failures.is_empty() {
+        anyhow::bail!("Build isn't reproducible: {}", download_failures.join("; "));
+    } else {
+        tracing::info!("Build successful");
     };
 
     Ok(build_result.image_hash.unwrap_or("".to_owned()))
@@ -170,7 +285,7 @@ pub async fn build_image(
 pub async fn run(matches: &ArgMatches) -> anyhow::Result<()> {
     let build_settings = build_settings(matches)?;
 
-    let git_cache_registry = Arc::new(GitCacheRegistry::default());
+    let git_cache_registry = Arc::new(git_cache_registry(build_settings.offline)?);
 
     let gosh_config = if let Some(ref git_context) = build_settings.git_context {
         GoshConfig::from_git_context(
@@ -187,40 +302,40 @@ pub async fn run(matches: &ArgMatches) -> anyhow::Result<()> {
 
     let sbom = Arc::new(Mutex::new(Sbom::default()));
 
+    // the SBOM to reproduce, it pins downloads and crates
+    let old_bom = if let Some(ref git_context) = &build_settings.git_context {
+        let file_path = PathBuf::from(git_context.sub_dir.as_str()).join(SBOM_DEFAULT_FILE_NAME);
+        Some(load_bom(
+            git_cache_registry
+                .git_show_uncompressed(
+                    &git_context.remote,
+                    git_context.git_ref.as_str(),
+                    file_path.to_string_lossy(),
+                )
+                .await?
+                .as_slice(),
+        )?)
+    } else if build_settings.validate {
+        Some(load_bom(File::open(SBOM_DEFAULT_FILE_NAME)?)?)
+    } else {
+        None
+    };
+
     let image_id = build_image(
         gosh_config,
         build_settings.quiet,
         build_settings.sbom_proxy_socket,
         sbom.clone(),
         git_cache_registry.clone(),
+        egress_policy()?,
+        old_bom.as_ref(),
     )
     .await?;
 
     // SBOM
 
-    if let Some(ref git_context) = &build_settings.git_context {
-        tracing::info!("Validate SBOM...");
-        let file_path = PathBuf::from(git_context.sub_dir.as_str()).join(SBOM_DEFAULT_FILE_NAME);
-        let old_bom = load_bom(
-            git_cache_registry
-                .git_show_uncompressed(
-                    git_context.remote.as_str(),
-                    git_context.git_ref.as_str(),
-                    file_path.to_string_lossy(),
-                )
-                .await?
-                .as_slice(),
-        )?;
-
-        let bom = sbom.lock().await.get_bom()?;
-        if bom != old_bom {
-            anyhow::bail!("SBOM validation fail");
-        } else {
-            tracing::info!("SBOM validation success");
-        }
-    } else if build_settings.validate {
+    if let Some(old_bom) = old_bom {
         tracing::info!("Validate SBOM...");
-        let old_bom = load_bom(File::open(SBOM_DEFAULT_FILE_NAME)?)?;
         let bom = sbom.lock().await.get_bom()?;
         if bom != old_bom {
             anyhow::bail!("SBOM validation fail");
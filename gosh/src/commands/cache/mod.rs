@@ -0,0 +1,92 @@
+use super::build::git_cache_registry;
+use clap::ArgMatches;
+use git_registry::bundle;
+use gosh_sbom::{load_bom, SBOM_DEFAULT_FILE_NAME};
+use std::{fs::File, path::PathBuf};
+
+pub const COMMAND: &str = "cache";
+pub const EXPORT_COMMAND: &str = "export";
+pub const IMPORT_COMMAND: &str = "import";
+
+pub fn command() -> clap::Command {
+    clap::Command::new(COMMAND)
+        .about("Move the git cache to a machine without network access")
+        .subcommand(
+            clap::Command::new(EXPORT_COMMAND)
+                .about("Pack git sources referenced by the SBOM into an archive")
+                .arg(
+                    clap::Arg::new("sbom")
+                        .long("sbom")
+                        .value_name("PATH")
+                        .help("SBOM of the build which sources are packed")
+                        .default_value(SBOM_DEFAULT_FILE_NAME),
+                )
+                .arg(clap::Arg::new("output").value_name("PATH").required(true)),
+        )
+        .subcommand(
+            clap::Command::new(IMPORT_COMMAND)
+                .about(
+                    "Verify an archive made by `gosh cache export` and put it into the git cache",
+                )
+                .arg(clap::Arg::new("archive").value_name("PATH").required(true)),
+        )
+        .subcommand_required(true)
+}
+
+pub async fn run(matches: &ArgMatches) -> anyhow::Result<()> {
+    match matches.subcommand() {
+        Some((EXPORT_COMMAND, args)) => export(args).await,
+        Some((IMPORT_COMMAND, args)) => import(args).await,
+        _ => anyhow::bail!("Wrong subcommand"),
+    }
+}
+
+async fn export(matches: &ArgMatches) -> anyhow::Result<()> {
+    let sbom_path = matches
+        .get_one::<String>("sbom")
+        .expect("should never fail due to `.default_value`");
+    let output = PathBuf::from(
+        matches
+            .get_one::<String>("output")
+            .expect("should never fail due to `.required`"),
+    );
+
+    let bom = load_bom(File::open(sbom_path)?)?;
+    let names = bom
+        .components
+        .iter()
+        .flat_map(|components| components.0.iter())
+        .map(|component| component.name.to_string())
+        .collect::<Vec<_>>();
+    let sources = bundle::sources(names.iter().map(String::as_str));
+    if sources.is_empty() {
+        anyhow::bail!("{} doesn't reference any git sources", sbom_path);
+    }
+
+    let registry = git_cache_registry(false)?;
+    let manifest = bundle::export(&registry, &sources, &output).await?;
+    for repo in &manifest.repos {
+        if repo.commits.is_empty() {
+            tracing::info!("Exported {} (all refs)", repo.url);
+        } else {
+            tracing::info!("Exported {} ({} commits)", repo.url, repo.commits.len());
+        }
+    }
+    tracing::info!("Cache archive: {:?}", output);
+    Ok(())
+}
+
+async fn import(matches: &ArgMatches) -> anyhow::Result<()> {
+    let archive = PathBuf::from(
+        matches
+            .get_one::<String>("archive")
+            .expect("should never fail due to `.required`"),
+    );
+
+    let registry = git_cache_registry(true)?;
+    let manifest = bundle::import(&registry, &archive).await?;
+    for repo in &manifest.repos {
+        tracing::info!("Imported {}", repo.url);
+    }
+    Ok(())
+}
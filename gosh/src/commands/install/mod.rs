@@ -1,6 +1,6 @@
-use crate::commands::build::build_image;
+use crate::commands::build::{build_image, egress_policy, git_cache_registry};
 use clap::ArgMatches;
-use git_registry::{git_context::GitContext, registry::GitCacheRegistry};
+use git_registry::git_context::GitContext;
 use gosh_builder_config::GoshConfig;
 use gosh_sbom::{load_bom, Sbom, SBOM_DEFAULT_FILE_NAME};
 use std::{net::SocketAddr, path::PathBuf, process::Stdio, sync::Arc};
@@ -16,6 +16,7 @@ pub struct InstallSettings {
     pub workdir: PathBuf,
     pub git_context: Option<GitContext>,
     pub sbom_proxy_socket: SocketAddr,
+    pub offline: bool,
 }
 
 pub fn command() -> clap::Command {
@@ -24,7 +25,7 @@ pub fn command() -> clap::Command {
             clap::Arg::new("socket")
                 .short('s')
                 .long("socket")
-                .help("Socket address for the SBOM proxy server")
+                .help("SBOM proxy server port (IP:PORT), it listens on the gateway of the build network")
                 .value_name("IP:PORT")
                 .default_value(DEFAULT_SOCKET_ADDR),
         )
@@ -36,7 +37,13 @@ pub fn command() -> clap::Command {
                 .help("Config path (in case of GOSH url context it should be relative to the root)")
                 .default_value(DEFAULT_CONFIG_PATH),
         )
-        .arg(clap::Arg::new("gosh_url").value_name("gosh://0:..."))
+        .arg(
+            clap::Arg::new("offline")
+                .long("offline")
+                .action(clap::ArgAction::SetTrue)
+                .help("Use only git repos already in the cache (see `gosh cache import`)"),
+        )
+        .arg(clap::Arg::new("gosh_url").value_name("URL"))
         .about("Install GOSH repo")
 }
 
@@ -83,11 +90,14 @@ pub fn install_settings(matches: &ArgMatches) -> anyhow::Result<InstallSettings>
         .expect("should never fail due to `.default_value`")
         .parse()?;
 
+    let offline = matches.get_flag("offline");
+
     let settings = InstallSettings {
         config_path: gosh_configfile,
         workdir,
         git_context,
         sbom_proxy_socket,
+        offline,
     };
 
     tracing::debug!("{:?}", settings);
@@ -98,7 +108,7 @@ pub fn install_settings(matches: &ArgMatches) -> anyhow::Result<InstallSettings>
 pub async fn run(matches: &ArgMatches) -> anyhow::Result<()> {
     let build_settings = install_settings(matches)?;
 
-    let git_cache_registry = Arc::new(GitCacheRegistry::default());
+    let git_cache_registry = Arc::new(git_cache_registry(build_settings.offline)?);
 
     let Some(ref git_context) = build_settings.git_context else {
         anyhow::bail!("url is required")
@@ -119,12 +129,27 @@ pub async fn run(matches: &ArgMatches) -> anyhow::Result<()> {
 
     let sbom = Arc::new(Mutex::new(Sbom::default()));
 
+    // the SBOM to reproduce, it pins downloads and crates
+    let file_path = PathBuf::from(git_context.sub_dir.as_str()).join(SBOM_DEFAULT_FILE_NAME);
+    let old_bom = load_bom(
+        git_cache_registry
+            .git_show_uncompressed(
+                &git_context.remote,
+                git_context.git_ref.as_str(),
+                file_path.to_string_lossy(),
+            )
+            .await?
+            .as_slice(),
+    )?;
+
     let image_id = build_image(
         gosh_config,
         true,
         build_settings.sbom_proxy_socket,
         sbom.clone(),
         git_cache_registry.clone(),
+        egress_policy()?,
+        Some(&old_bom),
     )
     .await?
     .trim()
@@ -136,18 +161,6 @@ pub async fn run(matches: &ArgMatches) -> anyhow::Result<()> {
 
     // SBOM
     tracing::info!("Validate SBOM...");
-    let file_path = PathBuf::from(git_context.sub_dir.as_str()).join(SBOM_DEFAULT_FILE_NAME);
-    let old_bom = load_bom(
-        git_cache_registry
-            .git_show_uncompressed(
-                git_context.remote.as_str(),
-                git_context.git_ref.as_str(),
-                file_path.to_string_lossy(),
-            )
-            .await?
-            .as_slice(),
-    )?;
-
     let bom = sbom.lock().await.get_bom()?;
     if bom != old_bom {
         anyhow::bail!("SBOM validation fail");
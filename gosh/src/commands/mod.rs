@@ -1,4 +1,5 @@
 pub mod anytree;
 pub mod build;
+pub mod cache;
 pub mod init;
 pub mod install;
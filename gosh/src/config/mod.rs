@@ -4,9 +4,10 @@ use crate::blockchain::ever_client::create_client;
 use crate::crypto::generate_keypair_from_secret;
 use crate::profile::check_profile_pubkey;
 use colored::Colorize;
+use git_registry::upstream::UploadToken;
 use std::collections::HashMap;
 use std::io::{BufReader, Read, Write};
-use std::path::Path;
+use std::path::PathBuf;
 use std::{env, fmt};
 
 #[derive(Clone, serde::Deserialize, serde::Serialize)]
@@ -24,6 +25,21 @@ pub struct NetworkConfig {
     endpoints: Vec<String>,
 }
 
+/// `git config url.<base>.insteadOf <instead-of>` alike mirror
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct UrlRewriteConfig {
+    pub base: String,
+    #[serde(rename = "instead-of")]
+    pub instead_of: String,
+}
+
+/// how much of the repos matching `repos` glob is cloned: `full`, `shallow` or `partial`
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct ClonePolicyConfig {
+    pub repos: String,
+    pub policy: String,
+}
+
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 #[serde(default)]
 pub struct Config {
@@ -35,6 +51,43 @@ pub struct Config {
 
     #[serde(rename = "networks")]
     networks: HashMap<String, NetworkConfig>,
+
+    /// non-gosh git hosts which are permitted as build sources
+    #[serde(rename = "git-allowed-hosts")]
+    git_allowed_hosts: Vec<String>,
+
+    /// mirrors for git remotes, tried in order before the original url
+    #[serde(rename = "git-url-rewrites")]
+    git_url_rewrites: Vec<UrlRewriteConfig>,
+
+    /// where git LFS objects are fetched from, pointer files are served as is
+    /// if not set
+    #[serde(rename = "git-lfs-store", skip_serializing_if = "Option::is_none")]
+    git_lfs_store: Option<String>,
+
+    /// team `gosh-git-server` which is tried before git remotes
+    #[serde(rename = "git-upstream-cache", skip_serializing_if = "Option::is_none")]
+    git_upstream_cache: Option<String>,
+
+    /// send pinned commits of repos fetched from remotes back to
+    /// `git-upstream-cache`, which takes them with this token
+    #[serde(
+        rename = "git-upstream-upload-token",
+        skip_serializing_if = "Option::is_none"
+    )]
+    git_upstream_upload_token: Option<UploadToken>,
+
+    /// shallow or blob-less clones of large repos, the first matching rule wins
+    #[serde(rename = "git-clone-policies")]
+    git_clone_policies: Vec<ClonePolicyConfig>,
+
+    /// hosts builds may reach through the build proxy, any if empty
+    #[serde(rename = "build-allowed-hosts")]
+    build_allowed_hosts: Vec<String>,
+
+    /// hosts builds may never reach, they win over `build-allowed-hosts`
+    #[serde(rename = "build-denied-hosts")]
+    build_denied_hosts: Vec<String>,
 }
 
 impl fmt::Debug for UserWalletConfig {
@@ -60,6 +113,14 @@ impl Default for Config {
                 })
                 .collect(),
             primary_network: defaults::PRIMARY_NETWORK.to_string(),
+            git_allowed_hosts: Vec::new(),
+            git_url_rewrites: Vec::new(),
+            git_lfs_store: None,
+            git_upstream_cache: None,
+            git_upstream_upload_token: None,
+            git_clone_policies: Vec::new(),
+            build_allowed_hosts: Vec::new(),
+            build_denied_hosts: Vec::new(),
         }
     }
 }
@@ -70,11 +131,14 @@ impl Config {
         Ok(config)
     }
 
-    pub fn load() -> anyhow::Result<Self> {
+    fn path() -> PathBuf {
         let config_path =
             env::var("GOSH_CONFIG_PATH").unwrap_or_else(|_| defaults::CONFIG_LOCATION.to_string());
-        let config_path = shellexpand::tilde(&config_path).into_owned();
-        let config_path = Path::new(&config_path);
+        PathBuf::from(shellexpand::tilde(&config_path).into_owned())
+    }
+
+    pub fn load() -> anyhow::Result<Self> {
+        let config_path = Self::path();
         if !config_path.exists() {
             anyhow::bail!("No gosh config was found");
         }
@@ -84,11 +148,17 @@ impl Config {
         Self::read(config_reader)
     }
 
+    /// same as [`Config::load`] but missing config is not an error
+    pub fn load_or_default() -> anyhow::Result<Self> {
+        if Self::path().exists() {
+            Self::load()
+        } else {
+            Ok(Self::default())
+        }
+    }
+
     pub fn save(&self) -> anyhow::Result<()> {
-        let config_path =
-            env::var("GOSH_CONFIG_PATH").unwrap_or_else(|_| defaults::CONFIG_LOCATION.to_string());
-        let config_path = shellexpand::tilde(&config_path).into_owned();
-        let config_path = Path::new(&config_path);
+        let config_path = Self::path();
         if let Some(dir_path) = config_path.parent() {
             if !dir_path.exists() {
                 std::fs::create_dir_all(dir_path)?;
@@ -117,6 +187,14 @@ impl Config {
                 })
                 .collect(),
             primary_network: defaults::PRIMARY_NETWORK.to_string(),
+            git_allowed_hosts: Vec::new(),
+            git_url_rewrites: Vec::new(),
+            git_lfs_store: None,
+            git_upstream_cache: None,
+            git_upstream_upload_token: None,
+            git_clone_policies: Vec::new(),
+            build_allowed_hosts: Vec::new(),
+            build_denied_hosts: Vec::new(),
         }
     }
 
@@ -163,6 +241,38 @@ impl Config {
             .clone()
     }
 
+    pub fn git_allowed_hosts(&self) -> &[String] {
+        &self.git_allowed_hosts
+    }
+
+    pub fn git_url_rewrites(&self) -> &[UrlRewriteConfig] {
+        &self.git_url_rewrites
+    }
+
+    pub fn git_lfs_store(&self) -> Option<&str> {
+        self.git_lfs_store.as_deref()
+    }
+
+    pub fn git_upstream_cache(&self) -> Option<&str> {
+        self.git_upstream_cache.as_deref()
+    }
+
+    pub fn git_upstream_upload_token(&self) -> Option<&UploadToken> {
+        self.git_upstream_upload_token.as_ref()
+    }
+
+    pub fn git_clone_policies(&self) -> &[ClonePolicyConfig] {
+        &self.git_clone_policies
+    }
+
+    pub fn build_allowed_hosts(&self) -> &[String] {
+        &self.build_allowed_hosts
+    }
+
+    pub fn build_denied_hosts(&self) -> &[String] {
+        &self.build_denied_hosts
+    }
+
     pub fn get_user_data(&self) -> UserWalletConfig {
         self.networks
             .get(&self.primary_network)
@@ -30,6 +30,7 @@ In case of any issues, or to receive assistance when working with GOSH cli pleas
         )
         .subcommand(commands::anytree::command())
         .subcommand(commands::build::command())
+        .subcommand(commands::cache::command())
         .subcommand(commands::install::command())
         .subcommand_required(true)
         .get_matches();
@@ -38,6 +39,7 @@ In case of any issues, or to receive assistance when working with GOSH cli pleas
         Some(("init", _)) => commands::init::init_command().await?,
         Some((commands::anytree::COMMAND, args)) => commands::anytree::run(args).await?,
         Some((commands::build::COMMAND, args)) => commands::build::run(args).await?,
+        Some((commands::cache::COMMAND, args)) => commands::cache::run(args).await?,
         Some((commands::install::COMMAND, args)) => commands::install::run(args).await?,
         _ => anyhow::bail!("Wrong subcommand"),
     };